@@ -3,17 +3,16 @@ use native_windows_derive as nwd;
 
 use nwd::NwgUi;
 use nwg::{CheckBoxState, InsertListViewColumn, ListViewColumnFlags, NativeUi, WindowFlags};
-use passtool::{generator, PassTable, Password, PasswordMeta};
+use passtool::{clipboard::ClipboardService, fingerprint::Verification, generator, store, PassTable, Password, PasswordMeta};
 
 use std::{cell::RefCell, ffi::OsStr, path::Path, thread, time::Duration};
 use winapi::{shared::{minwindef::{HMODULE, MAX_PATH}, ntdef::{LPCWSTR, WCHAR}, windef::POINT}, um::{uxtheme::SetWindowTheme, winnt::{PROCESS_QUERY_INFORMATION, PROCESS_VM_READ}, winuser::{GetAsyncKeyState, VK_CONTROL, VK_MENU}}};
 //const flaggg: WindowFlags = WindowFlags::POPUP;
 
-pub const SAVEFILE: &str = "passwords.pt";
-
 #[derive(Default, NwgUi)]
 pub struct PassToolApp {
     passtable: RefCell<PassTable>,
+    clipboard: ClipboardService,
 
     #[nwg_resource]
     embed: nwg::EmbedResource,
@@ -48,6 +47,7 @@ pub struct PassToolApp {
     rec_pass_names: RefCell<Vec<String>>,
 
     active_process: RefCell<String>, // process name below the popup window
+    active_title: RefCell<String>, // title of the window below the popup window
     
     #[nwg_control(text: "All passwords:", flags: "VISIBLE")]
     #[nwg_layout_item(layout: layout, row: 5, col_span: 3)]
@@ -205,7 +205,10 @@ impl PassToolApp {
                 
         let len: u16 = self.password_len.text().parse().unwrap();
         let password = generator::generate_password(len, letters, digits, special);
-        nwg::Clipboard::set_data_text(self.generate_password_window.handle, &password);
+        if let Err(e) = self.clipboard.copy(&password) {
+            nwg::modal_error_message(self.generate_password_window.handle, "Clipboard error!", &format!("{e}"));
+            return;
+        }
         self.generate_password_window.set_visible(false);
         nwg::modal_info_message(self.popup_window.handle, "Success!","Password saved into clipboard!");
     }
@@ -239,13 +242,12 @@ impl PassToolApp {
 
         let name = name.as_ref().unwrap();
         let app = &*self.active_process.borrow();
-        {
-            let mut pt = self.passtable.borrow_mut();
-            let apps = &mut pt.get_metadata_mut(name).unwrap().apps;
+        let _ = self.modify(|pt| {
+            let apps = &mut pt.get_metadata_mut(name)?.apps;
             if !apps.contains(app) { apps.push(app.clone()); }
-        }
+            Ok(())
+        });
 
-        let _ = self.save();
         self.update_app_list();
         self.update_rec_passwords();
     }
@@ -258,13 +260,11 @@ impl PassToolApp {
 
         let ind = ind.unwrap(); //chosen index
         let name = name.as_ref().unwrap(); //chosen password
-        {
-            let mut pt = self.passtable.borrow_mut();
-            let apps = &mut pt.get_metadata_mut(name).unwrap().apps;
-            apps.remove(ind);
-        }
+        let _ = self.modify(|pt| {
+            pt.get_metadata_mut(name)?.remove_app(ind);
+            Ok(())
+        });
 
-        let _ = self.save();
         self.update_app_list();
         self.update_rec_passwords();
     }
@@ -276,16 +276,15 @@ impl PassToolApp {
         let name = name.as_ref().unwrap();
         let confirm_password_delete = nwg::MessageParams {
             title: "Delete password",
-            content: &format!("Are you sure you want to delete the password \'{name}\'?"),
+            content: &format!("Are you sure you want to delete the password \'{name}\'? It is kept in the trash for a while."),
             buttons: nwg::MessageButtons::YesNo,
             icons: nwg::MessageIcons::Warning
         };
         if let nwg::MessageChoice::Yes = nwg::modal_message(self.popup_window.handle, &confirm_password_delete)
         {
-            let _ = self.passtable.borrow_mut().remove_password(name);
+            let _ = self.modify(|pt| Ok(pt.remove_password(name)?));
         }
 
-        let _ = self.save();
         self.update_lists();
     }
 
@@ -301,14 +300,18 @@ impl PassToolApp {
             return;
         }
         self.key_input.set_text("");
-        match self.passtable.borrow().get_password(name, &key) {
+        let password = self.modify(|pt| Ok(pt.access_password(name, &key)?));
+        match password {
             Ok(password) => {
-                nwg::Clipboard::set_data_text(self.popup_window.handle, &password);
+                if let Err(e) = self.clipboard.copy(&password) {
+                    nwg::modal_error_message(self.popup_window.handle, "Clipboard error!", &format!("{e}"));
+                    return;
+                }
                 //dbg!(&password);
                 nwg::modal_info_message(self.popup_window.handle, "Success!","Password saved into clipboard!");
                 self.disable_input();
             }
-            Err(passtool::IncorrectPass) => {
+            Err(e) if matches!(e.downcast_ref(), Some(passtool::IncorrectPass)) => {
                 nwg::modal_error_message(self.popup_window.handle, "Warning!", "Incorrect password!");
                 self.key_input.set_focus();
             },
@@ -354,8 +357,9 @@ impl PassToolApp {
             icons: nwg::MessageIcons::Warning
         };
         
+        let replace;
         { // additional scope for pt
-            let mut pt = self.passtable.borrow_mut();
+            let pt = self.passtable.borrow();
             if name.len() == 0 {
                 nwg::modal_error_message(self.add_password_window.handle, "Warning!", "Empty name is not allowed!");
                 return;
@@ -373,8 +377,7 @@ impl PassToolApp {
     
                     if let nwg::MessageChoice::Yes = nwg::modal_message(self.popup_window.handle, &confirm_password_edit)
                     {
-                        let old_apps = pt.get_metadata(&name).unwrap().apps.clone();
-                        let _ = pt.update_metadata(&name, PasswordMeta::new(description, old_apps));
+                        replace = true;
                     }
                     else {return}
                 }
@@ -390,15 +393,31 @@ impl PassToolApp {
                 }
                 if pt.contains(&name){
                     if let nwg::MessageChoice::Yes = nwg::modal_message(self.popup_window.handle, &confirm_password_edit) {
-                        let _ = pt.remove_password(&name);
+                        replace = true;
                     }
                     else {return}
                 }
-                let _ = pt.add_password(&name, &password, PasswordMeta::new(description, Default::default()), &key);
+                else {
+                    replace = false;
+                }
             }
         }
 
-        let _ = self.save();
+        let result = self.modify(|pt| {
+            if replace {
+                // the old password stays in the entry's history
+                if password.len() != 0 { pt.update_password(&name, &password, &key)?; }
+                pt.get_metadata_mut(&name)?.description = description;
+            }
+            else {
+                pt.add_password(&name, &password, PasswordMeta::new(description, Default::default()), &key)?;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            nwg::modal_error_message(self.add_password_window.handle, "Unknown error!", &format!("{e}"));
+            return;
+        }
         self.clear_add_password();
         self.add_password_window.set_visible(false);
         self.update_lists();
@@ -426,7 +445,7 @@ impl PassToolApp {
     }
 
     fn toggle_overlay(&self) {
-        use winapi::um::{psapi::GetModuleFileNameExW, processthreadsapi::OpenProcess, winuser::{ShowWindow, IsWindowVisible, SW_HIDE, SW_SHOW, WindowFromPoint, GetWindowThreadProcessId}};
+        use winapi::um::{psapi::GetModuleFileNameExW, processthreadsapi::OpenProcess, winuser::{ShowWindow, IsWindowVisible, SW_HIDE, SW_SHOW, WindowFromPoint, GetWindowThreadProcessId, GetAncestor, GetWindowTextW, GA_ROOT}};
         unsafe{
             if !self.popup_window.visible() {                
                 let (mut x, mut y) = nwg::GlobalCursor::position(); //cursor position
@@ -437,6 +456,9 @@ impl PassToolApp {
                 let hprocess = OpenProcess(PROCESS_QUERY_INFORMATION | PROCESS_VM_READ, 0, process_id); //process handle of the window below the cursos
                 GetModuleFileNameExW(hprocess, 0 as HMODULE, process_name.as_mut_ptr(), MAX_PATH as u32);
                 *self.active_process.borrow_mut() = process_name.iter().filter_map(|x| if *x != 0 { Some(char::from_u32(*x as u32).unwrap()) } else { None }).collect(); //setting active_process to the name of the underlying window process
+                let mut title = [0 as WCHAR; 512]; //title of the top-level window, the point can be over a child control
+                let len = GetWindowTextW(GetAncestor(active_hwnd, GA_ROOT), title.as_mut_ptr(), title.len() as i32);
+                *self.active_title.borrow_mut() = String::from_utf16_lossy(&title[..len.max(0) as usize]);
                 
                 //dbg!(self.active_process.borrow());
 
@@ -448,6 +470,7 @@ impl PassToolApp {
                 y = std::cmp::min(total_height-h-50, y-h/2);
 
                 self.popup_window.set_position(x, y);
+                self.reload();
                 self.update_lists();
                 self.popup_window.set_enabled(true);
                 self.popup_window.set_visible(true);
@@ -591,17 +614,20 @@ impl PassToolApp {
     fn update_rec_passwords(&self) {
         let app = &*self.active_process.borrow();
         let pt = self.passtable.borrow();
-        let mut names: Vec<&String> = pt.get_names().collect();
-        names.sort();
-        *self.rec_pass_names.borrow_mut() = names.iter()
-                                                .map(|x| {(*x).clone()})
-                                                .filter(|x| {
-                                                    pt.get_metadata(x).unwrap().apps.contains(app)
-                                                }).collect();
+        let suggestions = passtool::app_rules::suggest(&pt, app, Some(&self.active_title.borrow()), &passtool::fingerprint::Files);
+        *self.rec_pass_names.borrow_mut() = suggestions.iter().map(|suggestion| suggestion.name.clone()).collect();
         let rv = &self.rec_pass_view;
         rv.clear();
-        for name in &(*self.rec_pass_names.borrow()) {
+        for suggestion in &suggestions {
+            let name = &suggestion.name;
             let meta = pt.get_metadata(name).unwrap();
+            // warn about executables that aren't the one the rule was bound to
+            let status = match suggestion.verification {
+                Verification::Unbound => "",
+                Verification::Verified => "[verified] ",
+                Verification::Mismatch => "[different executable] ",
+                Verification::Unavailable => "[unverified] "
+            };
             let ind: i32 = rv.len() as i32;
             rv.insert_item(nwg::InsertListViewItem {
                 index: Some(ind),
@@ -612,7 +638,7 @@ impl PassToolApp {
             rv.insert_item(nwg::InsertListViewItem {
                 index: Some(ind),
                 column_index: 1,
-                text: Some(meta.description.clone()),
+                text: Some(format!("{status}{}", meta.description)),
                 image: None,
             }); 
         }
@@ -620,19 +646,20 @@ impl PassToolApp {
 
     fn update_all_passwords(&self) {
         let pt = self.passtable.borrow();
-        let mut names: Vec<&String> = pt.get_names().collect();
-        names.sort();
-        *self.pass_names.borrow_mut() = names.iter().map(|x| {(*x).clone()}).collect(); 
+        // grouped entries come first and are shown with their group path
+        let tree = pt.get_tree(None);
+        let entries = tree.flatten();
+        *self.pass_names.borrow_mut() = entries.iter().map(|(_, name)| name.to_string()).collect();
 
         let dv = &self.pass_view;
         dv.clear();
-        for name in &(*self.pass_names.borrow()) {
+        for (group, name) in entries {
             let meta = pt.get_metadata(name).unwrap();
             let ind: i32 = dv.len() as i32;
             dv.insert_item(nwg::InsertListViewItem {
                 index: Some(ind),
                 column_index: 0,
-                text: Some(name.clone()),
+                text: Some(if group.path.is_empty() {name.to_string()} else {format!("{}/{name}", group.path)}),
                 image: None,
             });
             dv.insert_item(nwg::InsertListViewItem {
@@ -644,31 +671,34 @@ impl PassToolApp {
         }
     }
 
-    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let mut path = std::env::current_exe().unwrap();
-        path.pop();
-        path.push(SAVEFILE);
-        self.passtable.borrow().to_file(&path)
+    /// Applies `update` to the vault under its lock and reloads it, so changes made by other programs are kept.
+    fn modify<T>(&self, update: impl FnOnce(&mut PassTable) -> Result<T, Box<dyn std::error::Error>>) -> Result<T, Box<dyn std::error::Error>> {
+        let result = store::update(passtool::default_path(), &mut vault_key, update)?;
+        self.reload();
+        Ok(result)
+    }
+
+    /// Picks up changes made by other programs.
+    fn reload(&self) {
+        if let Ok(pt) = store::load(passtool::default_path(), &mut vault_key) {
+            *self.passtable.borrow_mut() = pt;
+        }
     }
 
     fn exit(&self) {
-        let _ = self.save();
+        self.clipboard.shutdown();
         nwg::stop_thread_dispatch();
     }
 
 }
 
+/// The default vault is a vault file, it is opened without a key.
+fn vault_key() -> Result<String, Box<dyn std::error::Error>> {
+    Err("the vault needs a key".into())
+}
+
 pub fn run() {
-    let pt = PassTable::default();
-    
-    let mut path = std::env::current_exe().unwrap();
-    path.pop();
-    path.push(SAVEFILE);
-    
-    if !path.exists() {
-        pt.to_file(&path).unwrap();
-    }
-    let pt = PassTable::from_file(&path).unwrap();
+    let pt = store::create(passtool::default_path(), &mut vault_key).and_then(|store| store.load()).unwrap();
     
     nwg::init().expect("Failed to init Native Windows GUI");
     nwg::Font::set_global_family("Segoe UI").expect("Failed to set default font");
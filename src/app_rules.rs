@@ -0,0 +1,281 @@
+//! Rules in `PasswordMeta::apps` deciding which entries the overlay offers for an application.
+//! Paths are compared ignoring case and the kind of slash, so a changed drive letter case still matches.
+//!
+//! ```text
+//! C:\Program Files\Git\git-bash.exe           the exact path
+//! exe:chrome.exe                              any executable with this file name
+//! glob:C:\Users\*\AppData\**\slack.exe        * and ? stop at slashes, ** doesn't
+//! regex:(?i)\\firefox( nightly)?\\            regular expression searched in the path as it is
+//! exe:chrome.exe | title:GitHub               any of the above, only while the window title matches the regex
+//! ```
+//! Rules can be bound to an executable with a `fingerprint`, `suggest` reports whether it still matches.
+use std::{cell::OnceCell, io, path::Path};
+
+use regex::Regex;
+
+use crate::{fingerprint::{Fingerprinter, Verification}, PassTable, PasswordMeta};
+
+const TITLE: &str = " | title:";
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// Normalized by `normalize`.
+    Path(String),
+    /// Lowercase file name.
+    Exe(String),
+    Glob(Regex),
+    Regex(Regex)
+}
+
+#[derive(Debug, Clone)]
+pub struct AppRule {
+    pub pattern: Pattern,
+    pub title: Option<Regex>
+}
+
+/// Lowercase with forward slashes.
+fn normalize(path: &str) -> String {
+    path.replace('\\', "/").to_lowercase()
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+/// Case-insensitive regex matching the whole normalized path.
+fn glob(pattern: &str) -> Result<Regex, regex::Error> {
+    let mut regex = String::from("^");
+    let pattern = normalize(pattern);
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.next_if_eq(&'*').is_some() => regex.push_str(".*"),
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string()))
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex)
+}
+
+impl AppRule {
+    pub fn parse(rule: &str) -> Result<Self, regex::Error> {
+        let (rule, title) = match rule.rsplit_once(TITLE) {
+            Some((rule, title)) => (rule, Some(Regex::new(title)?)),
+            None => (rule, None)
+        };
+        let pattern = if let Some(exe) = rule.strip_prefix("exe:") {
+            Pattern::Exe(exe.to_lowercase())
+        } else if let Some(pattern) = rule.strip_prefix("glob:") {
+            Pattern::Glob(glob(pattern)?)
+        } else if let Some(regex) = rule.strip_prefix("regex:") {
+            Pattern::Regex(Regex::new(regex)?)
+        } else {
+            Pattern::Path(normalize(rule))
+        };
+        Ok(AppRule{pattern, title})
+    }
+
+    /// How specific the match of the app at `path` is, `None` if it doesn't match.
+    /// Rules with a title pattern only match if the window `title` is known.
+    pub fn score(&self, path: &str, title: Option<&str>) -> Option<u32> {
+        let score = match &self.pattern {
+            Pattern::Path(rule) => (normalize(path) == *rule).then_some(4),
+            Pattern::Glob(glob) => glob.is_match(&normalize(path)).then_some(3),
+            Pattern::Regex(regex) => regex.is_match(path).then_some(3),
+            Pattern::Exe(exe) => (file_name(path).to_lowercase() == *exe).then_some(2)
+        }?;
+        match &self.title {
+            Some(regex) => regex.is_match(title?).then_some(score + 4),
+            None => Some(score)
+        }
+    }
+}
+
+/// Best matching rule in `apps` with its score, rules that don't parse never match.
+pub fn best_rule<'a>(apps: &'a [String], path: &str, title: Option<&str>) -> Option<(u32, &'a String)> {
+    apps.iter()
+        .filter_map(|rule| Some((AppRule::parse(rule).ok()?.score(path, title)?, rule)))
+        .max_by_key(|(score, _)| *score)
+}
+
+pub fn score(apps: &[String], path: &str, title: Option<&str>) -> Option<u32> {
+    best_rule(apps, path, title).map(|(score, _)| score)
+}
+
+/// Entries with their best rule matching the app at `path`, the most specific match first.
+fn matching<'a>(table: &'a PassTable, path: &str, title: Option<&str>) -> Vec<(u32, &'a String, &'a PasswordMeta, &'a String)> {
+    let mut found: Vec<(u32, &String, &PasswordMeta, &String)> = table.get_names()
+        .filter_map(|name| {
+            let meta = table.get_metadata(name).unwrap();
+            let (score, rule) = best_rule(&meta.apps, path, title)?;
+            Some((score, name, meta, rule))
+        })
+        .collect();
+    found.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    found
+}
+
+/// Entries with a rule matching the app at `path`, the most specific match first.
+pub fn rank(table: &PassTable, path: &str, title: Option<&str>) -> Vec<String> {
+    matching(table, path, title).into_iter().map(|(_, name, _, _)| name.clone()).collect()
+}
+
+/// Entry offered for an app with the check of the executable against the fingerprint of the matched rule.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Suggestion {
+    pub name: String,
+    pub verification: Verification
+}
+
+/// Every pinned entry checks the same executable, a `suggest` call reads it once.
+struct Once<'a> {
+    inner: &'a dyn Fingerprinter,
+    sha256: OnceCell<Result<[u8; 32], io::ErrorKind>>,
+    signer: OnceCell<Result<Option<String>, io::ErrorKind>>
+}
+
+impl Fingerprinter for Once<'_> {
+    fn sha256(&self, path: &Path) -> io::Result<[u8; 32]> {
+        Ok((*self.sha256.get_or_init(|| self.inner.sha256(path).map_err(|e| e.kind())))?)
+    }
+
+    fn signer(&self, path: &Path) -> io::Result<Option<String>> {
+        Ok(self.signer.get_or_init(|| self.inner.signer(path).map_err(|e| e.kind())).clone()?)
+    }
+}
+
+/// `rank` with the fingerprints checked, entries whose executable doesn't match come last.
+pub fn suggest(table: &PassTable, path: &str, title: Option<&str>, fingerprinter: &dyn Fingerprinter) -> Vec<Suggestion> {
+    let fingerprinter = Once{inner: fingerprinter, sha256: OnceCell::new(), signer: OnceCell::new()};
+    let mut suggestions: Vec<Suggestion> = matching(table, path, title).into_iter()
+        .map(|(_, name, meta, rule)| Suggestion{
+            name: name.clone(),
+            verification: meta.fingerprints.get(rule).map_or(Verification::Unbound, |fingerprint| fingerprinter.verify(fingerprint, Path::new(path)))
+        })
+        .collect();
+    // the sort is stable, the rank order stays otherwise
+    suggestions.sort_by_key(|suggestion| suggestion.verification == Verification::Mismatch);
+    suggestions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::Digest;
+
+    fn matches(rule: &str, path: &str, title: Option<&str>) -> bool {
+        AppRule::parse(rule).unwrap().score(path, title).is_some()
+    }
+
+    #[test]
+    fn rule_test() {
+        let chrome = r"C:\Program Files\Google\Chrome\Application\chrome.exe";
+        assert!(matches(r"c:\program files\google\chrome\application\CHROME.EXE", chrome, None));
+        assert!(matches("C:/Program Files/Google/Chrome/Application/chrome.exe", chrome, None));
+        assert!(!matches(r"D:\Program Files\Google\Chrome\Application\chrome.exe", chrome, None));
+        assert!(matches("exe:Chrome.exe", chrome, None));
+        assert!(matches("exe:chrome", "/opt/google/chrome/chrome", None));
+        assert!(!matches("exe:chrome.exe", r"C:\chrome.exe.bak", None));
+
+        assert!(matches(r"glob:C:\Program Files\*\Chrome\**\chrome.exe", chrome, None));
+        assert!(!matches(r"glob:C:\Program Files\*\chrome.exe", chrome, None));
+        assert!(matches(r"glob:c:\program files\google\chrome\applicatio?\*.exe", chrome, None));
+        assert!(matches(r"glob:/usr/bin/python3.1?", "/usr/bin/python3.12", None));
+        assert!(!matches(r"glob:/usr/bin/python3.1?", "/usr/bin/python3.1", None));
+        assert!(matches(r"regex:(?i)\\google\\chrome\\", chrome, None));
+        assert!(!matches(r"regex:\\google\\chrome\\", chrome, None));
+
+        assert!(matches("exe:chrome.exe | title:GitHub", chrome, Some("Pull requests - GitHub - Google Chrome")));
+        assert!(!matches("exe:chrome.exe | title:GitHub", chrome, Some("Inbox - Gmail")));
+        assert!(!matches("exe:chrome.exe | title:GitHub", chrome, None));
+        assert!(AppRule::parse("regex:(").is_err());
+        assert!(AppRule::parse("exe:x | title:(").is_err());
+    }
+
+    #[test]
+    fn rank_test() -> Result<(), crate::Error> {
+        let chrome = r"C:\Program Files\Google\Chrome\Application\chrome.exe";
+        let mut pt = PassTable::new();
+        pt.add_password("any browser", "x", PasswordMeta::new(String::new(), vec!["exe:firefox.exe".to_string(), "exe:chrome.exe".to_string()]), "key")?;
+        pt.add_password("github", "x", PasswordMeta::new(String::new(), vec!["exe:chrome.exe | title:GitHub".to_string()]), "key")?;
+        pt.add_password("chrome", "x", PasswordMeta::new(String::new(), vec![chrome.to_lowercase()]), "key")?;
+        pt.add_password("broken", "x", PasswordMeta::new(String::new(), vec!["regex:(".to_string()]), "key")?;
+        pt.add_password("other", "x", PasswordMeta::new(String::new(), vec!["exe:notepad.exe".to_string()]), "key")?;
+
+        assert_eq!(rank(&pt, chrome, Some("GitHub")), ["github", "chrome", "any browser"]);
+        assert_eq!(rank(&pt, chrome, Some("Gmail")), ["chrome", "any browser"]);
+        assert_eq!(rank(&pt, r"C:\Windows\notepad.exe", None), ["other"]);
+        Ok(())
+    }
+
+    /// Executables named by their contents.
+    struct Fake;
+
+    impl Fingerprinter for Fake {
+        fn sha256(&self, path: &Path) -> std::io::Result<[u8; 32]> {
+            if path.starts_with("/missing") { return Err(std::io::ErrorKind::NotFound.into()); }
+            Ok(sha2::Sha256::digest(path.to_string_lossy().as_bytes()).into())
+        }
+
+        fn signer(&self, path: &Path) -> std::io::Result<Option<String>> {
+            Ok(path.to_string_lossy().contains("signed").then(|| "Vendor".to_string()))
+        }
+    }
+
+    #[test]
+    fn suggest_once_test() -> Result<(), crate::Error> {
+        struct Counting(std::cell::Cell<usize>);
+
+        impl Fingerprinter for Counting {
+            fn sha256(&self, path: &Path) -> std::io::Result<[u8; 32]> {
+                self.0.set(self.0.get() + 1);
+                Fake.sha256(path)
+            }
+
+            fn signer(&self, path: &Path) -> std::io::Result<Option<String>> {
+                Fake.signer(path)
+            }
+        }
+
+        let mut pt = PassTable::new();
+        for (name, bound) in [("first", "/a/tool.exe"), ("second", "/b/tool.exe")] {
+            let mut meta = PasswordMeta::new(String::new(), vec!["exe:tool.exe".to_string()]);
+            meta.fingerprints.insert("exe:tool.exe".to_string(), Fake.fingerprint(Path::new(bound), false).unwrap());
+            pt.add_password(name, "x", meta, "key")?;
+        }
+        let counting = Counting(Default::default());
+        assert_eq!(suggest(&pt, "/a/tool.exe", None, &counting).len(), 2);
+        assert_eq!(counting.0.get(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn suggest_test() -> Result<(), crate::Error> {
+        use crate::fingerprint::Fingerprint;
+        let mut pt = PassTable::new();
+        let mut meta = PasswordMeta::new(String::new(), vec!["exe:tool.exe".to_string()]);
+        meta.fingerprints.insert("exe:tool.exe".to_string(), Fake.fingerprint(Path::new("/good/tool.exe"), false).unwrap());
+        pt.add_password("pinned", "x", meta, "key")?;
+        let mut meta = PasswordMeta::new(String::new(), vec!["exe:tool.exe".to_string()]);
+        meta.fingerprints.insert("exe:tool.exe".to_string(), Fingerprint::Signer("Vendor".to_string()));
+        pt.add_password("signed", "x", meta, "key")?;
+        pt.add_password("unbound", "x", PasswordMeta::new(String::new(), vec!["exe:tool.exe".to_string()]), "key")?;
+
+        let suggest = |path: &str| -> Vec<(String, Verification)> {
+            suggest(&pt, path, None, &Fake).into_iter().map(|s| (s.name, s.verification)).collect()
+        };
+        assert_eq!(suggest("/good/tool.exe"), [("pinned".to_string(), Verification::Verified), ("unbound".to_string(), Verification::Unbound),
+            ("signed".to_string(), Verification::Mismatch)]);
+        assert_eq!(suggest("/signed/tool.exe"), [("signed".to_string(), Verification::Verified), ("unbound".to_string(), Verification::Unbound),
+            ("pinned".to_string(), Verification::Mismatch)]);
+        assert_eq!(suggest("/good/other.exe").len(), 0);
+        assert_eq!(suggest("/missing/tool.exe")[0], ("pinned".to_string(), Verification::Unavailable));
+
+        let mut meta = pt.get_metadata("pinned")?.clone();
+        meta.remove_app(0);
+        assert!(meta.fingerprints.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+//! askpass provider for `SSH_ASKPASS`, `SUDO_ASKPASS` and `GIT_ASKPASS`: the tool runs the program with
+//! its prompt as argument and reads the answer from stdout.
+//! Prompts are mapped to entries by rules, one per line:
+//!
+//! ```text
+//! # entry[ / field] = regex
+//! sudo = ^\[sudo\] password for
+//! github / username = ^Username for 'https://github\.com'
+//! git $1 = ^Password for 'https://(?:[^@]+@)?([^']+)'
+//! ```
+//!
+//! The first matching rule wins, `$1` or `${name}` in the entry name are replaced with captures.
+use core::fmt;
+use std::{fs, io, path::{Path, PathBuf}};
+
+use regex::Regex;
+
+use crate::{template::FIELDS, PassTable};
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    MissingSeparator(usize),
+    UnknownField(usize, String),
+    Regex(usize, String)
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingSeparator(line) => write!(f, "line {line}: expected 'entry = regex'"),
+            Self::UnknownField(line, field) => write!(f, "line {line}: unknown field '{field}'"),
+            Self::Regex(line, e) => write!(f, "line {line}: {e}")
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug)]
+pub struct Rule {
+    pub pattern: Regex,
+    pub entry: String,
+    pub field: String
+}
+
+pub fn parse(rules: &str) -> Result<Vec<Rule>, ParseError> {
+    let mut parsed = Vec::new();
+    for (i, line) in rules.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        let (target, pattern) = line.split_once(" = ").ok_or(ParseError::MissingSeparator(i + 1))?;
+        let (entry, field) = target.rsplit_once(" / ").unwrap_or((target, "password"));
+        let field = field.trim();
+        if !FIELDS.contains(&field) {
+            return Err(ParseError::UnknownField(i + 1, field.to_string()));
+        }
+        let pattern = Regex::new(pattern.trim()).map_err(|e| ParseError::Regex(i + 1, e.to_string()))?;
+        parsed.push(Rule{pattern, entry: entry.trim().to_string(), field: field.to_string()});
+    }
+    Ok(parsed)
+}
+
+/// `$PASSTOOL_ASKPASS_RULES` or `<vault>.askpass`.
+pub fn rules_path(vault: &Path) -> PathBuf {
+    if let Some(path) = std::env::var_os("PASSTOOL_ASKPASS_RULES") {
+        return PathBuf::from(path);
+    }
+    let mut path = vault.as_os_str().to_owned();
+    path.push(".askpass");
+    PathBuf::from(path)
+}
+
+/// Rules stored in `path`, none if the file does not exist.
+pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<Vec<Rule>, Box<dyn std::error::Error>> {
+    match fs::read_to_string(path) {
+        Ok(rules) => Ok(parse(&rules)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into())
+    }
+}
+
+/// Entry and field of the first rule matching `prompt`.
+pub fn resolve<'a>(rules: &'a [Rule], prompt: &str) -> Option<(String, &'a str)> {
+    rules.iter().find_map(|rule| {
+        let captures = rule.pattern.captures(prompt)?;
+        let mut entry = String::new();
+        captures.expand(&rule.entry, &mut entry);
+        Some((entry, rule.field.as_str()))
+    })
+}
+
+/// Decrypts the password of an entry.
+pub type PasswordFn<'a> = dyn FnMut(&str) -> Result<String, Box<dyn std::error::Error>> + 'a;
+
+#[derive(Debug, PartialEq)]
+pub enum Answer {
+    /// The field of the entry a rule maps the prompt to.
+    Vault(String),
+    /// No rule matches the prompt.
+    NoRule,
+    /// The entry a rule maps the prompt to doesn't exist.
+    MissingEntry(String)
+}
+
+impl Answer {
+    /// The answer from the vault, otherwise the one typed at the terminal.
+    pub fn or_prompt(self, prompt: &str) -> io::Result<String> {
+        match self {
+            Answer::Vault(answer) => Ok(answer),
+            Answer::NoRule | Answer::MissingEntry(_) => rpassword::prompt_password(prompt)
+        }
+    }
+}
+
+/// Answers `prompt` from the vault, `password` decrypts an entry, the other fields are read from the metadata.
+pub fn answer(rules: &[Rule], prompt: &str, table: &PassTable, password: &mut PasswordFn) -> Result<Answer, Box<dyn std::error::Error>> {
+    let Some((entry, field)) = resolve(rules, prompt) else { return Ok(Answer::NoRule) };
+    let Ok(meta) = table.get_metadata(&entry) else { return Ok(Answer::MissingEntry(entry)) };
+    Ok(Answer::Vault(match field {
+        "username" => meta.username.clone(),
+        "url" => meta.url.clone(),
+        "description" => meta.description.clone(),
+        _ => password(&entry)?
+    }))
+}
+
+/// Answers `prompt` with the rules of `vault`, entries are decrypted through the unlock agent if one is running.
+pub fn run(vault: &Path, prompt: &str, key: &mut dyn FnMut() -> Result<String, Box<dyn std::error::Error>>) -> Result<Answer, Box<dyn std::error::Error>> {
+    let rules = parse_file(rules_path(vault))?;
+    let table = crate::store::load(vault, key)?;
+    let mut decrypted = Vec::new();
+    let answer = answer(&rules, prompt, &table, &mut |entry| {
+        let password = crate::unlock_agent::get_password(vault, &table, entry, key)?;
+        decrypted.push(entry.to_string());
+        Ok(password)
+    })?;
+    crate::store::mark_accessed(vault, key, &decrypted)?;
+    Ok(answer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PasswordMeta;
+
+    const RULES: &str = r"
+# sudo and git
+sudo = ^\[sudo\] password for
+github / username = ^Username for 'https://github\.com'
+git $1 = ^Password for 'https://(?:[^@]+@)?([^']+)'
+";
+
+    #[test]
+    fn parse_test() {
+        let rules = parse(RULES).unwrap();
+        assert_eq!(rules.len(), 3);
+        assert_eq!((rules[1].entry.as_str(), rules[1].field.as_str()), ("github", "username"));
+        assert_eq!(parse("sudo ^x").unwrap_err(), ParseError::MissingSeparator(1));
+        assert_eq!(parse("\nx / secret = y").unwrap_err(), ParseError::UnknownField(2, "secret".to_string()));
+        assert!(matches!(parse("x = (").unwrap_err(), ParseError::Regex(1, _)));
+    }
+
+    #[test]
+    fn resolve_test() {
+        let rules = parse(RULES).unwrap();
+        assert_eq!(resolve(&rules, "[sudo] password for alice: "), Some(("sudo".to_string(), "password")));
+        assert_eq!(resolve(&rules, "Username for 'https://github.com': "), Some(("github".to_string(), "username")));
+        assert_eq!(resolve(&rules, "Password for 'https://bob@gitlab.com': "), Some(("git gitlab.com".to_string(), "password")));
+        assert_eq!(resolve(&rules, "Enter passphrase for key '/home/alice/.ssh/id_ed25519': "), None);
+    }
+
+    #[test]
+    fn answer_test() -> Result<(), Box<dyn std::error::Error>> {
+        let rules = parse(RULES)?;
+        let mut pt = PassTable::new();
+        pt.add_password("github", "token", PasswordMeta{username: "alice".to_string(), ..Default::default()}, "key")?;
+        pt.add_password("git gitlab.com", "hunter2", PasswordMeta::default(), "key")?;
+        let mut password = |name: &str| -> Result<String, Box<dyn std::error::Error>> { Ok(pt.get_password(name, "key")?) };
+        assert_eq!(answer(&rules, "Username for 'https://github.com': ", &pt, &mut password)?, Answer::Vault("alice".to_string()));
+        assert_eq!(answer(&rules, "Password for 'https://gitlab.com': ", &pt, &mut password)?, Answer::Vault("hunter2".to_string()));
+        assert_eq!(answer(&rules, "Password for 'https://example.com': ", &pt, &mut password)?, Answer::MissingEntry("git example.com".to_string()));
+        assert_eq!(answer(&rules, "Enter passphrase: ", &pt, &mut password)?, Answer::NoRule);
+        Ok(())
+    }
+}
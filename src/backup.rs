@@ -0,0 +1,142 @@
+use std::{fs::File, io::{self, BufReader, Read, Write}, iter, path::Path, str::FromStr};
+
+use age::{
+    armor::{ArmoredReader, ArmoredWriter, Format},
+    scrypt, x25519,
+    secrecy::SecretString,
+    Decryptor, Encryptor, IdentityFile
+};
+
+use crate::PassTable;
+
+/// Who an exported vault is encrypted to.
+pub enum Recipients {
+    /// scrypt recipient stanza, anyone knowing the passphrase can decrypt.
+    Passphrase(String),
+    /// X25519 public keys (`age1...`).
+    Keys(Vec<String>)
+}
+
+/// What an exported vault is decrypted with.
+pub enum Identities {
+    Passphrase(String),
+    /// Contents of an age identity file: `AGE-SECRET-KEY-1...` lines, `#` comments are allowed.
+    Keys(String)
+}
+
+fn encryptor(to: &Recipients, work_factor: Option<u8>) -> Result<Encryptor, Box<dyn std::error::Error>> {
+    match to {
+        Recipients::Passphrase(passphrase) => {
+            let mut recipient = scrypt::Recipient::new(SecretString::from(passphrase.clone()));
+            if let Some(log_n) = work_factor { recipient.set_work_factor(log_n); }
+            Ok(Encryptor::with_recipients(iter::once(&recipient as _))?)
+        }
+        Recipients::Keys(keys) => {
+            let recipients = keys.iter()
+                .map(|k| x25519::Recipient::from_str(k.trim()))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Encryptor::with_recipients(recipients.iter().map(|r| r as _))?)
+        }
+    }
+}
+
+fn export_with<W: Write>(table: &PassTable, to: &Recipients, output: W, armor: bool, work_factor: Option<u8>) -> Result<(), Box<dyn std::error::Error>> {
+    let format = if armor {Format::AsciiArmor} else {Format::Binary};
+    let armored = ArmoredWriter::wrap_output(output, format)?;
+    let mut writer = encryptor(to, work_factor)?.wrap_output(armored)?;
+    writer.write_all(&table.encoded())?;
+    writer.finish()?.finish()?;
+    Ok(())
+}
+
+/// Writes a snapshot of `table` as an age v1 file, optionally ASCII-armored.
+pub fn export<W: Write>(table: &PassTable, to: &Recipients, output: W, armor: bool) -> Result<(), Box<dyn std::error::Error>> {
+    export_with(table, to, output, armor, None)
+}
+
+/// Reads a vault snapshot from an age v1 file, armored or binary.
+pub fn import<R: Read>(input: R, with: &Identities) -> Result<PassTable, Box<dyn std::error::Error>> {
+    let decryptor = Decryptor::new(ArmoredReader::new(BufReader::new(input)))?;
+    let mut reader = match with {
+        Identities::Passphrase(passphrase) => {
+            if !decryptor.is_scrypt() { return Err("file is not encrypted with a passphrase".into()); }
+            let identity = scrypt::Identity::new(SecretString::from(passphrase.clone()));
+            decryptor.decrypt(iter::once(&identity as _))?
+        }
+        Identities::Keys(keys) => {
+            let identities = IdentityFile::from_buffer(keys.as_bytes())?.into_identities()?;
+            decryptor.decrypt(identities.iter().map(|i| i.as_ref() as _))?
+        }
+    };
+    let mut encoded = Vec::new();
+    reader.read_to_end(&mut encoded)?;
+    PassTable::from_binary(&encoded)
+}
+
+pub fn export_file<P: AsRef<Path>>(table: &PassTable, to: &Recipients, filename: P, armor: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = io::BufWriter::new(File::create(filename)?);
+    export(table, to, &mut file, armor)?;
+    file.flush()?;
+    Ok(())
+}
+
+pub fn import_file<P: AsRef<Path>>(filename: P, with: &Identities) -> Result<PassTable, Box<dyn std::error::Error>> {
+    import(File::open(filename)?, with)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+    use crate::PasswordMeta;
+
+    fn table() -> PassTable {
+        let mut pt = PassTable::new();
+        pt.add_password("pass1", "test1", PasswordMeta::new("desc".to_string(), vec!["app.exe".to_string()]), "key1").unwrap();
+        pt.add_password("pass2", "test2", PasswordMeta::default(), "key2").unwrap();
+        pt
+    }
+
+    #[test]
+    fn x25519_roundtrip_test() -> Result<(), Box<dyn std::error::Error>> {
+        let identity = x25519::Identity::generate();
+        let recipient = identity.to_public().to_string();
+        let pt = table();
+
+        let mut file = Vec::new();
+        export(&pt, &Recipients::Keys(vec![recipient]), &mut file, false)?;
+        assert!(file.starts_with(b"age-encryption.org/v1\n-> X25519 "));
+
+        let keys = format!("# created: test\n{}\n", identity.to_string().expose_secret());
+        let pt2 = import(&file[..], &Identities::Keys(keys))?;
+        assert_eq!(pt, pt2);
+        assert_eq!(pt2.get_password("pass2", "key2")?, "test2");
+        Ok(())
+    }
+
+    #[test]
+    fn armored_wrong_identity_test() -> Result<(), Box<dyn std::error::Error>> {
+        let identity = x25519::Identity::generate();
+        let other = x25519::Identity::generate();
+        let mut file = Vec::new();
+        export(&table(), &Recipients::Keys(vec![identity.to_public().to_string()]), &mut file, true)?;
+        assert!(file.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----"));
+
+        let keys = other.to_string().expose_secret().to_string();
+        assert!(import(&file[..], &Identities::Keys(keys)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn passphrase_roundtrip_test() -> Result<(), Box<dyn std::error::Error>> {
+        let pt = table();
+        let mut file = Vec::new();
+        export_with(&pt, &Recipients::Passphrase("backup pass".to_string()), &mut file, false, Some(2))?;
+        assert!(file.starts_with(b"age-encryption.org/v1\n-> scrypt "));
+
+        assert!(import(&file[..], &Identities::Passphrase("wrong".to_string())).is_err());
+        let pt2 = import(&file[..], &Identities::Passphrase("backup pass".to_string()))?;
+        assert_eq!(pt, pt2);
+        Ok(())
+    }
+}
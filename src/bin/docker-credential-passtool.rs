@@ -0,0 +1,32 @@
+//! docker credential helper, enable it with `"credsStore": "passtool"` in ~/.docker/config.json.
+use std::{env, error::Error, io, path::PathBuf, process::ExitCode};
+
+use passtool::{docker_credential, store};
+
+fn key() -> Result<String, Box<dyn Error>> {
+    Ok(match env::var("PASSTOOL_KEY") {
+        Ok(key) => key,
+        Err(_) => rpassword::prompt_password("passtool key: ")?
+    })
+}
+
+fn main() -> ExitCode {
+    let Some(action) = env::args().nth(1) else {
+        eprintln!("usage: docker-credential-passtool <store|get|erase|list|version>");
+        return ExitCode::FAILURE;
+    };
+    if action == "version" {
+        println!("docker-credential-passtool {}", env!("CARGO_PKG_VERSION"));
+        return ExitCode::SUCCESS;
+    }
+
+    let vault = env::var_os("PASSTOOL_VAULT").map_or_else(passtool::default_path, PathBuf::from);
+    let result = store::update(&vault, &mut key, |table| Ok(docker_credential::handle(&action, io::stdin().lock(), io::stdout(), table, &mut key)?));
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            println!("{e}"); // docker reads errors from stdout
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,30 @@
+//! askpass program answering from the vault, point `SSH_ASKPASS`, `SUDO_ASKPASS` or `GIT_ASKPASS` at it.
+//! Same as `passtool askpass`, the vault is `$PASSTOOL_VAULT` or the default one.
+use std::{env, path::PathBuf, process::ExitCode};
+
+use passtool::askpass::{self, Answer};
+
+fn main() -> ExitCode {
+    let prompt = env::args().skip(1).collect::<Vec<_>>().join(" ");
+    let vault = env::var_os("PASSTOOL_VAULT").map_or_else(passtool::default_path, PathBuf::from);
+    let mut key = || Ok(match env::var("PASSTOOL_KEY") {
+        Ok(key) => key,
+        Err(_) => rpassword::prompt_password("passtool key: ")?
+    });
+    let answer = askpass::run(&vault, &prompt, &mut key).and_then(|answer| {
+        if let Answer::MissingEntry(entry) = &answer {
+            eprintln!("passtool-askpass: entry '{entry}' not found");
+        }
+        Ok(answer.or_prompt(&prompt)?)
+    });
+    match answer {
+        Ok(answer) => {
+            println!("{answer}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("passtool-askpass: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,29 @@
+//! Browser native-messaging host, register it with a manifest like
+//! `{"name": "passtool", "description": "passtool", "path": "/path/to/passtool-native-host", "type": "stdio", "allowed_origins": ["chrome-extension://<id>/"]}`
+//! (`allowed_extensions` for Firefox). The vault is `$PASSTOOL_VAULT` or the default one.
+use std::{env, io, path::PathBuf, process::ExitCode};
+
+use passtool::{native_messaging, store};
+
+fn journal_key(key: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+    match key {
+        Some(key) => Ok(key.to_string()),
+        None => env::var("PASSTOOL_KEY").map_err(|_| "the vault is locked".into())
+    }
+}
+
+fn main() -> ExitCode {
+    let vault = env::var_os("PASSTOOL_VAULT").map_or_else(passtool::default_path, PathBuf::from);
+    // a journal vault is opened with the key the extension unlocks with, or $PASSTOOL_KEY before that
+    let (source, accessed) = (vault.clone(), vault.clone());
+    let mut host = native_messaging::Host::new(vault, Box::new(move |key| store::load(&source, &mut || journal_key(key))));
+    host.on_access(Box::new(move |name, key| store::mark_accessed(&accessed, &mut || journal_key(key), &[name])));
+    // stdout carries the messages, diagnostics go to stderr
+    match native_messaging::serve(&mut host, io::stdin().lock(), io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("passtool-native-host: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
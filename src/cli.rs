@@ -0,0 +1,637 @@
+//! Command line interface, used when passtool is started with arguments.
+use std::{cell::RefCell, env, error::Error, fs, io::{self, Read, Write}, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}, vec::IntoIter};
+
+use passtool::{app_rules, askpass, fingerprint::{self, Fingerprinter}, git_credential, rest_api, run, search, ssh_agent, store, template, tui, unlock_agent, AccessRecorder, Expiry, Group, PassTable, PasswordMeta, TableSource};
+
+const DAY: u64 = 24 * 60 * 60;
+
+const USAGE: &str = "usage: passtool [--vault FILE] <command> [args]
+
+commands:
+    run [--env VAR=ENTRY]... [--mask] -- COMMAND [ARGS]...
+        run COMMAND with the entries decrypted into its environment,
+        --mask replaces secret values in its output
+    inject [--check] [-i TEMPLATE] [-o FILE]
+        render {{ passtool \"entry\" \"field\" }} placeholders from stdin or TEMPLATE,
+        --check only reports placeholders referencing missing entries
+    git-credential get|store|erase
+        git credential helper, configure it with
+        git config credential.helper '!passtool git-credential'
+    add-ssh-key NAME FILE
+        store an unencrypted OpenSSH private key as a vault entry
+    ssh-agent [-a SOCKET]
+        serve the vault's SSH keys over the ssh-agent protocol (unix only),
+        ssh-add -x locks the agent, ssh-add -X unlocks it with the vault key
+    get NAME
+        print the password of an entry
+    list [--tag TAG] [GROUP]
+        show the entries below GROUP as a tree, only those tagged TAG if given
+    search QUERY...
+        list matching entries, best first: words are matched fuzzily against
+        names, descriptions, usernames, URLs and app paths, \"quoted phrases\"
+        exactly, tag:TAG, app:APP, url:URL and group:GROUP filter
+    move NAME GROUP
+        move an entry into GROUP, a path like work/servers, / is the top level
+    tag NAME TAG... | untag NAME TAG...
+        add tags to or remove them from an entry
+    app add|remove NAME RULE
+        change the apps an entry is offered for: a path, exe:FILE_NAME,
+        glob:PATTERN or regex:REGEX, followed by ' | title:REGEX' to also
+        require a window title
+    app pin NAME RULE FILE [--signer] | app unpin NAME RULE
+        bind an app rule to the executable FILE by its SHA-256 or, with
+        --signer, its Authenticode signer on Windows; the overlay marks
+        suggestions whose executable doesn't match
+    rename NAME NEW_NAME
+        rename an entry, its id stays the same
+    expire NAME DAYS|never
+        require the password of an entry to be changed every DAYS days
+    expiring [DAYS]
+        list entries whose password has expired or expires within DAYS days
+        (default 14)
+    trash [list] | trash restore NAME | trash purge [NAME]
+        list, restore or drop removed entries, purge without NAME empties the trash
+    trash retention DAYS|never
+        drop removed entries DAYS days after their removal (default 30)
+    agent [--timeout SECONDS] [--socket SOCKET]
+        keep keys unlocked in the background (unix only), they are forgotten
+        after SECONDS without use (default 900)
+    lock [--socket SOCKET]
+        make the running agent forget its keys
+    secret-service [--locked] [--askpass PROGRAM]
+        provide org.freedesktop.secrets on the session bus (linux only),
+        PROGRAM (default $SSH_ASKPASS) prints the key when a client unlocks
+    api token add NAME [--all] [--entry ENTRY]... [--tag TAG]... [--write]
+        create a bearer token for the HTTP API limited to the given entries
+        and tags, it is printed once and only its hash is kept
+    api token list | api token remove NAME
+    api serve [--port PORT] [--audit FILE]
+        serve the HTTP API on 127.0.0.1 (port 8731 by default), requests are
+        logged to FILE (the vault path with .audit.log appended by default)
+    askpass PROMPT...
+        print the vault answer to an askpass prompt (see passtool-askpass),
+        prompts are mapped to entries by rules in $PASSTOOL_ASKPASS_RULES or
+        the vault path with .askpass appended, lines of 'entry[ / field] = regex'
+    tui [--app APP]
+        browse the vault in a full-screen terminal UI, entries listing APP
+        are offered first and new apps are added to entries as APP, copied
+        text is cleared after $PASSTOOL_CLIPBOARD_TIMEOUT seconds (default 30)
+    convert DEST
+        copy every entry into the vault DEST, a SQLite database if its name
+        ends in .db, .sqlite or .sqlite3 or an append-only journal encrypted
+        with the key if it ends in .ptj (entries stay encrypted)
+
+The vault defaults to $PASSTOOL_VAULT or passwords.pt next to the executable,
+it is a SQLite database or a journal when named like a convert DEST, the key is read from $PASSTOOL_KEY or prompted for on the terminal.
+While an agent for the vault is running on $PASSTOOL_AGENT_SOCK (or the default
+socket), get and run ask it first and only prompt when it is locked.";
+
+struct Context {
+    vault: PathBuf,
+    key: RefCell<Option<String>>
+}
+
+impl Context {
+    fn load(&self) -> Result<PassTable, Box<dyn Error>> {
+        store::load(&self.vault, &mut || self.key()).map_err(|e| format!("can't load {}: {e}", self.vault.display()).into())
+    }
+
+    /// `store::update` of the vault.
+    fn update<T>(&self, update: impl FnOnce(&mut PassTable) -> Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+        store::update(&self.vault, &mut || self.key(), update)
+    }
+
+    /// The key if the vault is a journal, which can't be read without it.
+    fn vault_key(&self) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(if store::is_journal(&self.vault) {Some(self.key()?)} else {None})
+    }
+
+    /// Records that the secrets of `names` were decrypted.
+    fn accessed(&self, names: &[impl AsRef<str>]) -> Result<(), Box<dyn Error>> {
+        store::mark_accessed(&self.vault, &mut || self.key(), names)
+    }
+
+    /// `accessed` for long-running services, the key of a journal is asked for up front.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    fn recorder(&self) -> Result<AccessRecorder, Box<dyn Error>> {
+        let vault = self.vault.clone();
+        let key = self.vault_key()?;
+        Ok(Box::new(move |name| store::mark_accessed(&vault, &mut || key.clone().ok_or_else(|| "missing key".into()), &[name])))
+    }
+
+    /// Loads the vault on every call, the key of a journal is asked for up front.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    fn source(&self) -> Result<TableSource, Box<dyn Error>> {
+        let vault = self.vault.clone();
+        let key = self.vault_key()?;
+        Ok(Box::new(move || store::load(&vault, &mut || key.clone().ok_or_else(|| "missing key".into()))))
+    }
+
+    /// Asked for at most once per invocation.
+    fn key(&self) -> Result<String, Box<dyn Error>> {
+        if let Some(key) = self.key.borrow().as_ref() {
+            return Ok(key.clone());
+        }
+        let key = match env::var("PASSTOOL_KEY") {
+            Ok(key) => key,
+            Err(_) => rpassword::prompt_password("Key: ")?
+        };
+        *self.key.borrow_mut() = Some(key.clone());
+        Ok(key)
+    }
+
+    /// Decrypts an entry through the unlock agent if one is running.
+    fn secret(&self, table: &PassTable, name: &str) -> Result<String, Box<dyn Error>> {
+        unlock_agent::get_password(&self.vault, table, name, &mut || self.key())
+    }
+}
+
+fn run_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let mut env = Vec::new();
+    let mut mask = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--env" => {
+                let binding = args.next().ok_or("--env expects VAR=ENTRY")?;
+                env.push(run::parse_binding(&binding).ok_or(format!("invalid binding '{binding}', expected VAR=ENTRY"))?);
+            }
+            "--mask" => mask = true,
+            "--" => break,
+            _ => return Err(format!("unexpected argument '{arg}'").into())
+        }
+    }
+    let program = args.next().ok_or("missing command to run")?;
+    let table = ctx.load()?;
+    let names: Vec<String> = env.iter().map(|(_, entry)| entry.clone()).collect();
+    let env = env.into_iter()
+        .map(|(var, entry)| Ok((var, ctx.secret(&table, &entry)?)))
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    ctx.accessed(&names)?;
+    run::spawn(env, mask, &program, args.as_slice())
+}
+
+fn get_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let name = args.next().ok_or("missing entry name")?;
+    let table = ctx.load()?;
+    let password = ctx.secret(&table, &name)?;
+    ctx.accessed(&[&name])?;
+    println!("{password}");
+    Ok(0)
+}
+
+fn print_tree(group: &Group, depth: usize) {
+    for subgroup in &group.groups {
+        println!("{:indent$}{}/", "", subgroup.name, indent = depth * 2);
+        print_tree(subgroup, depth + 1);
+    }
+    for name in &group.entries {
+        println!("{:indent$}{name}", "", indent = depth * 2);
+    }
+}
+
+fn list_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let (mut tag, mut group) = (None, None);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tag" => tag = Some(args.next().ok_or("--tag expects a tag")?),
+            _ if group.is_none() => group = Some(arg),
+            _ => return Err(format!("unexpected argument '{arg}'").into())
+        }
+    }
+    let tree = ctx.load()?.get_tree(tag.as_deref());
+    let group = group.unwrap_or_default();
+    print_tree(tree.find(&group).ok_or(format!("no entries in group '{group}'"))?, 0);
+    Ok(0)
+}
+
+fn search_command(ctx: &Context, args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let query = search::Query::parse(&args.collect::<Vec<_>>().join(" "));
+    let table = ctx.load()?;
+    for name in search::search(&table, &query) {
+        println!("{name}");
+    }
+    Ok(0)
+}
+
+fn move_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let name = args.next().ok_or("missing entry name")?;
+    let group = args.next().ok_or("missing group")?;
+    ctx.update(|table| Ok(table.move_to_group(&name, &group)?))?;
+    Ok(0)
+}
+
+fn tag_command(ctx: &Context, mut args: IntoIter<String>, add: bool) -> Result<i32, Box<dyn Error>> {
+    let name = args.next().ok_or("missing entry name")?;
+    let tags: Vec<String> = args.collect();
+    ctx.update(|table| {
+        let meta = table.get_metadata_mut(&name)?;
+        meta.tags.retain(|tag| !tags.contains(tag));
+        if add { meta.tags.extend(tags); }
+        Ok(())
+    })?;
+    Ok(0)
+}
+
+fn app_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    const USAGE: &str = "usage: passtool app add|remove|unpin NAME RULE | passtool app pin NAME RULE FILE [--signer]";
+    let (Some(operation), Some(name), Some(rule)) = (args.next(), args.next(), args.next()) else {
+        return Err(USAGE.into());
+    };
+    app_rules::AppRule::parse(&rule)?;
+    // read before taking the lock, hashing a large executable takes a moment
+    let fingerprint = match operation.as_str() {
+        "pin" => {
+            let file = args.next().ok_or(USAGE)?;
+            let signer = args.next().is_some_and(|arg| arg == "--signer");
+            Some(fingerprint::Files.fingerprint(Path::new(&file), signer)?)
+        }
+        _ => None
+    };
+    ctx.update(|table| {
+        let meta = table.get_metadata_mut(&name)?;
+        match operation.as_str() {
+            "add" => if !meta.apps.contains(&rule) { meta.apps.push(rule.clone()); },
+            "remove" => if let Some(index) = meta.apps.iter().position(|app| *app == rule) { meta.remove_app(index); },
+            "pin" if !meta.apps.contains(&rule) => return Err(format!("'{name}' has no app rule '{rule}'").into()),
+            "pin" => { meta.fingerprints.insert(rule.clone(), fingerprint.clone().unwrap()); },
+            "unpin" => { meta.fingerprints.remove(&rule); },
+            _ => return Err(format!("unknown operation '{operation}'").into())
+        }
+        Ok(())
+    })?;
+    if let Some(fingerprint) = fingerprint {
+        println!("{fingerprint}");
+    }
+    Ok(0)
+}
+
+fn rename_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let name = args.next().ok_or("missing entry name")?;
+    let new_name = args.next().ok_or("missing new name")?;
+    ctx.update(|table| Ok(table.rename(&name, &new_name)?))?;
+    Ok(0)
+}
+
+/// `days` given on the command line in seconds.
+fn seconds(days: &str) -> Result<u64, String> {
+    days.parse::<u64>().ok().and_then(|days| days.checked_mul(DAY)).ok_or_else(|| format!("invalid number of days '{days}'"))
+}
+
+fn expire_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let name = args.next().ok_or("missing entry name")?;
+    let expiry = match args.next().ok_or("expire expects DAYS or never")?.as_str() {
+        "never" => None,
+        days => Some(Expiry::MaxAge(seconds(days)?))
+    };
+    ctx.update(|table| {
+        table.get_metadata_mut(&name)?.expiry = expiry;
+        Ok(())
+    })?;
+    Ok(0)
+}
+
+fn expiring_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let within = match args.next() {
+        Some(days) => seconds(&days)?,
+        None => 14 * DAY
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    for (name, expires) in ctx.load()?.get_expiring(now, within) {
+        match expires.checked_sub(now) {
+            Some(left) if left > 0 => println!("{name}\texpires in {} days", left.div_ceil(DAY)),
+            _ => println!("{name}\texpired")
+        }
+    }
+    Ok(0)
+}
+
+fn trash_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let command = args.next().unwrap_or_else(|| "list".to_string());
+    let name = args.next();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    match (command.as_str(), name) {
+        ("list", None) => for trashed in ctx.load()?.get_trash() {
+            println!("{}\tremoved {} days ago", trashed.name, now.saturating_sub(trashed.deleted) / DAY);
+        },
+        ("restore", Some(name)) => ctx.update(|table| Ok(table.restore(&name)?))?,
+        ("purge", Some(name)) => ctx.update(|table| Ok(table.purge(&name)?))?,
+        ("purge", None) => ctx.update(|table| {
+            table.empty_trash();
+            Ok(())
+        })?,
+        ("retention", Some(days)) => {
+            let retention = match days.as_str() {
+                "never" => None,
+                days => Some(seconds(days)?)
+            };
+            ctx.update(|table| {
+                table.set_trash_retention(retention);
+                Ok(())
+            })?
+        }
+        _ => return Err("usage: passtool trash [list] | restore NAME | purge [NAME] | retention DAYS|never".into())
+    }
+    Ok(0)
+}
+
+fn inject_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let (mut input, mut output, mut check) = (None, None, false);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-i" | "--input" => input = Some(args.next().ok_or("-i expects a file")?),
+            "-o" | "--output" => output = Some(args.next().ok_or("-o expects a file")?),
+            "--check" => check = true,
+            _ => return Err(format!("unexpected argument '{arg}'").into())
+        }
+    }
+    let source = match &input {
+        Some(input) => fs::read_to_string(input)?,
+        None => {
+            let mut source = String::new();
+            io::stdin().read_to_string(&mut source)?;
+            source
+        }
+    };
+    let table = ctx.load()?;
+
+    if check {
+        let missing = template::check(&source, &table)?;
+        for r in &missing {
+            eprintln!("line {}: entry '{}' not found", r.line, r.name);
+        }
+        return Ok(if missing.is_empty() {0} else {1});
+    }
+
+    let key = ctx.key()?;
+    let rendered = template::render(&source, &table, &key)?;
+    let decrypted: Vec<String> = template::references(&source)?.into_iter().filter(|r| r.field == "password").map(|r| r.name).collect();
+    ctx.accessed(&decrypted)?;
+    match output {
+        Some(output) => template::write_output(output, &rendered)?,
+        None => io::stdout().write_all(rendered.as_bytes())?
+    }
+    Ok(0)
+}
+
+fn git_credential_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let operation = args.next().ok_or("missing operation")?;
+    ctx.update(|table| git_credential::handle(&operation, io::stdin().lock(), io::stdout(), table, &mut || ctx.key()))?;
+    Ok(0)
+}
+
+fn add_ssh_key_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let name = args.next().ok_or("missing entry name")?;
+    let file = args.next().ok_or("missing key file")?;
+    let openssh = fs::read_to_string(file)?;
+    ctx.update(|table| ssh_agent::add_key(table, &name, &openssh, PasswordMeta::default(), &ctx.key()?))?;
+    Ok(0)
+}
+
+#[cfg(unix)]
+fn ssh_agent_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    let socket = match args.next().as_deref() {
+        Some("-a") => PathBuf::from(args.next().ok_or("-a expects a socket path")?),
+        Some(arg) => return Err(format!("unexpected argument '{arg}'").into()),
+        None => {
+            let dir = env::temp_dir().join(format!("passtool-ssh-{}", std::process::id()));
+            fs::DirBuilder::new().mode(0o700).create(&dir)?;
+            dir.join("agent.sock")
+        }
+    };
+    let mut agent = ssh_agent::Agent::new(ctx.source()?);
+    agent.on_access(ctx.recorder()?);
+    let loaded = agent.unlock(&ctx.key()?)?;
+    eprintln!("passtool: {loaded} key(s) loaded");
+    println!("SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;", socket.display());
+    io::stdout().flush()?;
+    ssh_agent::listen(&socket, agent)?;
+    Ok(0)
+}
+
+#[cfg(not(unix))]
+fn ssh_agent_command(_ctx: &Context, _args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    Err("ssh-agent is only supported on unix".into())
+}
+
+#[cfg(unix)]
+fn agent_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let mut timeout = unlock_agent::DEFAULT_IDLE_TIMEOUT;
+    let mut socket = unlock_agent::default_socket_path();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--timeout" => {
+                let secs = args.next().ok_or("--timeout expects seconds")?;
+                timeout = std::time::Duration::from_secs(secs.parse().map_err(|_| format!("invalid timeout '{secs}'"))?);
+            }
+            "--socket" => socket = PathBuf::from(args.next().ok_or("--socket expects a path")?),
+            _ => return Err(format!("unexpected argument '{arg}'").into())
+        }
+    }
+    let agent = unlock_agent::UnlockAgent::new(&ctx.vault, ctx.source()?, timeout);
+    println!("PASSTOOL_AGENT_SOCK={}; export PASSTOOL_AGENT_SOCK;", socket.display());
+    io::stdout().flush()?;
+    unlock_agent::listen(&socket, agent)?;
+    Ok(0)
+}
+
+#[cfg(unix)]
+fn lock_command(_ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let socket = match args.next().as_deref() {
+        Some("--socket") => PathBuf::from(args.next().ok_or("--socket expects a path")?),
+        Some(arg) => return Err(format!("unexpected argument '{arg}'").into()),
+        None => unlock_agent::default_socket_path()
+    };
+    let mut client = unlock_agent::Client::connect(socket).map_err(|e| format!("can't reach the agent: {e}"))?;
+    client.request(&unlock_agent::Request::Lock)?;
+    Ok(0)
+}
+
+#[cfg(not(unix))]
+fn agent_command(_ctx: &Context, _args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    Err("agent is only supported on unix".into())
+}
+
+#[cfg(not(unix))]
+fn lock_command(_ctx: &Context, _args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    Err("agent is only supported on unix".into())
+}
+
+#[cfg(target_os = "linux")]
+fn secret_service_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let (mut locked, mut askpass) = (false, env::var("SSH_ASKPASS").ok());
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--locked" => locked = true,
+            "--askpass" => askpass = Some(args.next().ok_or("--askpass expects a program")?),
+            _ => return Err(format!("unexpected argument '{arg}'").into())
+        }
+    }
+    let key = if locked {None} else {Some(ctx.key()?)};
+    let connection = zbus::blocking::Connection::session()?;
+    passtool::secret_service::start(&connection, ctx.vault.clone(), key, askpass)?;
+    loop {
+        std::thread::park();
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn secret_service_command(_ctx: &Context, _args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    Err("secret-service is only supported on linux".into())
+}
+
+fn api_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let tokens_path = rest_api::tokens_path(&ctx.vault);
+    let command = match args.next().as_deref() {
+        Some("token") => format!("token {}", args.next().unwrap_or_default()),
+        Some(command) => command.to_string(),
+        None => String::new()
+    };
+    match command.as_str() {
+        "token add" => {
+            let name = args.next().ok_or("missing token name")?;
+            let mut scope = rest_api::Scope::default();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--all" => scope.all = true,
+                    "--entry" => scope.entries.push(args.next().ok_or("--entry expects an entry name")?),
+                    "--tag" => scope.tags.push(args.next().ok_or("--tag expects a tag")?),
+                    "--write" => scope.write = true,
+                    _ => return Err(format!("unexpected argument '{arg}'").into())
+                }
+            }
+            if !scope.all && scope.entries.is_empty() && scope.tags.is_empty() {
+                return Err("a token needs --all, --entry or --tag".into());
+            }
+            let mut tokens = rest_api::load_tokens(&tokens_path)?;
+            if tokens.iter().any(|token| token.name == name) {
+                return Err(format!("token '{name}' already exists").into());
+            }
+            let (token, secret) = rest_api::Token::generate(&name, scope);
+            tokens.push(token);
+            rest_api::save_tokens(&tokens_path, &tokens)?;
+            println!("{secret}");
+        }
+        "token list" => {
+            for token in rest_api::load_tokens(&tokens_path)? {
+                let scope = &token.scope;
+                let mut limits: Vec<String> = scope.entries.iter().map(|entry| format!("entry:{entry}")).collect();
+                limits.extend(scope.tags.iter().map(|tag| format!("tag:{tag}")));
+                if scope.all { limits = vec!["all".to_string()]; }
+                println!("{}\t{}\t{}", token.name, if scope.write {"read-write"} else {"read-only"}, limits.join(" "));
+            }
+        }
+        "token remove" => {
+            let name = args.next().ok_or("missing token name")?;
+            let mut tokens = rest_api::load_tokens(&tokens_path)?;
+            let count = tokens.len();
+            tokens.retain(|token| token.name != name);
+            if tokens.len() == count {
+                return Err(format!("no token named '{name}'").into());
+            }
+            rest_api::save_tokens(&tokens_path, &tokens)?;
+        }
+        "serve" => {
+            let (mut port, mut audit) = (rest_api::DEFAULT_PORT, rest_api::audit_path(&ctx.vault));
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--port" => port = args.next().ok_or("--port expects a number")?.parse().map_err(|_| "invalid port")?,
+                    "--audit" => audit = PathBuf::from(args.next().ok_or("--audit expects a file")?),
+                    _ => return Err(format!("unexpected argument '{arg}'").into())
+                }
+            }
+            let tokens = rest_api::load_tokens(&tokens_path)?;
+            if tokens.is_empty() {
+                return Err("no API tokens, create one with 'passtool api token add'".into());
+            }
+            let audit = rest_api::open_audit(audit)?;
+            let api = rest_api::Api::new(ctx.vault.clone(), ctx.key()?, tokens, Box::new(audit));
+            rest_api::serve(api, port, |port| {
+                println!("listening on http://127.0.0.1:{port}");
+                let _ = io::stdout().flush();
+            }).map_err(|e| e.to_string())?;
+        }
+        _ => return Err("expected 'api token add|list|remove' or 'api serve'".into())
+    }
+    Ok(0)
+}
+
+fn askpass_command(ctx: &Context, args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let prompt = args.collect::<Vec<_>>().join(" ");
+    let answer = askpass::run(&ctx.vault, &prompt, &mut || ctx.key())?;
+    if let askpass::Answer::MissingEntry(entry) = &answer {
+        eprintln!("passtool: entry '{entry}' not found");
+    }
+    println!("{}", answer.or_prompt(&prompt)?);
+    Ok(0)
+}
+
+fn tui_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let app = match args.next().as_deref() {
+        Some("--app") => Some(args.next().ok_or("--app expects an app")?),
+        Some(arg) => return Err(format!("unexpected argument '{arg}'").into()),
+        None => None
+    };
+    tui::run(ctx.vault.clone(), ctx.vault_key()?, app)?;
+    Ok(0)
+}
+
+fn convert_command(ctx: &Context, mut args: IntoIter<String>) -> Result<i32, Box<dyn Error>> {
+    let dest = args.next().ok_or("convert expects a destination vault")?;
+    let count = store::copy(store::open(&ctx.vault, &mut || ctx.key())?.as_ref(), store::create(dest, &mut || ctx.key())?.as_mut())?;
+    eprintln!("copied {count} entries");
+    Ok(0)
+}
+
+pub fn run(args: Vec<String>) -> i32 {
+    let mut args = args.into_iter();
+    let mut ctx = Context{vault: env::var_os("PASSTOOL_VAULT").map_or_else(passtool::default_path, PathBuf::from), key: RefCell::new(None)};
+    let result = loop {
+        let Some(arg) = args.next() else {
+            eprintln!("{USAGE}");
+            return 2;
+        };
+        match arg.as_str() {
+            "--vault" => match args.next() {
+                Some(vault) => ctx.vault = PathBuf::from(vault),
+                None => break Err("--vault expects a file".into())
+            },
+            "run" => break run_command(&ctx, args),
+            "inject" => break inject_command(&ctx, args),
+            "git-credential" => break git_credential_command(&ctx, args),
+            "add-ssh-key" => break add_ssh_key_command(&ctx, args),
+            "ssh-agent" => break ssh_agent_command(&ctx, args),
+            "get" => break get_command(&ctx, args),
+            "list" => break list_command(&ctx, args),
+            "search" => break search_command(&ctx, args),
+            "move" => break move_command(&ctx, args),
+            "tag" => break tag_command(&ctx, args, true),
+            "untag" => break tag_command(&ctx, args, false),
+            "app" => break app_command(&ctx, args),
+            "rename" => break rename_command(&ctx, args),
+            "expire" => break expire_command(&ctx, args),
+            "expiring" => break expiring_command(&ctx, args),
+            "trash" => break trash_command(&ctx, args),
+            "agent" => break agent_command(&ctx, args),
+            "lock" => break lock_command(&ctx, args),
+            "secret-service" => break secret_service_command(&ctx, args),
+            "api" => break api_command(&ctx, args),
+            "askpass" => break askpass_command(&ctx, args),
+            "tui" => break tui_command(&ctx, args),
+            "convert" => break convert_command(&ctx, args),
+            "help" | "--help" | "-h" => {
+                println!("{USAGE}");
+                return 0;
+            }
+            _ => break Err(format!("unknown command '{arg}'").into())
+        }
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("passtool: {e}");
+            1
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! Clipboard access with automatic clearing: a copied secret is removed after a timeout
+//! unless something else has been copied in the meantime.
+use std::{env, error::Error, io::{self, Write}, process::{Command, Stdio}, sync::{Arc, Condvar, Mutex}, thread::{self, JoinHandle}, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub trait Clipboard: Send {
+    fn set(&mut self, text: &str) -> Result<(), Box<dyn Error>>;
+    /// Current text, `None` if the clipboard holds none.
+    fn get(&mut self) -> Result<Option<String>, Box<dyn Error>>;
+    fn clear(&mut self) -> Result<(), Box<dyn Error>>;
+}
+
+/// Process-local clipboard, clones share the contents.
+#[derive(Default, Clone, Debug)]
+pub struct Memory(pub Arc<Mutex<Option<String>>>);
+
+impl Clipboard for Memory {
+    fn set(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        *self.0.lock().unwrap() = Some(text.to_string());
+        Ok(())
+    }
+
+    fn get(&mut self) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(self.0.lock().unwrap().clone())
+    }
+
+    fn clear(&mut self) -> Result<(), Box<dyn Error>> {
+        *self.0.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+/// External program pair reading the text from stdin and printing it to stdout.
+#[derive(Clone, Copy, Debug)]
+pub struct Tool {
+    pub copy: &'static [&'static str],
+    pub paste: &'static [&'static str],
+    /// Setting the empty text is used without one.
+    pub clear: Option<&'static [&'static str]>
+}
+
+pub const WL_CLIPBOARD: Tool = Tool{copy: &["wl-copy"], paste: &["wl-paste", "--no-newline"], clear: Some(&["wl-copy", "--clear"])};
+pub const XCLIP: Tool = Tool{copy: &["xclip", "-selection", "clipboard", "-in"], paste: &["xclip", "-selection", "clipboard", "-out"], clear: None};
+pub const XSEL: Tool = Tool{copy: &["xsel", "--clipboard", "--input"], paste: &["xsel", "--clipboard", "--output"], clear: Some(&["xsel", "--clipboard", "--clear"])};
+
+impl Tool {
+    fn command(args: &[&str]) -> Command {
+        let mut command = Command::new(args[0]);
+        command.args(&args[1..]);
+        command
+    }
+
+    /// Whether the program is on `$PATH`.
+    pub fn available(&self) -> bool {
+        env::var_os("PATH").is_some_and(|path| env::split_paths(&path).any(|dir| dir.join(self.copy[0]).is_file()))
+    }
+}
+
+impl Clipboard for Tool {
+    fn set(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        let mut child = Tool::command(self.copy).stdin(Stdio::piped()).stdout(Stdio::null()).spawn()?;
+        child.stdin.take().unwrap().write_all(text.as_bytes())?;
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(format!("{} failed with {status}", self.copy[0]).into());
+        }
+        Ok(())
+    }
+
+    fn get(&mut self) -> Result<Option<String>, Box<dyn Error>> {
+        let output = Tool::command(self.paste).stdin(Stdio::null()).stderr(Stdio::null()).output()?;
+        // the tools fail on an empty clipboard or one without text
+        if !output.status.success() { return Ok(None); }
+        Ok(String::from_utf8(output.stdout).ok())
+    }
+
+    fn clear(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.clear {
+            Some(clear) => {
+                Tool::command(clear).stdin(Stdio::null()).status()?;
+                Ok(())
+            }
+            None => self.set("")
+        }
+    }
+}
+
+/// Clipboard of the terminal through the OSC 52 escape sequence, works over SSH.
+/// The terminal can't be read back, so the last text set here is taken as its contents.
+#[derive(Default, Debug)]
+pub struct Osc52 {
+    last: Option<String>
+}
+
+impl Osc52 {
+    fn write(data: &str) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "\x1b]52;c;{data}\x07")?;
+        stdout.flush()
+    }
+}
+
+impl Clipboard for Osc52 {
+    fn set(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        Osc52::write(&STANDARD.encode(text))?;
+        self.last = Some(text.to_string());
+        Ok(())
+    }
+
+    fn get(&mut self) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(self.last.clone())
+    }
+
+    fn clear(&mut self) -> Result<(), Box<dyn Error>> {
+        Osc52::write("")?;
+        self.last = None;
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+pub struct Windows;
+
+#[cfg(windows)]
+impl Windows {
+    /// Runs `f` with the clipboard opened by this thread.
+    fn with_clipboard<T>(f: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+        use winapi::um::winuser::{CloseClipboard, OpenClipboard};
+        unsafe {
+            if OpenClipboard(std::ptr::null_mut()) == 0 { return Err(io::Error::last_os_error()); }
+            let result = f();
+            CloseClipboard();
+            result
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Clipboard for Windows {
+    fn set(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        use winapi::um::{winbase::{GlobalAlloc, GlobalFree, GlobalLock, GlobalUnlock, GMEM_MOVEABLE}, winuser::{EmptyClipboard, SetClipboardData, CF_UNICODETEXT}};
+        let wide: Vec<u16> = text.encode_utf16().chain([0]).collect();
+        Ok(Windows::with_clipboard(|| unsafe {
+            EmptyClipboard();
+            let memory = GlobalAlloc(GMEM_MOVEABLE, wide.len() * 2);
+            if memory.is_null() { return Err(io::Error::last_os_error()); }
+            let data = GlobalLock(memory) as *mut u16;
+            if data.is_null() {
+                let error = io::Error::last_os_error();
+                GlobalFree(memory);
+                return Err(error);
+            }
+            std::ptr::copy_nonoverlapping(wide.as_ptr(), data, wide.len());
+            GlobalUnlock(memory);
+            // the clipboard owns the memory once it is set
+            if SetClipboardData(CF_UNICODETEXT, memory).is_null() {
+                let error = io::Error::last_os_error();
+                GlobalFree(memory);
+                return Err(error);
+            }
+            Ok(())
+        })?)
+    }
+
+    fn get(&mut self) -> Result<Option<String>, Box<dyn Error>> {
+        use winapi::um::{winbase::{GlobalLock, GlobalUnlock}, winuser::{GetClipboardData, CF_UNICODETEXT}};
+        Ok(Windows::with_clipboard(|| unsafe {
+            let memory = GetClipboardData(CF_UNICODETEXT);
+            if memory.is_null() { return Ok(None); }
+            let data = GlobalLock(memory) as *const u16;
+            if data.is_null() { return Ok(None); }
+            let len = (0..).take_while(|&i| *data.add(i) != 0).count();
+            let text = String::from_utf16_lossy(std::slice::from_raw_parts(data, len));
+            GlobalUnlock(memory);
+            Ok(Some(text))
+        })?)
+    }
+
+    fn clear(&mut self) -> Result<(), Box<dyn Error>> {
+        use winapi::um::winuser::EmptyClipboard;
+        Ok(Windows::with_clipboard(|| unsafe {
+            EmptyClipboard();
+            Ok(())
+        })?)
+    }
+}
+
+/// Clipboard of the desktop session.
+#[cfg(windows)]
+pub fn system() -> Option<Box<dyn Clipboard>> {
+    Some(Box::new(Windows))
+}
+
+/// Clipboard of the desktop session: wl-clipboard on Wayland, xclip or xsel on X11.
+#[cfg(not(windows))]
+pub fn system() -> Option<Box<dyn Clipboard>> {
+    let mut tools = Vec::new();
+    if env::var_os("WAYLAND_DISPLAY").is_some() { tools.push(WL_CLIPBOARD); }
+    if env::var_os("DISPLAY").is_some() { tools.extend([XCLIP, XSEL]); }
+    tools.into_iter().find(Tool::available).map(|tool| Box::new(tool) as Box<dyn Clipboard>)
+}
+
+/// `$PASSTOOL_CLIPBOARD_TIMEOUT` seconds, 0 keeps copied text, `DEFAULT_TIMEOUT` if unset.
+pub fn timeout_from_env() -> Option<Duration> {
+    match env::var("PASSTOOL_CLIPBOARD_TIMEOUT").ok().and_then(|secs| secs.parse::<u64>().ok()) {
+        Some(0) => None,
+        Some(secs) => Some(Duration::from_secs(secs)),
+        None => Some(DEFAULT_TIMEOUT)
+    }
+}
+
+pub struct ClipboardService {
+    clipboard: Arc<Mutex<Box<dyn Clipboard>>>,
+    timeout: Option<Duration>,
+    /// Set on shutdown, wakes the pending timers.
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    timers: Mutex<Vec<JoinHandle<()>>>
+}
+
+impl ClipboardService {
+    pub fn new(clipboard: Box<dyn Clipboard>, timeout: Option<Duration>) -> Self {
+        ClipboardService{clipboard: Arc::new(Mutex::new(clipboard)), timeout, stopped: Default::default(), timers: Default::default()}
+    }
+
+    /// Copies `text` and starts the timer clearing it, returns whether there is one.
+    pub fn copy(&self, text: &str) -> Result<bool, Box<dyn Error>> {
+        self.clipboard.lock().unwrap().set(text)?;
+        let Some(timeout) = self.timeout else { return Ok(false) };
+        // only the digest is kept to recognize the text
+        let digest = Sha256::digest(text);
+        let clipboard = self.clipboard.clone();
+        let stopped = self.stopped.clone();
+        let mut timers = self.timers.lock().unwrap();
+        timers.retain(|timer| !timer.is_finished());
+        timers.push(thread::spawn(move || {
+            let (lock, wakeup) = &*stopped;
+            drop(wakeup.wait_timeout_while(lock.lock().unwrap(), timeout, |stopped| !*stopped).unwrap());
+            let mut clipboard = clipboard.lock().unwrap();
+            if let Ok(Some(current)) = clipboard.get() {
+                if Sha256::digest(current) == digest {
+                    let _ = clipboard.clear();
+                }
+            }
+        }));
+        Ok(true)
+    }
+
+    /// Runs the pending timers now, clearing text copied here that is still on the clipboard.
+    pub fn shutdown(&self) {
+        let (lock, wakeup) = &*self.stopped;
+        *lock.lock().unwrap() = true;
+        wakeup.notify_all();
+        for timer in self.timers.lock().unwrap().drain(..) {
+            let _ = timer.join();
+        }
+        *lock.lock().unwrap() = false;
+    }
+}
+
+impl Drop for ClipboardService {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl Default for ClipboardService {
+    /// The system clipboard, the terminal's if there is none, cleared after `timeout_from_env`.
+    fn default() -> Self {
+        ClipboardService::new(system().unwrap_or_else(|| Box::new(Osc52::default())), timeout_from_env())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wait_cleared(memory: &Memory) -> bool {
+        (0..200).any(|_| {
+            thread::sleep(Duration::from_millis(5));
+            memory.0.lock().unwrap().is_none()
+        })
+    }
+
+    #[test]
+    fn auto_clear_test() -> Result<(), Box<dyn Error>> {
+        let memory = Memory::default();
+        let service = ClipboardService::new(Box::new(memory.clone()), Some(Duration::from_millis(10)));
+        assert!(service.copy("secret")?);
+        assert!(wait_cleared(&memory));
+
+        // something copied meanwhile is left alone
+        let service = ClipboardService::new(Box::new(memory.clone()), Some(Duration::from_secs(60)));
+        service.copy("secret")?;
+        *memory.0.lock().unwrap() = Some("mine".to_string());
+        service.shutdown();
+        assert_eq!(memory.0.lock().unwrap().as_deref(), Some("mine"));
+
+        let service = ClipboardService::new(Box::new(memory.clone()), None);
+        assert!(!service.copy("kept")?);
+        drop(service);
+        assert_eq!(memory.0.lock().unwrap().as_deref(), Some("kept"));
+        Ok(())
+    }
+
+    #[test]
+    fn shutdown_test() -> Result<(), Box<dyn Error>> {
+        let memory = Memory::default();
+        let service = ClipboardService::new(Box::new(memory.clone()), Some(Duration::from_secs(60)));
+        service.copy("first")?;
+        service.copy("second")?;
+        // dropping before the timeout still clears what is ours
+        drop(service);
+        assert_eq!(*memory.0.lock().unwrap(), None);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tool_test() -> Result<(), Box<dyn Error>> {
+        // the shell's parent is this test process
+        let mut tool = Tool{copy: &["sh", "-c", "cat > \"${TMPDIR:-/tmp}/passtool-clipboard-test-$PPID\""], paste: &["sh", "-c", "cat \"${TMPDIR:-/tmp}/passtool-clipboard-test-$PPID\""], clear: None};
+        assert!(tool.available());
+        tool.set("secret\n")?;
+        assert_eq!(tool.get()?.as_deref(), Some("secret\n"));
+        tool.clear()?;
+        assert_eq!(tool.get()?.as_deref(), Some(""));
+        std::fs::remove_file(env::temp_dir().join(format!("passtool-clipboard-test-{}", std::process::id())))?;
+        assert_eq!(tool.get()?, None);
+        assert!(!Tool{copy: &["passtool-no-such-tool"], ..tool}.available());
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+//! docker-credential-helpers protocol: `store`, `get`, `erase` and `list` over stdin/stdout.
+//! Registry credentials are entries named after the server URL and tagged `docker`,
+//! `URL (docker)` if an entry of another kind already has that name.
+use core::fmt;
+use std::{collections::BTreeMap, io::{Read, Write}};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{PassTable, PasswordMeta};
+
+pub const TAG: &str = "docker";
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    MissingUrl,
+    UnknownAction(String),
+    Input(String),
+    Vault(Box<dyn std::error::Error>)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // docker checks for this exact message
+            Self::NotFound => f.write_str("credentials not found in native keychain"),
+            Self::MissingUrl => f.write_str("no credentials server URL"),
+            Self::UnknownAction(action) => write!(f, "unknown action: {action}"),
+            Self::Input(e) => write!(f, "invalid input: {e}"),
+            Self::Vault(e) => write!(f, "{e}")
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<crate::Error> for Error {
+    fn from(e: crate::Error) -> Self {
+        Error::Vault(Box::new(e))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct Credentials {
+    #[serde(rename = "ServerURL")]
+    pub server_url: String,
+    pub username: String,
+    pub secret: String
+}
+
+fn find(table: &PassTable, server_url: &str) -> Option<String> {
+    table.get_names_with_tag(TAG).into_iter().find(|name| table.get_metadata(name).unwrap().url == server_url)
+}
+
+/// Name for a new entry of `server_url` that no other entry has.
+fn free_name(table: &PassTable, server_url: &str) -> String {
+    (1..).map(|n| match n {
+        1 => server_url.to_string(),
+        2 => format!("{server_url} ({TAG})"),
+        n => format!("{server_url} ({TAG} {n})")
+    }).find(|name| !table.contains(name)).unwrap()
+}
+
+fn read_url<R: Read>(mut input: R) -> Result<String, Error> {
+    let mut url = String::new();
+    input.read_to_string(&mut url).map_err(|e| Error::Input(e.to_string()))?;
+    let url = url.trim();
+    if url.is_empty() { return Err(Error::MissingUrl); }
+    Ok(url.to_string())
+}
+
+/// Handles one helper invocation, `key` is only asked for when a secret is decrypted or encrypted.
+/// Returns whether the table was changed and has to be saved, getting a secret records the access.
+pub fn handle<R: Read, W: Write>(action: &str, input: R, mut output: W, table: &mut PassTable, key: &mut dyn FnMut() -> Result<String, Box<dyn std::error::Error>>) -> Result<bool, Error> {
+    match action {
+        "store" => {
+            let credentials: Credentials = serde_json::from_reader(input).map_err(|e| Error::Input(e.to_string()))?;
+            if credentials.server_url.is_empty() { return Err(Error::MissingUrl); }
+            let key = key().map_err(Error::Vault)?;
+            match find(table, &credentials.server_url) {
+                Some(name) => {
+                    // every docker login stores the credentials again
+                    let changed = table.get_password(&name, &key)? != credentials.secret;
+                    if changed {
+                        table.update_password(&name, &credentials.secret, &key)?;
+                    }
+                    if table.get_metadata(&name)?.username == credentials.username { return Ok(changed); }
+                    table.get_metadata_mut(&name)?.username = credentials.username;
+                }
+                None => {
+                    let meta = PasswordMeta {
+                        description: "Docker registry".to_string(),
+                        username: credentials.username,
+                        url: credentials.server_url.clone(),
+                        tags: vec![TAG.to_string()],
+                        ..Default::default()
+                    };
+                    table.add_password(&free_name(table, &credentials.server_url), &credentials.secret, meta, &key)?;
+                }
+            }
+            Ok(true)
+        }
+        "get" => {
+            let server_url = read_url(input)?;
+            let name = find(table, &server_url).ok_or(Error::NotFound)?;
+            let secret = table.access_password(&name, &key().map_err(Error::Vault)?)?;
+            let username = table.get_metadata(&name)?.username.clone();
+            serde_json::to_writer(&mut output, &Credentials{server_url, username, secret}).map_err(|e| Error::Vault(Box::new(e)))?;
+            Ok(true)
+        }
+        "erase" => {
+            let name = find(table, &read_url(input)?).ok_or(Error::NotFound)?;
+            table.remove_password(&name)?;
+            Ok(true)
+        }
+        "list" => {
+            let list: BTreeMap<String, String> = table.get_names_with_tag(TAG).iter()
+                .map(|name| table.get_metadata(name).unwrap())
+                .map(|meta| (meta.url.clone(), meta.username.clone()))
+                .collect();
+            serde_json::to_writer(&mut output, &list).map_err(|e| Error::Vault(Box::new(e)))?;
+            Ok(false)
+        }
+        _ => Err(Error::UnknownAction(action.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(action: &str, input: &str, table: &mut PassTable) -> Result<String, Error> {
+        let mut output = Vec::new();
+        handle(action, input.as_bytes(), &mut output, table, &mut || Ok("key".to_string()))?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn protocol_test() -> Result<(), Error> {
+        let mut pt = PassTable::new();
+        pt.add_password("unrelated", "x", PasswordMeta{url: "https://index.docker.io/v1/".to_string(), ..Default::default()}, "key")?;
+        assert!(matches!(call("get", "https://index.docker.io/v1/\n", &mut pt), Err(Error::NotFound)));
+
+        call("store", r#"{"ServerURL":"https://index.docker.io/v1/","Username":"alice","Secret":"t0ken"}"#, &mut pt)?;
+        call("store", r#"{"ServerURL":"ghcr.io","Username":"bob","Secret":"ghp"}"#, &mut pt)?;
+        assert_eq!(call("get", "https://index.docker.io/v1/\n", &mut pt)?, r#"{"ServerURL":"https://index.docker.io/v1/","Username":"alice","Secret":"t0ken"}"#);
+        assert_eq!(call("list", "", &mut pt)?, r#"{"ghcr.io":"bob","https://index.docker.io/v1/":"alice"}"#);
+
+        call("store", r#"{"ServerURL":"ghcr.io","Username":"carol","Secret":"new"}"#, &mut pt)?;
+        assert_eq!(call("get", "ghcr.io", &mut pt)?, r#"{"ServerURL":"ghcr.io","Username":"carol","Secret":"new"}"#);
+        assert!(pt.get_times("ghcr.io")?.accessed > 0);
+        // storing the same credentials again changes nothing
+        let unchanged = handle("store", r#"{"ServerURL":"ghcr.io","Username":"carol","Secret":"new"}"#.as_bytes(), Vec::new(), &mut pt, &mut || Ok("key".to_string()))?;
+        assert!(!unchanged);
+        assert_eq!(pt.get_versions("ghcr.io")?.len(), 1);
+
+        // an entry of another kind keeps its name
+        pt.add_password("registry.example.com", "x", PasswordMeta::default(), "key")?;
+        call("store", r#"{"ServerURL":"registry.example.com","Username":"dave","Secret":"pw"}"#, &mut pt)?;
+        assert_eq!(pt.get_password("registry.example.com (docker)", "key")?, "pw");
+        assert_eq!(call("get", "registry.example.com", &mut pt)?, r#"{"ServerURL":"registry.example.com","Username":"dave","Secret":"pw"}"#);
+
+        call("erase", "ghcr.io\n", &mut pt)?;
+        assert!(matches!(call("erase", "ghcr.io\n", &mut pt), Err(Error::NotFound)));
+        assert!(pt.contains("unrelated"));
+        assert!(matches!(call("get", "\n", &mut pt), Err(Error::MissingUrl)));
+        assert!(matches!(call("store", "{}", &mut pt), Err(Error::Input(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+use core::fmt;
+use std::{fs, path::Path};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::{PassTable, PasswordMeta};
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    /// Line (1-based) is neither a comment nor a `KEY=value` pair.
+    InvalidLine(usize),
+    InvalidKey(String),
+    /// Line (1-based) where the unterminated quoted value starts.
+    UnterminatedQuote(usize),
+    /// Kubernetes object name that is not a DNS-1123 subdomain.
+    InvalidSecretName(String)
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidLine(line) => write!(f, "line {line}: expected KEY=value"),
+            Self::InvalidKey(key) => write!(f, "invalid variable name '{key}'"),
+            Self::UnterminatedQuote(line) => write!(f, "line {line}: unterminated quoted value"),
+            Self::InvalidSecretName(name) => write!(f, "invalid secret name '{name}': use lowercase letters, digits, '-' and '.'")
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') &&
+        chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-')
+}
+
+/// Splits `raw` at the closing `quote`, unescaping the value for double quotes.
+/// Returns `None` if the closing quote is not there yet.
+fn unquote(raw: &str, quote: char) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = raw.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Some((value, &raw[i + 1..])),
+            '\\' if quote == '"' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                c @ ('"' | '\\' | '$') => value.push(c),
+                c => { value.push('\\'); value.push(c); }
+            },
+            c => value.push(c)
+        }
+    }
+    None
+}
+
+/// Parses `KEY=value` lines: `export` prefixes, `#` comments, single-quoted literals and
+/// double-quoted values with escapes are supported, quoted values may span several lines.
+pub fn parse(contents: &str) -> Result<Vec<(String, String)>, ParseError> {
+    let mut vars = Vec::new();
+    let mut lines = contents.lines().enumerate();
+    while let Some((n, line)) = lines.next() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        let line = line.strip_prefix("export")
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+            .map_or(line, str::trim_start);
+
+        let (key, value) = line.split_once('=').ok_or(ParseError::InvalidLine(n + 1))?;
+        let key = key.trim_end();
+        if !is_valid_key(key) { return Err(ParseError::InvalidKey(key.to_string())); }
+
+        let value = value.trim_start();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let mut raw = value[1..].to_string();
+                loop {
+                    if let Some((value, rest)) = unquote(&raw, quote) {
+                        let rest = rest.trim_start();
+                        if !rest.is_empty() && !rest.starts_with('#') { return Err(ParseError::InvalidLine(n + 1)); }
+                        break value;
+                    }
+                    let (_, next) = lines.next().ok_or(ParseError::UnterminatedQuote(n + 1))?;
+                    raw.push('\n');
+                    raw.push_str(next);
+                }
+            }
+            _ => {
+                let end = value.char_indices()
+                    .find(|&(i, c)| c == '#' && value[..i].ends_with(char::is_whitespace))
+                    .map_or(value.len(), |(i, _)| i);
+                value[..end].trim_end().to_string()
+            }
+        };
+        vars.push((key.to_string(), value));
+    }
+    Ok(vars)
+}
+
+/// Adds every variable as an entry named after it and tagged with `tag`, the last value of a repeated variable wins.
+/// Nothing is added if any of the entries already exists.
+pub fn import(table: &mut PassTable, contents: &str, tag: &str, key: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut vars: Vec<(String, String)> = Vec::new();
+    for (name, value) in parse(contents)? {
+        match vars.iter_mut().find(|(n, _)| *n == name) {
+            Some(var) => var.1 = value,
+            None => vars.push((name, value))
+        }
+    }
+    if vars.iter().any(|(name, _)| table.contains(name)) {
+        return Err(Box::new(crate::PassExists));
+    }
+
+    let mut names = Vec::new();
+    for (name, value) in vars {
+        let meta = PasswordMeta {
+            description: format!("Imported from {tag}"),
+            tags: vec![tag.to_string()],
+            ..Default::default()
+        };
+        table.add_password(&name, &value, meta, key)?;
+        names.push(name);
+    }
+    Ok(names)
+}
+
+/// Same as `import`, the file name is used as the tag.
+pub fn import_file<P: AsRef<Path>>(table: &mut PassTable, filename: P, key: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let tag = filename.as_ref().file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    import(table, &fs::read_to_string(filename)?, &tag, key)
+}
+
+fn quote(value: &str) -> String {
+    if !value.contains(['\'', '\n', '\r']) {
+        return format!("'{value}'");
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '"' | '\\' | '$' => { quoted.push('\\'); quoted.push(c); }
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn decrypt_all(table: &PassTable, names: &[String], key: &str) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    names.iter().map(|name| {
+        if !is_valid_key(name) { return Err(ParseError::InvalidKey(name.clone()).into()); }
+        Ok((name.clone(), table.get_password(name, key)?))
+    }).collect()
+}
+
+/// Renders the given entries as a `.env` file, entry names are used as variable names.
+pub fn export_env(table: &PassTable, names: &[String], key: &str) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(decrypt_all(table, names, key)?.into_iter()
+        .map(|(name, value)| format!("{name}={}\n", quote(&value)))
+        .collect())
+}
+
+/// Whether `name` is a DNS-1123 subdomain, as Kubernetes requires for Secret names.
+pub fn is_valid_secret_name(name: &str) -> bool {
+    name.len() <= 253 && name.split('.').all(|label| {
+        !label.is_empty() && !label.starts_with('-') && !label.ends_with('-') &&
+            label.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    })
+}
+
+/// Renders the given entries as an `Opaque` Kubernetes Secret manifest named `secret_name`.
+pub fn export_yaml(table: &PassTable, names: &[String], key: &str, secret_name: &str) -> Result<String, Box<dyn std::error::Error>> {
+    if !is_valid_secret_name(secret_name) { return Err(ParseError::InvalidSecretName(secret_name.to_string()).into()); }
+    let mut yaml = format!("apiVersion: v1\nkind: Secret\nmetadata:\n  name: {secret_name}\ntype: Opaque\ndata:\n");
+    for (name, value) in decrypt_all(table, names, key)? {
+        let data = STANDARD.encode(value);
+        yaml.push_str(&format!("  {name}: {}\n", if data.is_empty() {"\"\""} else {&data}));
+    }
+    Ok(yaml)
+}
+
+pub fn export_env_file<P: AsRef<Path>>(table: &PassTable, names: &[String], key: &str, filename: P) -> Result<(), Box<dyn std::error::Error>> {
+    crate::write_private(filename, export_env(table, names, key)?.as_bytes())?;
+    Ok(())
+}
+
+pub fn export_yaml_file<P: AsRef<Path>>(table: &PassTable, names: &[String], key: &str, secret_name: &str, filename: P) -> Result<(), Box<dyn std::error::Error>> {
+    crate::write_private(filename, export_yaml(table, names, key, secret_name)?.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENV: &str = r#"# database
+export DB_HOST=db.internal # inline comment
+DB_PASS="p@ss \"word\"\n$HOME"
+TOKEN = 'literal \n #not a comment'
+EMPTY=
+URL=http://example.com/#anchor
+CERT="-----BEGIN-----
+abc
+-----END-----"
+"#;
+
+    #[test]
+    fn parse_test() -> Result<(), ParseError> {
+        let vars = parse(ENV)?;
+        assert_eq!(vars, vec![
+            ("DB_HOST".to_string(), "db.internal".to_string()),
+            ("DB_PASS".to_string(), "p@ss \"word\"\n$HOME".to_string()),
+            ("TOKEN".to_string(), "literal \\n #not a comment".to_string()),
+            ("EMPTY".to_string(), "".to_string()),
+            ("URL".to_string(), "http://example.com/#anchor".to_string()),
+            ("CERT".to_string(), "-----BEGIN-----\nabc\n-----END-----".to_string()),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_error_test() {
+        assert_eq!(parse("A=1\nNOT A PAIR"), Err(ParseError::InvalidLine(2)));
+        assert_eq!(parse("1A=1"), Err(ParseError::InvalidKey("1A".to_string())));
+        assert_eq!(parse("A=1\nB=\"open\n"), Err(ParseError::UnterminatedQuote(2)));
+        assert_eq!(parse("A='x' trailing"), Err(ParseError::InvalidLine(1)));
+    }
+
+    #[test]
+    fn import_export_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pt = PassTable::new();
+        let names = import(&mut pt, ENV, ".env.production", "key")?;
+        assert_eq!(names.len(), 6);
+        assert_eq!(pt.get_metadata("DB_PASS")?.tags, vec![".env.production".to_string()]);
+        assert_eq!(pt.get_names_with_tag(".env.production"), {let mut n = names.clone(); n.sort(); n});
+        assert!(import(&mut pt, "DB_HOST=x", "other", "key").is_err());
+        assert_eq!(import(&mut pt, "A=1\nB=2\nA=3", "other", "key")?, ["A", "B"]);
+        assert_eq!(pt.get_password("A", "key")?, "3");
+
+        let env = export_env(&pt, &names, "key")?;
+        assert_eq!(parse(&env)?, parse(ENV)?);
+
+        let yaml = export_yaml(&pt, &["DB_HOST".to_string(), "EMPTY".to_string()], "key", "db-credentials")?;
+        assert_eq!(yaml, "apiVersion: v1\nkind: Secret\nmetadata:\n  name: db-credentials\ntype: Opaque\ndata:\n  DB_HOST: ZGIuaW50ZXJuYWw=\n  EMPTY: \"\"\n");
+        for invalid in ["", "DB", "db creds", "x\nkind: Pod", "-db", "db..x"] {
+            assert!(export_yaml(&pt, &[], "key", invalid).is_err());
+        }
+        assert!(export_yaml(&pt, &[], "key", "db.credentials-1").is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn export_invalid_name_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pt = PassTable::new();
+        pt.add_password("my password", "x", PasswordMeta::default(), "key")?;
+        assert!(export_env(&pt, &["my password".to_string()], "key").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+//! Fingerprints binding app rules to the executables they were set up for, so that another binary
+//! at a trusted path or a lookalike window is not offered an entry unnoticed.
+//! A fingerprint is the SHA-256 of the executable or, on Windows, the signer of its valid Authenticode signature.
+use std::{fmt, fs::File, io, path::Path};
+
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub enum Fingerprint {
+    Sha256([u8; 32]),
+    /// Subject name of the signing certificate.
+    Signer(String)
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fingerprint::Sha256(digest) => write!(f, "sha256:{}", digest.iter().map(|b| format!("{b:02x}")).collect::<String>()),
+            Fingerprint::Signer(signer) => write!(f, "signer:{signer}")
+        }
+    }
+}
+
+/// Whether the executable of a suggestion is the one its app rule was bound to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Verification {
+    /// The rule has no fingerprint.
+    Unbound,
+    Verified,
+    /// The executable is not the one the rule was bound to.
+    Mismatch,
+    /// The executable couldn't be read.
+    Unavailable
+}
+
+pub trait Fingerprinter {
+    fn sha256(&self, path: &Path) -> io::Result<[u8; 32]>;
+    /// Signer of the valid code signature, `None` if there is none or signatures aren't supported.
+    fn signer(&self, path: &Path) -> io::Result<Option<String>>;
+
+    /// Fingerprint of the executable at `path`, its signer if `signer` is set.
+    fn fingerprint(&self, path: &Path, signer: bool) -> io::Result<Fingerprint> {
+        if !signer {
+            return Ok(Fingerprint::Sha256(self.sha256(path)?));
+        }
+        match self.signer(path)? {
+            Some(signer) => Ok(Fingerprint::Signer(signer)),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} has no valid signature", path.display())))
+        }
+    }
+
+    fn verify(&self, fingerprint: &Fingerprint, path: &Path) -> Verification {
+        let matches = match fingerprint {
+            Fingerprint::Sha256(digest) => self.sha256(path).map(|actual| actual == *digest),
+            Fingerprint::Signer(signer) => self.signer(path).map(|actual| actual.as_ref() == Some(signer))
+        };
+        match matches {
+            Ok(true) => Verification::Verified,
+            Ok(false) => Verification::Mismatch,
+            Err(_) => Verification::Unavailable
+        }
+    }
+}
+
+/// Reads the executables from disk.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct Files;
+
+impl Fingerprinter for Files {
+    fn sha256(&self, path: &Path) -> io::Result<[u8; 32]> {
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+        Ok(hasher.finalize().into())
+    }
+
+    #[cfg(windows)]
+    fn signer(&self, path: &Path) -> io::Result<Option<String>> {
+        authenticode::signer(path)
+    }
+
+    #[cfg(not(windows))]
+    fn signer(&self, _path: &Path) -> io::Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+#[cfg(windows)]
+mod authenticode {
+    use std::{io, mem::size_of, os::windows::ffi::OsStrExt, path::Path, ptr};
+
+    use winapi::um::{
+        softpub::WINTRUST_ACTION_GENERIC_VERIFY_V2,
+        wincrypt::{
+            CertCloseStore, CertFreeCertificateContext, CertGetNameStringW, CertGetSubjectCertificateFromStore, CryptMsgClose, CryptMsgGetParam, CryptQueryObject,
+            CERT_NAME_SIMPLE_DISPLAY_TYPE, CERT_QUERY_CONTENT_FLAG_PKCS7_SIGNED_EMBED, CERT_QUERY_FORMAT_FLAG_BINARY, CERT_QUERY_OBJECT_FILE,
+            CMSG_SIGNER_CERT_INFO_PARAM, HCERTSTORE, HCRYPTMSG, PCERT_INFO
+        },
+        wintrust::{WinVerifyTrust, WINTRUST_DATA, WINTRUST_FILE_INFO, WTD_CHOICE_FILE, WTD_REVOKE_NONE, WTD_STATEACTION_CLOSE, WTD_STATEACTION_VERIFY, WTD_UI_NONE}
+    };
+
+    /// Whether the embedded signature of the file is valid and chains to a trusted root.
+    unsafe fn trusted(path: &[u16]) -> bool {
+        let mut file = WINTRUST_FILE_INFO{cbStruct: size_of::<WINTRUST_FILE_INFO>() as u32, pcwszFilePath: path.as_ptr(), hFile: ptr::null_mut(), pgKnownSubject: ptr::null()};
+        let mut data: WINTRUST_DATA = std::mem::zeroed();
+        data.cbStruct = size_of::<WINTRUST_DATA>() as u32;
+        data.dwUIChoice = WTD_UI_NONE;
+        data.fdwRevocationChecks = WTD_REVOKE_NONE;
+        data.dwUnionChoice = WTD_CHOICE_FILE;
+        *data.u.pFile_mut() = &mut file;
+        data.dwStateAction = WTD_STATEACTION_VERIFY;
+        let mut action = WINTRUST_ACTION_GENERIC_VERIFY_V2;
+        let status = WinVerifyTrust(ptr::null_mut(), &mut action, &mut data as *mut WINTRUST_DATA as _);
+        // releases the state kept by the verification
+        data.dwStateAction = WTD_STATEACTION_CLOSE;
+        WinVerifyTrust(ptr::null_mut(), &mut action, &mut data as *mut WINTRUST_DATA as _);
+        status == 0
+    }
+
+    unsafe fn subject(store: HCERTSTORE, msg: HCRYPTMSG, encoding: u32) -> io::Result<String> {
+        let mut len = 0;
+        if CryptMsgGetParam(msg, CMSG_SIGNER_CERT_INFO_PARAM, 0, ptr::null_mut(), &mut len) == 0 { return Err(io::Error::last_os_error()); }
+        // u64 keeps the CERT_INFO aligned
+        let mut info = vec![0u64; (len as usize).div_ceil(8)];
+        if CryptMsgGetParam(msg, CMSG_SIGNER_CERT_INFO_PARAM, 0, info.as_mut_ptr() as _, &mut len) == 0 { return Err(io::Error::last_os_error()); }
+        let cert = CertGetSubjectCertificateFromStore(store, encoding, info.as_mut_ptr() as PCERT_INFO);
+        if cert.is_null() { return Err(io::Error::last_os_error()); }
+        let mut name = [0u16; 256];
+        let len = CertGetNameStringW(cert, CERT_NAME_SIMPLE_DISPLAY_TYPE, 0, ptr::null_mut(), name.as_mut_ptr(), name.len() as u32);
+        CertFreeCertificateContext(cert);
+        // the length counts the terminating zero
+        Ok(String::from_utf16_lossy(&name[..(len as usize).saturating_sub(1)]))
+    }
+
+    pub fn signer(path: &Path) -> io::Result<Option<String>> {
+        let path: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+        unsafe {
+            if !trusted(&path) { return Ok(None); }
+            let (mut encoding, mut content, mut format) = (0, 0, 0);
+            let (mut store, mut msg) = (ptr::null_mut(), ptr::null_mut());
+            if CryptQueryObject(CERT_QUERY_OBJECT_FILE, path.as_ptr() as _, CERT_QUERY_CONTENT_FLAG_PKCS7_SIGNED_EMBED, CERT_QUERY_FORMAT_FLAG_BINARY, 0,
+                &mut encoding, &mut content, &mut format, &mut store, &mut msg, ptr::null_mut()) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let subject = subject(store, msg, encoding);
+            CryptMsgClose(msg);
+            CertCloseStore(store, 0);
+            subject.map(Some)
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elf_test() -> Result<(), Box<dyn std::error::Error>> {
+        let exe = Path::new("/bin/sh");
+        assert_eq!(&std::fs::read(exe)?[..4], b"\x7fELF");
+        let fingerprint = Files.fingerprint(exe, false)?;
+        assert_eq!(Files.verify(&fingerprint, exe), Verification::Verified);
+        assert!(fingerprint.to_string().starts_with("sha256:"));
+
+        // another ELF file at the same path
+        let copy = std::env::temp_dir().join(format!("passtool-fingerprint-{}", std::process::id()));
+        let mut elf = std::fs::read(exe)?;
+        std::fs::write(&copy, &elf)?;
+        let bound = Files.fingerprint(&copy, false)?;
+        elf.push(0);
+        std::fs::write(&copy, &elf)?;
+        assert_eq!(Files.verify(&bound, &copy), Verification::Mismatch);
+        std::fs::remove_file(&copy)?;
+        assert_eq!(Files.verify(&bound, &copy), Verification::Unavailable);
+
+        // ELF files carry no Authenticode signature
+        assert!(Files.fingerprint(exe, true).is_err());
+        assert_eq!(Files.verify(&Fingerprint::Signer("Vendor".to_string()), exe), Verification::Mismatch);
+        Ok(())
+    }
+}
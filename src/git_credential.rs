@@ -0,0 +1,215 @@
+//! `git credential` helper protocol, see gitcredentials(7).
+//! Entries are matched through their URL field (`protocol://host[:port][/path]`) and username.
+use std::io::{BufRead, Write};
+
+use crate::{PassTable, PasswordMeta};
+
+/// Attributes git sends to and expects from a helper.
+#[derive(Debug, Default, PartialEq)]
+pub struct Credential {
+    pub protocol: String,
+    pub host: String,
+    pub path: String,
+    pub username: String,
+    pub password: String
+}
+
+impl Credential {
+    /// Reads `key=value` lines up to an empty line or the end of input, unknown keys are ignored.
+    pub fn read<R: BufRead>(input: R) -> std::io::Result<Self> {
+        let mut credential = Credential::default();
+        for line in input.lines() {
+            let line = line?;
+            if line.is_empty() { break; }
+            let Some((key, value)) = line.split_once('=') else { continue };
+            let value = value.to_string();
+            match key {
+                "protocol" => credential.protocol = value,
+                "host" => credential.host = value,
+                "path" => credential.path = value,
+                "username" => credential.username = value,
+                "password" => credential.password = value,
+                "url" => {
+                    let url = Credential::from_url(&value);
+                    credential = Credential{password: credential.password, ..url};
+                }
+                _ => {}
+            }
+        }
+        Ok(credential)
+    }
+
+    /// Splits `protocol://[username@]host[:port][/path]`.
+    pub fn from_url(url: &str) -> Self {
+        let (protocol, rest) = url.split_once("://").unwrap_or(("", url));
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (username, host) = authority.rsplit_once('@').unwrap_or(("", authority));
+        Credential {
+            protocol: protocol.to_string(),
+            host: host.to_string(),
+            path: path.trim_end_matches('/').to_string(),
+            username: username.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn url(&self) -> String {
+        let mut url = format!("{}://{}", self.protocol, self.host);
+        if !self.path.is_empty() {
+            url.push('/');
+            url.push_str(&self.path);
+        }
+        url
+    }
+
+    /// Whether an entry with `meta` can answer this request.
+    /// An entry bound to a path only answers requests for that path.
+    pub fn matches(&self, meta: &PasswordMeta) -> bool {
+        let entry = Credential::from_url(&meta.url);
+        let username = username(meta);
+        !entry.host.is_empty() &&
+            entry.protocol.eq_ignore_ascii_case(&self.protocol) &&
+            entry.host.eq_ignore_ascii_case(&self.host) &&
+            (entry.path.is_empty() || entry.path == self.path) &&
+            (self.username.is_empty() || username == self.username)
+    }
+}
+
+/// Username of an entry, the one in its URL if the username field is empty.
+fn username(meta: &PasswordMeta) -> String {
+    if meta.username.is_empty() {Credential::from_url(&meta.url).username} else {meta.username.clone()}
+}
+
+/// Entries that can answer `credential`, the ones bound to a path first.
+pub fn find(table: &PassTable, credential: &Credential) -> Vec<String> {
+    let mut names: Vec<String> = table.get_names()
+        .filter(|name| credential.matches(table.get_metadata(name).unwrap()))
+        .cloned()
+        .collect();
+    names.sort_by_key(|name| (Credential::from_url(&table.get_metadata(name).unwrap().url).path.is_empty(), name.clone()));
+    names
+}
+
+/// `name`, or `name (n)` when another entry already has it.
+fn free_name(table: &PassTable, name: &str) -> String {
+    (1..).map(|n| match n {
+        1 => name.to_string(),
+        n => format!("{name} ({n})")
+    }).find(|name| !table.contains(name)).unwrap()
+}
+
+/// Handles one helper invocation. `key` is only asked for when something has to be decrypted or encrypted.
+/// Returns whether the table was changed and has to be saved, getting a secret records the access.
+pub fn handle<R: BufRead, W: Write>(operation: &str, input: R, mut output: W, table: &mut PassTable, key: &mut dyn FnMut() -> Result<String, Box<dyn std::error::Error>>) -> Result<bool, Box<dyn std::error::Error>> {
+    let credential = Credential::read(input)?;
+    if credential.protocol.is_empty() || credential.host.is_empty() {
+        return Ok(false);
+    }
+    let names = find(table, &credential);
+    match operation {
+        "get" => {
+            let Some(name) = names.first() else { return Ok(false) };
+            let password = table.access_password(name, &key()?)?;
+            let username = username(table.get_metadata(name)?);
+            if !username.is_empty() { writeln!(output, "username={username}")?; }
+            writeln!(output, "password={password}")?;
+            Ok(true)
+        }
+        "store" => {
+            if credential.username.is_empty() || credential.password.is_empty() { return Ok(false); }
+            let key = key()?;
+            match names.iter().find(|name| username(table.get_metadata(name).unwrap()) == credential.username) {
+                Some(name) => {
+                    if table.get_password(name, &key)? == credential.password { return Ok(false); }
+                    table.update_password(&name.clone(), &credential.password, &key)?;
+                }
+                None => {
+                    let meta = PasswordMeta {
+                        description: "Stored by git".to_string(),
+                        username: credential.username.clone(),
+                        url: credential.url(),
+                        ..Default::default()
+                    };
+                    let name = free_name(table, &meta.url.replacen("://", &format!("://{}@", credential.username), 1));
+                    table.add_password(&name, &credential.password, meta, &key)?;
+                }
+            }
+            Ok(true)
+        }
+        "erase" => {
+            // git sends the rejected password, only entries holding it are removed
+            if credential.password.is_empty() { return Ok(false); }
+            let mut erased = false;
+            for name in names {
+                if table.get_password(&name, &key()?).ok().as_ref() != Some(&credential.password) {
+                    continue;
+                }
+                table.remove_password(&name)?;
+                erased = true;
+            }
+            Ok(erased)
+        }
+        _ => Ok(false) // unknown operations must be ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> Result<String, Box<dyn std::error::Error>> {
+        Ok("key".to_string())
+    }
+
+    fn call(operation: &str, input: &str, table: &mut PassTable) -> (String, bool) {
+        let mut output = Vec::new();
+        let changed = handle(operation, input.as_bytes(), &mut output, table, &mut key).unwrap();
+        (String::from_utf8(output).unwrap(), changed)
+    }
+
+    #[test]
+    fn read_test() -> std::io::Result<()> {
+        let credential = Credential::read("protocol=https\nhost=example.com:8443\npath=org/repo.git\ncapability[]=authtype\nusername=bob\n\nignored=1\n".as_bytes())?;
+        assert_eq!(credential, Credential{protocol: "https".to_string(), host: "example.com:8443".to_string(), path: "org/repo.git".to_string(), username: "bob".to_string(), password: "".to_string()});
+        assert_eq!(Credential::read("url=https://bob@example.com/repo\n".as_bytes())?, Credential::from_url("https://bob@example.com/repo"));
+        assert_eq!(credential.url(), "https://example.com:8443/org/repo.git");
+        Ok(())
+    }
+
+    #[test]
+    fn get_store_erase_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pt = PassTable::new();
+        pt.add_password("github", "token", PasswordMeta{url: "https://github.com".to_string(), username: "alice".to_string(), ..Default::default()}, "key")?;
+        pt.add_password("work repo", "work-token", PasswordMeta{url: "https://bot@github.com/work/repo.git".to_string(), ..Default::default()}, "key")?;
+
+        assert_eq!(call("get", "protocol=https\nhost=github.com\n", &mut pt).0, "username=alice\npassword=token\n");
+        assert_eq!(call("get", "protocol=https\nhost=github.com\npath=work/repo.git\n", &mut pt).0, "username=bot\npassword=work-token\n");
+        assert!(pt.get_times("work repo")?.accessed > 0);
+        assert_eq!(call("get", "protocol=https\nhost=github.com\nusername=carol\n", &mut pt).0, "");
+        assert_eq!(call("get", "protocol=http\nhost=github.com\n", &mut pt).0, "");
+
+        assert_eq!(call("store", "protocol=https\nhost=gitlab.com\nusername=carol\npassword=secret\n", &mut pt), (String::new(), true));
+        assert_eq!(pt.get_metadata("https://carol@gitlab.com")?.url, "https://gitlab.com");
+        assert!(!call("store", "protocol=https\nhost=gitlab.com\nusername=carol\npassword=secret\n", &mut pt).1);
+        assert!(call("store", "protocol=https\nhost=gitlab.com\nusername=carol\npassword=rotated\n", &mut pt).1);
+        assert_eq!(call("get", "protocol=https\nhost=gitlab.com\n", &mut pt).0, "username=carol\npassword=rotated\n");
+
+        // the username in the URL identifies the entry as well
+        assert!(call("store", "protocol=https\nhost=github.com\npath=work/repo.git\nusername=bot\npassword=new-token\n", &mut pt).1);
+        assert_eq!(pt.get_password("work repo", "key")?, "new-token");
+        assert_eq!(pt.get_names().count(), 3);
+
+        assert!(!call("erase", "protocol=https\nhost=gitlab.com\nusername=carol\npassword=stale\n", &mut pt).1);
+        assert!(!call("erase", "protocol=https\nhost=github.com\n", &mut pt).1);
+        assert_eq!(pt.get_names().count(), 3);
+        assert!(call("erase", "protocol=https\nhost=gitlab.com\nusername=carol\npassword=rotated\n", &mut pt).1);
+        assert!(!pt.contains("https://carol@gitlab.com"));
+
+        // a name taken by an unrelated entry isn't overwritten
+        pt.add_password("https://dave@example.org", "other", PasswordMeta::default(), "key")?;
+        assert!(call("store", "protocol=https\nhost=example.org\nusername=dave\npassword=secret\n", &mut pt).1);
+        assert_eq!(pt.get_password("https://dave@example.org", "key")?, "other");
+        assert_eq!(pt.get_password("https://dave@example.org (2)", "key")?, "secret");
+        Ok(())
+    }
+}
@@ -0,0 +1,468 @@
+//! Log-structured vault: every change is appended to the file as an encrypted and authenticated record,
+//! the table is rebuilt by replaying them and compaction replaces the log by a snapshot of the table.
+//! Trailing bytes too short to hold a record are left by a crash and ignored, the next write cuts them off.
+//! Any other record that can't be read is an error and leaves the file as it is until `JournalStore::recover`
+//! drops it and everything after it.
+//!
+//! Layout: `PTJ1`, an 8 byte file id, then records of a little-endian `u32` length, a 12 byte nonce and
+//! the sealed bincode record. The id and the record's sequence number are authenticated with it,
+//! so records can't be reordered or moved between journals.
+use std::{cell::RefCell, error::Error, fs::{File, OpenOptions}, io::{Read, Seek, SeekFrom, Write}, path::PathBuf};
+
+use aes_gcm_siv::{aead::{Aead, KeyInit, Payload}, Nonce};
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use sha2::Digest;
+
+use crate::{store::{Operations, VaultStore}, replace_private, PassCypher, PassHasher, PassTable, Password, Trashed, VaultLock};
+
+const MAGIC: &[u8] = b"PTJ1";
+const HEADER_LEN: u64 = 12;
+/// Length, nonce and authentication tag of a record with nothing sealed.
+const MIN_FRAME: usize = 4 + 12 + 16;
+pub const DEFAULT_COMPACT_AFTER: u64 = 1000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Change {
+    Put{name: String, entry: Box<Password>},
+    Delete{name: String},
+    /// The whole trash with its retention.
+    Trash{trash: Vec<Trashed>, retention: Option<u64>}
+}
+
+#[derive(Serialize, Deserialize)]
+enum Record {
+    /// Whole table, the first record of every journal.
+    Snapshot(PassTable),
+    /// Changes of one operation or transaction, applied together.
+    Changes(Vec<Change>)
+}
+
+fn apply(table: &mut PassTable, changes: &[Change]) {
+    for change in changes {
+        match change {
+            Change::Put{name, entry} => table.set_entry(name, (**entry).clone()),
+            Change::Delete{name} => { table.remove_entry(name); }
+            Change::Trash{trash, retention} => {
+                table.trash = trash.clone();
+                table.trash_retention = *retention;
+            }
+        }
+    }
+}
+
+/// Journal key, kept apart from the keys derived for entries.
+fn cipher(key: &str) -> PassCypher {
+    let mut hasher = PassHasher::new();
+    hasher.update(key.as_bytes());
+    hasher.update(b"journal");
+    PassCypher::new_from_slice(&hasher.finalize()).unwrap()
+}
+
+fn associated_data(id: &[u8; 8], sequence: u64) -> Vec<u8> {
+    [id.as_slice(), &sequence.to_le_bytes()].concat()
+}
+
+/// Replayed state of the file.
+struct Log {
+    id: [u8; 8],
+    table: PassTable,
+    /// Sequence number of the next record.
+    next: u64,
+    /// Length of the valid part of the file.
+    len: u64,
+    /// Bytes after the last valid record.
+    torn: u64
+}
+
+/// Transaction view: changes apply to a copy of the table and are collected into one record.
+struct Recorder {
+    table: PassTable,
+    changes: Vec<Change>
+}
+
+impl VaultStore for Recorder {
+    fn load(&self) -> Result<PassTable, Box<dyn Error>> {
+        Ok(self.table.clone())
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Password>, Box<dyn Error>> {
+        self.table.get(name)
+    }
+
+    fn put(&mut self, name: &str, entry: &Password) -> Result<(), Box<dyn Error>> {
+        self.table.set_entry(name, entry.clone());
+        self.changes.push(Change::Put{name: name.to_string(), entry: Box::new(entry.clone())});
+        Ok(())
+    }
+
+    fn delete(&mut self, name: &str) -> Result<bool, Box<dyn Error>> {
+        let deleted = self.table.delete(name)?;
+        if deleted {
+            self.changes.push(Change::Delete{name: name.to_string()});
+        }
+        Ok(deleted)
+    }
+
+    fn put_trash(&mut self, table: &PassTable) -> Result<(), Box<dyn Error>> {
+        self.table.put_trash(table)?;
+        self.changes.push(Change::Trash{trash: table.trash.clone(), retention: table.trash_retention});
+        Ok(())
+    }
+
+    fn transaction(&mut self, operations: &mut Operations) -> Result<(), Box<dyn Error>> {
+        let (table, len) = (self.table.clone(), self.changes.len());
+        let result = operations(self);
+        if result.is_err() {
+            self.table = table;
+            self.changes.truncate(len);
+        }
+        result
+    }
+}
+
+pub struct JournalStore {
+    path: PathBuf,
+    cipher: PassCypher,
+    log: RefCell<Log>,
+    compact_after: u64,
+    dropped: u64
+}
+
+impl JournalStore {
+    /// Opens or creates the journal at `path`, a damaged record is an error and the file isn't changed.
+    pub fn open<P: Into<PathBuf>>(path: P, key: &str) -> Result<Self, Box<dyn Error>> {
+        let mut journal = Self::new(path.into(), key);
+        let _lock = VaultLock::exclusive(&journal.path)?;
+        if journal.path.exists() {
+            journal.sync()?;
+            journal.dropped = journal.log.borrow().torn;
+        } else {
+            journal.rewrite()?;
+        }
+        Ok(journal)
+    }
+
+    /// Opens the journal at `path` and cuts it off at its first record that can't be read,
+    /// the records after it are lost.
+    pub fn recover<P: Into<PathBuf>>(path: P, key: &str) -> Result<Self, Box<dyn Error>> {
+        let mut journal = Self::new(path.into(), key);
+        let _lock = VaultLock::exclusive(&journal.path)?;
+        journal.replay(true)?;
+        journal.dropped = journal.log.borrow().torn;
+        journal.cut()?;
+        Ok(journal)
+    }
+
+    fn new(path: PathBuf, key: &str) -> Self {
+        JournalStore{path, cipher: cipher(key), compact_after: DEFAULT_COMPACT_AFTER, dropped: 0,
+            log: RefCell::new(Log{id: [0; 8], table: PassTable::new(), next: 0, len: 0, torn: 0})}
+    }
+
+    /// Compacts once the journal holds more than `records` records.
+    pub fn compact_after(&mut self, records: u64) {
+        self.compact_after = records;
+    }
+
+    /// Bytes after the last record when the journal was opened, cut off by `recover` or the next write.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Number of records in the journal, the snapshot included.
+    pub fn records(&self) -> u64 {
+        self.log.borrow().next
+    }
+
+    fn seal(&self, id: &[u8; 8], sequence: u64, record: &Record) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let aad = associated_data(id, sequence);
+        let sealed = self.cipher.encrypt(Nonce::from_slice(&nonce), Payload{msg: &bincode::serialize(record)?, aad: &aad}).or(Err(crate::AES))?;
+        let mut framed = ((nonce.len() + sealed.len()) as u32).to_le_bytes().to_vec();
+        framed.extend(nonce);
+        framed.extend(sealed);
+        Ok(framed)
+    }
+
+    /// Record at the start of `data` and its framed length, `None` at the end or before bytes too short for a record.
+    fn unseal(&self, id: &[u8; 8], sequence: u64, data: &[u8]) -> Result<Option<(Record, usize)>, Box<dyn Error>> {
+        if data.len() < MIN_FRAME { return Ok(None); }
+        let len = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+        let record = data.get(4..4 + len).and_then(|framed| framed.split_at_checked(12)).and_then(|(nonce, sealed)| {
+            let aad = associated_data(id, sequence);
+            self.cipher.decrypt(Nonce::from_slice(nonce), Payload{msg: sealed, aad: &aad}).ok()
+        });
+        match record.and_then(|record| bincode::deserialize(&record).ok()) {
+            Some(record) => Ok(Some((record, 4 + len))),
+            // without a readable snapshot the key is wrong
+            None if sequence == 0 => Err(crate::IncorrectPass.into()),
+            None => Err(format!("record {sequence} of the journal is damaged").into())
+        }
+    }
+
+    /// Replays the records appended since the last call, the whole file after it was compacted elsewhere.
+    /// Callers hold the `VaultLock`.
+    fn sync(&self) -> Result<(), Box<dyn Error>> {
+        self.replay(false)
+    }
+
+    /// `sync`, with `salvage` the records from the first damaged one on are left as torn.
+    fn replay(&self, salvage: bool) -> Result<(), Box<dyn Error>> {
+        let mut log = self.log.borrow_mut();
+        let mut file = File::open(&self.path)?;
+        let mut header = [0u8; HEADER_LEN as usize];
+        file.read_exact(&mut header).map_err(|_| "not a passtool journal")?;
+        if &header[..4] != MAGIC { return Err("not a passtool journal".into()); }
+        let id: [u8; 8] = header[4..].try_into().unwrap();
+        if id != log.id || file.metadata()?.len() < log.len {
+            *log = Log{id, table: PassTable::new(), next: 0, len: HEADER_LEN, torn: 0};
+        }
+        file.seek(SeekFrom::Start(log.len))?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let mut offset = 0;
+        loop {
+            let (record, len) = match self.unseal(&log.id, log.next, &data[offset..]) {
+                Ok(Some(record)) => record,
+                Ok(None) => break,
+                Err(_) if salvage && log.next > 0 => break,
+                Err(e) => {
+                    // the next call replays the file from the start
+                    log.id = [0; 8];
+                    return Err(e);
+                }
+            };
+            match record {
+                Record::Snapshot(table) => log.table = table,
+                Record::Changes(changes) => apply(&mut log.table, &changes)
+            }
+            log.next += 1;
+            offset += len;
+        }
+        // a journal cut off inside its snapshot can't be recovered
+        if log.next == 0 { return Err("the journal has no snapshot".into()); }
+        log.len += offset as u64;
+        log.torn = (data.len() - offset) as u64;
+        Ok(())
+    }
+
+    /// Cuts off the bytes after the last valid record so new records follow it.
+    fn cut(&self) -> Result<(), Box<dyn Error>> {
+        let mut log = self.log.borrow_mut();
+        if log.torn > 0 {
+            OpenOptions::new().write(true).open(&self.path)?.set_len(log.len)?;
+            log.torn = 0;
+        }
+        Ok(())
+    }
+
+    /// Replaces the file by a snapshot of the table under a new id.
+    fn rewrite(&self) -> Result<(), Box<dyn Error>> {
+        let mut id = [0u8; 8];
+        rand::thread_rng().fill_bytes(&mut id);
+        let table = self.log.borrow().table.clone();
+        let mut data = MAGIC.to_vec();
+        data.extend(id);
+        data.extend(self.seal(&id, 0, &Record::Snapshot(table))?);
+        replace_private(&self.path, &data)?;
+        let mut log = self.log.borrow_mut();
+        *log = Log{id, table: std::mem::take(&mut log.table), next: 1, len: data.len() as u64, torn: 0};
+        Ok(())
+    }
+
+    /// Rewrites the journal as a single snapshot.
+    pub fn compact(&mut self) -> Result<(), Box<dyn Error>> {
+        let _lock = VaultLock::exclusive(&self.path)?;
+        self.sync()?;
+        self.rewrite()
+    }
+
+    /// Appends one record, the caller holds the exclusive lock and has synced.
+    fn append(&self, changes: Vec<Change>) -> Result<(), Box<dyn Error>> {
+        let (id, next) = {
+            let log = self.log.borrow();
+            (log.id, log.next)
+        };
+        let record = Record::Changes(changes);
+        let framed = self.seal(&id, next, &record)?;
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        file.write_all(&framed)?;
+        file.sync_data()?;
+        let mut log = self.log.borrow_mut();
+        if let Record::Changes(changes) = &record {
+            apply(&mut log.table, changes);
+        }
+        log.next += 1;
+        log.len += framed.len() as u64;
+        let compact = log.next > self.compact_after;
+        drop(log);
+        if compact { self.rewrite()?; }
+        Ok(())
+    }
+}
+
+impl VaultStore for JournalStore {
+    fn load(&self) -> Result<PassTable, Box<dyn Error>> {
+        let _lock = VaultLock::shared(&self.path)?;
+        self.sync()?;
+        Ok(self.log.borrow().table.clone())
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Password>, Box<dyn Error>> {
+        let _lock = VaultLock::shared(&self.path)?;
+        self.sync()?;
+        Ok(self.log.borrow().table.get_entry(name).cloned())
+    }
+
+    fn put(&mut self, name: &str, entry: &Password) -> Result<(), Box<dyn Error>> {
+        self.transaction(&mut |store| store.put(name, entry))
+    }
+
+    fn delete(&mut self, name: &str) -> Result<bool, Box<dyn Error>> {
+        let mut deleted = false;
+        self.transaction(&mut |store| {
+            deleted = store.delete(name)?;
+            Ok(())
+        })?;
+        Ok(deleted)
+    }
+
+    fn put_trash(&mut self, table: &PassTable) -> Result<(), Box<dyn Error>> {
+        self.transaction(&mut |store| store.put_trash(table))
+    }
+
+    fn transaction(&mut self, operations: &mut Operations) -> Result<(), Box<dyn Error>> {
+        let _lock = VaultLock::exclusive(&self.path)?;
+        self.sync()?;
+        self.cut()?;
+        let mut recorder = Recorder{table: self.log.borrow().table.clone(), changes: Vec::new()};
+        operations(&mut recorder)?;
+        if !recorder.changes.is_empty() {
+            self.append(recorder.changes)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::PasswordMeta;
+
+    fn entry(password: &str) -> Password {
+        let mut table = PassTable::new();
+        table.add_password("x", password, PasswordMeta::default(), "key").unwrap();
+        table.get_entry("x").unwrap().clone()
+    }
+
+    fn path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("passtool-journal-{name}-{}.ptj", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn replay_test() -> Result<(), Box<dyn Error>> {
+        let path = path("replay");
+        let mut journal = JournalStore::open(&path, "vault key")?;
+        journal.put("a", &entry("1"))?;
+        journal.put("b", &entry("2"))?;
+        assert!(journal.delete("a")?);
+        assert!(!journal.delete("a")?);
+        assert!(journal.transaction(&mut |store| {
+            store.put("c", &entry("3"))?;
+            Err("abort".into())
+        }).is_err());
+        assert_eq!(journal.records(), 4);
+
+        // a second writer sees the appended records
+        let mut other = JournalStore::open(&path, "vault key")?;
+        assert_eq!(other.load()?, journal.load()?);
+        let c = entry("3");
+        other.put("c", &c)?;
+        assert_eq!(journal.get("c")?, Some(c));
+        assert_eq!(journal.load()?.get_password("c", "key")?, "3");
+        assert!(!journal.load()?.contains("a"));
+
+        assert_eq!(JournalStore::open(&path, "wrong").err().map(|e| e.to_string()), Some("incorrect password".to_string()));
+        assert_eq!(fs::metadata(&path)?.len(), journal.log.borrow().len);
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn torn_write_test() -> Result<(), Box<dyn Error>> {
+        let path = path("torn");
+        let mut journal = JournalStore::open(&path, "key")?;
+        journal.put("a", &entry("1"))?;
+        let valid = fs::metadata(&path)?.len();
+        let mut file = OpenOptions::new().append(true).open(&path)?;
+        file.write_all(&[1; MIN_FRAME - 1])?;
+
+        // too short for a record, ignored on open and cut off by the next write
+        let mut journal = JournalStore::open(&path, "key")?;
+        assert_eq!(journal.dropped(), MIN_FRAME as u64 - 1);
+        assert_eq!(fs::metadata(&path)?.len(), valid + MIN_FRAME as u64 - 1);
+        journal.put("b", &entry("2"))?;
+        let valid = fs::metadata(&path)?.len();
+        journal.put("c", &entry("3"))?;
+        file.set_len(fs::metadata(&path)?.len() - 5)?;
+
+        // a record cut short is only dropped by an explicit recovery
+        let data = fs::read(&path)?;
+        let opened = JournalStore::open(&path, "key");
+        assert_eq!(opened.err().map(|e| e.to_string()), Some("record 3 of the journal is damaged".to_string()));
+        assert_eq!(fs::read(&path)?, data);
+        let mut journal = JournalStore::recover(&path, "key")?;
+        assert!(journal.dropped() > 0);
+        assert_eq!(fs::metadata(&path)?.len(), valid);
+        assert!(journal.get("b")?.is_some() && journal.get("c")?.is_none());
+        journal.put("d", &entry("4"))?;
+        let journal = JournalStore::open(&path, "key")?;
+        assert_eq!(journal.dropped(), 0);
+        assert!(journal.get("d")?.is_some());
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn damaged_record_test() -> Result<(), Box<dyn Error>> {
+        let path = path("damaged");
+        let mut journal = JournalStore::open(&path, "key")?;
+        journal.put("a", &entry("1"))?;
+        journal.put("b", &entry("2"))?;
+        let mut data = fs::read(&path)?;
+        *data.last_mut().unwrap() ^= 1;
+        fs::write(&path, &data)?;
+
+        // a complete record failing authentication isn't a torn write, nothing is cut off
+        let opened = JournalStore::open(&path, "key");
+        assert_eq!(opened.err().map(|e| e.to_string()), Some("record 2 of the journal is damaged".to_string()));
+        assert_eq!(fs::read(&path)?, data);
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn compaction_test() -> Result<(), Box<dyn Error>> {
+        let path = path("compact");
+        let mut journal = JournalStore::open(&path, "key")?;
+        let other = JournalStore::open(&path, "key")?;
+        journal.compact_after(3);
+        for name in ["a", "b", "c"] {
+            journal.put(name, &entry(name))?;
+        }
+        assert_eq!(journal.records(), 1);
+        journal.delete("a")?;
+        assert_eq!(journal.records(), 2);
+        // the other instance notices the new file and replays it from the start
+        let mut names: Vec<String> = other.load()?.get_names().cloned().collect();
+        names.sort();
+        assert_eq!(names, ["b", "c"]);
+        assert_eq!(other.records(), 2);
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
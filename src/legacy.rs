@@ -0,0 +1,108 @@
+//! Table layouts of older format versions: the one written before the format header was introduced
+//! and `PTv1`, which added usernames and URLs.
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+struct PasswordMeta {
+    description: String,
+    apps: Vec<String>
+}
+
+#[derive(Deserialize)]
+struct Password {
+    cypher: Vec<u8>,
+    meta: PasswordMeta
+}
+
+#[derive(Deserialize)]
+struct PassTable {
+    passwords: HashMap<String, Password>
+}
+
+pub(crate) const V1_MAGIC: &[u8] = b"PTv1";
+
+#[derive(Deserialize)]
+struct PasswordMetaV1 {
+    description: String,
+    apps: Vec<String>,
+    username: String,
+    url: String
+}
+
+#[derive(Deserialize)]
+struct PasswordV1 {
+    cypher: Vec<u8>,
+    meta: PasswordMetaV1
+}
+
+#[derive(Deserialize)]
+struct PassTableV1 {
+    passwords: HashMap<String, PasswordV1>
+}
+
+/// Entry of an older layout, which had no ids or times: the id is derived from the name and the times are
+/// unknown (0), so every load of the same file gives the same table.
+fn entry(name: &str, cypher: Vec<u8>, meta: crate::PasswordMeta) -> crate::Password {
+    crate::Password{id: Uuid::new_v5(&Uuid::NAMESPACE_OID, name.as_bytes()), cypher, meta, history: Vec::new(), times: crate::Times::default()}
+}
+
+/// Table in the `PTv1` layout, without the magic.
+pub(crate) fn from_v1(encoded: &[u8]) -> Result<crate::PassTable, Box<dyn std::error::Error>> {
+    let v1: PassTableV1 = bincode::deserialize(encoded)?;
+    let mut table = crate::PassTable::new();
+    for (name, p) in v1.passwords {
+        let meta = crate::PasswordMeta{username: p.meta.username, url: p.meta.url, ..crate::PasswordMeta::new(p.meta.description, p.meta.apps)};
+        table.set_entry(&name, entry(&name, p.cypher, meta));
+    }
+    Ok(table)
+}
+
+pub(crate) fn from_binary(encoded: &[u8]) -> Result<crate::PassTable, Box<dyn std::error::Error>> {
+    let legacy: PassTable = bincode::deserialize(encoded)?;
+    let mut table = crate::PassTable::new();
+    for (name, p) in legacy.passwords {
+        table.set_entry(&name, entry(&name, p.cypher, crate::PasswordMeta::new(p.meta.description, p.meta.apps)));
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_load_test() -> Result<(), Box<dyn std::error::Error>> {
+        // name -> (cypher, (description, apps)), the way the first release serialized it
+        let mut passwords = HashMap::new();
+        passwords.insert("pass1".to_string(), (crate::encrypt(b"test1", "key1").unwrap(), ("desc".to_string(), vec!["app.exe".to_string()])));
+        let encoded = bincode::serialize(&passwords)?;
+
+        let table = crate::PassTable::from_binary(&encoded)?;
+        assert_eq!(table.get_password("pass1", "key1")?, "test1");
+        assert_eq!(table.get_metadata("pass1")?.apps, vec!["app.exe".to_string()]);
+        // nothing changes between loads
+        assert_eq!(crate::PassTable::from_binary(&encoded)?, table);
+        assert_eq!(table.get_metadata("pass1")?.username, "");
+        Ok(())
+    }
+
+    #[test]
+    fn v1_load_test() -> Result<(), Box<dyn std::error::Error>> {
+        // name -> (cypher, (description, apps, username, url))
+        let mut passwords = HashMap::new();
+        passwords.insert("pass1".to_string(), (crate::encrypt(b"test1", "key1").unwrap(),
+            ("desc".to_string(), vec!["app.exe".to_string()], "alice".to_string(), "https://example.com".to_string())));
+        let mut encoded = V1_MAGIC.to_vec();
+        encoded.append(&mut bincode::serialize(&passwords)?);
+
+        let table = crate::PassTable::from_binary(&encoded)?;
+        assert_eq!(table.get_password("pass1", "key1")?, "test1");
+        assert_eq!(table.get_metadata("pass1")?.username, "alice");
+        assert_eq!(table.get_metadata("pass1")?.url, "https://example.com");
+        assert_eq!(crate::PassTable::from_binary(&encoded)?, table);
+        Ok(())
+    }
+}
@@ -1,19 +1,40 @@
 use core::fmt;
-use std::{fs, collections::{HashMap, hash_map::Keys}, path::Path};
+use std::{fs, io, collections::{BTreeMap, HashMap, hash_map::Keys}, path::{Path, PathBuf}};
 
-use hex_literal::hex;
-
-use sha2::{Sha256, Sha512, Digest}; 
+use sha2::{Sha256, Digest}; 
 use sha2::digest::typenum::Unsigned;
 
 use aes_gcm_siv::{
-    aead::{Aead, KeyInit, Key, OsRng},
+    aead::{Aead, KeyInit, Key},
     Aes256GcmSiv, Nonce
 };
 
 use serde::{Serialize, Deserialize};
+use uuid::Uuid;
 
 pub mod generator;
+pub mod backup;
+pub mod netrc;
+pub mod dotenv;
+pub mod run;
+pub mod template;
+pub mod git_credential;
+pub mod docker_credential;
+pub mod ssh_agent;
+pub mod unlock_agent;
+pub mod native_messaging;
+pub mod rest_api;
+pub mod askpass;
+pub mod tui;
+pub mod clipboard;
+pub mod store;
+pub mod journal;
+pub mod search;
+pub mod app_rules;
+pub mod fingerprint;
+#[cfg(target_os = "linux")]
+pub mod secret_service;
+mod legacy;
 
 pub use Error::*;
 #[derive(Debug, PartialEq)]
@@ -21,7 +42,8 @@ pub enum Error {
     PassExists,
     PassNotFound, 
     IncorrectPass,
-    AES
+    AES,
+    VersionNotFound
 }
 
 impl fmt::Display for Error {
@@ -30,7 +52,8 @@ impl fmt::Display for Error {
             Self::PassExists => f.write_str("password already exists"),
             Self::PassNotFound => f.write_str("password not found"),
             Self::IncorrectPass => f.write_str("incorrect password"),
-            Self::AES => f.write_str("aes error")
+            Self::AES => f.write_str("aes error"),
+            Self::VersionNotFound => f.write_str("version not found")
         }
     }
 }
@@ -56,83 +79,430 @@ pub fn key_from_password<D: Digest, K : KeyInit>(password: &str) -> Key<K> {
 }
 
 pub fn encrypt(message : &[u8], password : &str) -> Result<Vec<u8>, aes_gcm_siv::Error>{
-    let key = key_from_password::<PassHasher, PassCypher>(password);
-    let cipher = PassCypher::new(&key);
-    let nonce = nonce_from_password::<PassHasher>(password);
-    
-    cipher.encrypt(&nonce, message)
+    DerivedKey::new(password).encrypt(message)
 }
 
 pub fn decrypt(message : &[u8], password : &str) -> Result<Vec<u8>, aes_gcm_siv::Error>{
-    let key = key_from_password::<PassHasher, PassCypher>(password);
-    let cipher = PassCypher::new(&key);
-    let nonce = nonce_from_password::<PassHasher>(password);
-    
-    cipher.decrypt(&nonce, message)
+    DerivedKey::new(password).decrypt(message)
+}
+
+/// Cipher key and nonce derived from a user key, can be kept around instead of the key itself.
+/// Wiped from memory on drop.
+#[derive(PartialEq)]
+pub struct DerivedKey {
+    key: Key<PassCypher>,
+    nonce: Nonce
+}
+
+impl DerivedKey {
+    pub fn new(password: &str) -> Self {
+        DerivedKey {
+            key: key_from_password::<PassHasher, PassCypher>(password),
+            nonce: nonce_from_password::<PassHasher>(password)
+        }
+    }
+
+    pub fn encrypt(&self, message: &[u8]) -> Result<Vec<u8>, aes_gcm_siv::Error> {
+        PassCypher::new(&self.key).encrypt(&self.nonce, message)
+    }
+
+    pub fn decrypt(&self, message: &[u8]) -> Result<Vec<u8>, aes_gcm_siv::Error> {
+        PassCypher::new(&self.key).decrypt(&self.nonce, message)
+    }
+
+    /// All zero, to be swapped with a real key.
+    pub(crate) fn zeroed() -> Self {
+        DerivedKey{key: Default::default(), nonce: Default::default()}
+    }
+
+    pub(crate) fn wipe(&mut self) {
+        use zeroize::Zeroize;
+        self.key.as_mut_slice().zeroize();
+        self.nonce.as_mut_slice().zeroize();
+    }
+}
+
+impl Drop for DerivedKey {
+    fn drop(&mut self) {
+        self.wipe();
+    }
 }
 
-#[derive(Default, Debug, Serialize, Deserialize, PartialEq)]
+/// What the encrypted secret of an entry holds.
+#[derive(Default, Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum EntryKind {
+    #[default]
+    Password,
+    /// Unencrypted OpenSSH private key, served by the `ssh_agent`.
+    SshKey
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct PasswordMeta {
     pub description: String,
-    pub apps: Vec<String>
+    pub apps: Vec<String>,
+    pub username: String,
+    pub url: String,
+    pub tags: Vec<String>,
+    /// Group path like `work/servers`, empty for the top level.
+    pub group: String,
+    pub kind: EntryKind,
+    /// Lookup attributes of Secret Service items.
+    pub attributes: BTreeMap<String, String>,
+    /// When the secret should be rotated, never if `None`.
+    pub expiry: Option<Expiry>,
+    /// Executables the rules in `apps` are bound to, by rule.
+    pub fingerprints: BTreeMap<String, fingerprint::Fingerprint>
+}
+
+/// `group` without empty components or surrounding `/`, `/work//servers/` becomes `work/servers`.
+pub fn group_path(group: &str) -> String {
+    group.split('/').map(str::trim).filter(|part| !part.is_empty()).collect::<Vec<_>>().join("/")
+}
+
+/// Entries of a group and its subgroups, see `PassTable::get_tree`.
+#[derive(Default, Debug, PartialEq, Clone)]
+pub struct Group {
+    /// Last component of the path, empty for the top level.
+    pub name: String,
+    pub path: String,
+    /// Sorted by name.
+    pub groups: Vec<Group>,
+    /// Sorted.
+    pub entries: Vec<String>
+}
+
+impl Group {
+    /// The subgroup at `path`, relative to this group.
+    pub fn find(&self, path: &str) -> Option<&Group> {
+        group_path(path).split('/').filter(|part| !part.is_empty())
+            .try_fold(self, |group, part| group.groups.iter().find(|g| g.name == part))
+    }
+
+    /// Every entry below this group with the group holding it, subgroups come before entries.
+    pub fn flatten(&self) -> Vec<(&Group, &str)> {
+        let mut entries: Vec<(&Group, &str)> = self.groups.iter().flat_map(Group::flatten).collect();
+        entries.extend(self.entries.iter().map(|name| (self, name.as_str())));
+        entries
+    }
+
+    fn sort(&mut self) {
+        self.groups.sort_by(|a, b| a.name.cmp(&b.name));
+        self.groups.iter_mut().for_each(Group::sort);
+    }
+}
+
+/// Rotation policy of an entry, see `PassTable::get_expiring`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum Expiry {
+    /// Fixed time, seconds since the unix epoch.
+    At(u64),
+    /// Seconds after the password was last changed.
+    MaxAge(u64)
 }
 
 impl PasswordMeta {
     pub fn new(description: String, apps: Vec<String>) -> Self {
-        Self{description, apps}
+        Self{description, apps, ..Default::default()}
+    }
+
+    /// Removes the app rule at `index` together with its fingerprint.
+    pub fn remove_app(&mut self, index: usize) -> String {
+        let rule = self.apps.remove(index);
+        self.fingerprints.remove(&rule);
+        rule
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// Previous secrets kept per entry, older ones are dropped.
+pub const HISTORY_LEN: usize = 10;
+
+/// Seconds since the unix epoch.
+pub(crate) fn now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Secret an entry held before it was changed.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Version {
+    cypher: Vec<u8>,
+    /// When it was replaced, seconds since the unix epoch.
+    pub replaced: u64
+}
+
+/// When an entry was changed and used, seconds since the unix epoch, kept up to date by `PassTable`.
+#[derive(Default, Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub struct Times {
+    pub created: u64,
+    /// Last change of the secret or the metadata.
+    pub modified: u64,
+    pub password_changed: u64,
+    /// Last decryption through `PassTable::access_password` or `mark_accessed`, 0 if never.
+    pub accessed: u64
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Password {
+    /// Stays the same when the entry is renamed.
+    id: Uuid,
     cypher: Vec<u8>,
-    meta: PasswordMeta
+    meta: PasswordMeta,
+    /// Newest first, at most `HISTORY_LEN`.
+    history: Vec<Version>,
+    times: Times
 }
 
 impl Password {
     pub fn new(cypher: Vec<u8>, meta: PasswordMeta) -> Self{
-        Password{cypher, meta}
+        let now = now();
+        Password{id: Uuid::new_v4(), cypher, meta, history: Vec::new(), times: Times{created: now, modified: now, password_changed: now, accessed: 0}}
     }
 
     pub fn from_cypher(cypher: Vec<u8>) -> Self{
-        Password{cypher, meta: Default::default()}
+        Password::new(cypher, Default::default())
+    }
+
+    /// Sets a new secret and keeps the current one as the newest version.
+    fn replace_cypher(&mut self, cypher: Vec<u8>) {
+        let previous = std::mem::replace(&mut self.cypher, cypher);
+        self.history.insert(0, Version{cypher: previous, replaced: now()});
+        self.history.truncate(HISTORY_LEN);
+        self.times.password_changed = now();
+        self.times.modified = self.times.password_changed;
     }
 
     pub fn update_meta(&mut self, meta: PasswordMeta) {
         self.meta = meta;
+        self.times.modified = now();
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn meta(&self) -> &PasswordMeta {
+        &self.meta
+    }
+
+    pub fn times(&self) -> &Times {
+        &self.times
+    }
+
+    /// When the secret expires under the entry's `Expiry`.
+    pub fn expires(&self) -> Option<u64> {
+        match self.meta.expiry? {
+            Expiry::At(time) => Some(time),
+            Expiry::MaxAge(age) => Some(self.times.password_changed.saturating_add(age))
+        }
+    }
+}
+
+/// Loads the current state of the vault, used by long-running services to pick up changes.
+pub type TableSource = Box<dyn Fn() -> Result<PassTable, Box<dyn std::error::Error>> + Send>;
+
+/// Records that the secret of an entry was decrypted, the counterpart of `TableSource`.
+pub type AccessRecorder = Box<dyn Fn(&str) -> Result<(), Box<dyn std::error::Error>> + Send>;
+
+pub const SAVEFILE: &str = "passwords.pt";
+
+/// Vault the GUI uses: `passwords.pt` next to the executable.
+pub fn default_path() -> PathBuf {
+    let mut path = std::env::current_exe().unwrap();
+    path.pop();
+    path.push(SAVEFILE);
+    path
+}
+
+/// Creates or truncates a file only the current user can read (mode 0600 on unix).
+fn create_private<P: AsRef<Path>>(filename: P) -> io::Result<fs::File> {
+    open_private(filename, true)
+}
+
+/// Opens a file for writing, creating it if needed, only the current user can read it (mode 0600 on unix).
+pub(crate) fn open_private<P: AsRef<Path>>(filename: P, truncate: bool) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(truncate);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        let file = options.open(&filename)?;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?; // mode is ignored for existing files
+        Ok(file)
+    }
+    #[cfg(not(unix))]
+    options.open(filename)
+}
+
+/// Writes `contents` so that only the current user can read it (mode 0600 on unix).
+pub(crate) fn write_private<P: AsRef<Path>>(filename: P, contents: &[u8]) -> io::Result<()> {
+    use io::Write;
+    create_private(filename)?.write_all(contents)
+}
+
+/// Replaces `filename` with `contents` as a whole: they are written to a private temporary file,
+/// which is synced and renamed over it, so a crash leaves either the old or the new contents.
+pub(crate) fn replace_private<P: AsRef<Path>>(filename: P, contents: &[u8]) -> io::Result<()> {
+    use io::Write;
+    let mut temp = filename.as_ref().as_os_str().to_owned();
+    temp.push(".tmp");
+    let mut file = create_private(&temp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temp, filename)
+}
+
+/// Advisory lock on `<vault>.lock`, released when dropped.
+/// Writers hold it exclusively from loading to saving so that concurrent updates are not lost.
+/// On unix the lock file is only readable by the current user and removed by the last holder.
+#[cfg_attr(not(unix), allow(dead_code))] // elsewhere the file is only held, never checked or removed
+pub struct VaultLock {
+    file: fs::File,
+    path: PathBuf
+}
+
+impl VaultLock {
+    fn acquire(vault: &Path, exclusive: bool) -> io::Result<Self> {
+        let mut path = vault.as_os_str().to_owned();
+        path.push(".lock");
+        let path = PathBuf::from(path);
+        loop {
+            let mut options = fs::OpenOptions::new();
+            options.read(true).write(true).create(true).truncate(false);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let file = options.open(&path)?;
+            if exclusive { file.lock()? } else { file.lock_shared()? }
+            let lock = VaultLock{file, path: path.clone()};
+            // the previous holder may have removed the file while this one waited for it
+            if lock.is_current() { return Ok(lock); }
+        }
+    }
+
+    /// Whether the locked file is still the one at the path.
+    fn is_current(&self) -> bool {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            match (self.file.metadata(), fs::metadata(&self.path)) {
+                (Ok(locked), Ok(current)) => (locked.dev(), locked.ino()) == (current.dev(), current.ino()),
+                _ => false
+            }
+        }
+        #[cfg(not(unix))]
+        true
+    }
+
+    pub fn exclusive<P: AsRef<Path>>(vault: P) -> io::Result<Self> {
+        VaultLock::acquire(vault.as_ref(), true)
+    }
+
+    /// Keeps writers out while the vault is read.
+    pub fn shared<P: AsRef<Path>>(vault: P) -> io::Result<Self> {
+        VaultLock::acquire(vault.as_ref(), false)
+    }
+}
+
+impl Drop for VaultLock {
+    fn drop(&mut self) {
+        // only a holder nobody else shares the lock with may remove the file, others wait on it
+        #[cfg(unix)]
+        if self.file.try_lock().is_ok() && self.is_current() {
+            let _ = fs::remove_file(&self.path);
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+/// Entry removed by `PassTable::remove_password`, kept until it is purged.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Trashed {
+    pub name: String,
+    /// When it was removed, seconds since the unix epoch.
+    pub deleted: u64,
+    entry: Password
+}
+
+impl Trashed {
+    pub fn entry(&self) -> &Password {
+        &self.entry
+    }
+}
+
+/// How long removed entries are kept by default, 30 days.
+pub const DEFAULT_TRASH_RETENTION: u64 = 30 * 24 * 60 * 60;
+
+/// Prefix of the serialized table, changed with every change of the layout.
+/// Files with an older prefix or without one are read with the `legacy` layouts.
+const FORMAT_MAGIC: &[u8] = b"PTv2";
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct PassTable {
-    passwords: HashMap<String, Password>
+    passwords: HashMap<String, Password>,
+    /// Oldest first.
+    trash: Vec<Trashed>,
+    /// Seconds removed entries are kept, forever if `None`.
+    trash_retention: Option<u64>
+}
+
+impl Default for PassTable {
+    fn default() -> Self {
+        PassTable::new()
+    }
 }
 
 impl PassTable {
     pub fn new() -> Self {
-        PassTable { passwords: HashMap::new() }
+        PassTable { passwords: HashMap::new(), trash: Vec::new(), trash_retention: Some(DEFAULT_TRASH_RETENTION) }
     }
 
     fn encoded(&self) -> Vec<u8> {
-        bincode::serialize(self).unwrap()
+        let mut encoded = FORMAT_MAGIC.to_vec();
+        encoded.append(&mut bincode::serialize(self).unwrap());
+        encoded
     }
 
     pub fn from_binary(encoded: &[u8]) -> Result<Self, Box<dyn std::error::Error>>  {
-        let table: Self = bincode::deserialize(encoded)?;
+        let table: Self = if let Some(encoded) = encoded.strip_prefix(FORMAT_MAGIC) {
+            bincode::deserialize(encoded)?
+        } else if let Some(encoded) = encoded.strip_prefix(legacy::V1_MAGIC) {
+            legacy::from_v1(encoded)?
+        } else {
+            legacy::from_binary(encoded)?
+        };
         Ok(table)
     }
 
+    /// Removed entries past the trash retention are dropped, `update_file` saves that.
     pub fn from_file<P:  AsRef<Path>>(filename: P) -> Result<Self, Box<dyn std::error::Error>>  {
         let encoded = fs::read(filename)?;
-        PassTable::from_binary(&encoded)
+        let mut table = PassTable::from_binary(&encoded)?;
+        table.purge_expired(now());
+        Ok(table)
     }
 
+    /// Replaces the file atomically, it is only readable by the current user.
     pub fn to_file<P : AsRef<Path>>(&self, filename: P) -> Result<(), Box<dyn std::error::Error>>{
-        let encoded = self.encoded();
-        fs::write(filename, encoded)?;
+        replace_private(filename, &self.encoded())?;
         Ok(())
     }
 
+    /// `from_file` under a shared `VaultLock`.
+    pub fn from_file_locked<P: AsRef<Path>>(filename: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let _lock = VaultLock::shared(&filename)?;
+        PassTable::from_file(filename)
+    }
+
+    /// Loads `filename`, applies `update` and saves the result under an exclusive `VaultLock`.
+    /// Nothing is saved if `update` fails.
+    pub fn update_file<P: AsRef<Path>, T>(filename: P, update: impl FnOnce(&mut PassTable) -> Result<T, Box<dyn std::error::Error>>) -> Result<T, Box<dyn std::error::Error>> {
+        let _lock = VaultLock::exclusive(&filename)?;
+        let mut table = PassTable::from_file(&filename)?;
+        let result = update(&mut table)?;
+        table.to_file(&filename)?;
+        Ok(result)
+    }
+
     fn get_cypher(&self, name: &str) -> Option<&Password> {
         self.passwords.get(name)
     }
@@ -147,15 +517,48 @@ impl PassTable {
     }
 
     fn add_cypher(&mut self, name: String, cypher: Vec<u8>, meta: PasswordMeta) {
-        self.passwords.insert(name, Password{cypher, meta});
+        self.passwords.insert(name, Password::new(cypher, meta));
+    }
+
+    /// Stored entry with its encrypted secret, used to move entries between stores without the key.
+    pub fn get_entry(&self, name: &str) -> Option<&Password> {
+        self.get_cypher(name)
+    }
+
+    /// Inserts or replaces the stored entry `name`.
+    pub fn set_entry(&mut self, name: &str, entry: Password) {
+        self.passwords.insert(name.to_string(), entry);
+    }
+
+    /// Removes the stored entry `name` for good, unlike `remove_password` it doesn't go to the trash.
+    pub fn remove_entry(&mut self, name: &str) -> Option<Password> {
+        self.passwords.remove(name)
     }
 
     pub fn get_password(&self, name: &str, key: &str) -> Result<String, Error> {
+        self.get_password_with(name, &DerivedKey::new(key))
+    }
+
+    pub fn get_password_with(&self, name: &str, key: &DerivedKey) -> Result<String, Error> {
         let cypher = self.get_cypher(name).ok_or(PassNotFound)?;
-        let password = decrypt(&cypher.cypher, key).or(Err(IncorrectPass))?;
+        let password = key.decrypt(&cypher.cypher).or(Err(IncorrectPass))?;
         String::from_utf8(password).or(Err(AES))
     }
 
+    /// `get_password` recording the access time, for tables that are saved afterwards.
+    pub fn access_password(&mut self, name: &str, key: &str) -> Result<String, Error> {
+        let password = self.get_password(name, key)?;
+        self.mark_accessed(name)?;
+        Ok(password)
+    }
+
+    /// Records that the secret of `name` was used.
+    pub fn mark_accessed(&mut self, name: &str) -> Result<(), Error> {
+        let p = self.get_cypher_mut(name).ok_or(PassNotFound)?;
+        p.times.accessed = now();
+        Ok(())
+    }
+
     pub fn add_password(&mut self, name: &str, password: &str, meta: PasswordMeta, key: &str) -> Result<(), Error>{
         if self.passwords.contains_key(name) {return Err(PassExists)}
         let cypher = encrypt(password.as_bytes(), key).or(Err(AES))?;
@@ -163,23 +566,132 @@ impl PassTable {
         Ok(())
     }
 
+    /// Replaces the secret of an existing entry, metadata is kept and the old secret goes to its history.
+    pub fn update_password(&mut self, name: &str, password: &str, key: &str) -> Result<(), Error> {
+        let cypher = encrypt(password.as_bytes(), key).or(Err(AES))?;
+        let p = self.get_cypher_mut(name).ok_or(PassNotFound)?;
+        p.replace_cypher(cypher);
+        Ok(())
+    }
+
+    /// Previous secrets of an entry, newest first.
+    pub fn get_versions(&self, name: &str) -> Result<&[Version], Error> {
+        let p = self.get_cypher(name).ok_or(PassNotFound)?;
+        Ok(&p.history)
+    }
+
+    /// Decrypts the previous secret at `version`, an index into `get_versions`.
+    /// Versions are encrypted with the key in use when they were current.
+    pub fn get_password_version(&self, name: &str, version: usize, key: &str) -> Result<String, Error> {
+        let p = self.get_cypher(name).ok_or(PassNotFound)?;
+        let v = p.history.get(version).ok_or(VersionNotFound)?;
+        let password = decrypt(&v.cypher, key).or(Err(IncorrectPass))?;
+        String::from_utf8(password).or(Err(AES))
+    }
+
+    /// Makes the previous secret at `version` current again, the current one becomes the newest version.
+    pub fn rollback(&mut self, name: &str, version: usize) -> Result<(), Error> {
+        let p = self.get_cypher_mut(name).ok_or(PassNotFound)?;
+        if version >= p.history.len() { return Err(VersionNotFound) }
+        let v = p.history.remove(version);
+        p.replace_cypher(v.cypher);
+        Ok(())
+    }
+
     pub fn get_metadata(&self, name: &str) -> Result<&PasswordMeta, Error> {
         let p = self.get_cypher(name).ok_or(Error::PassNotFound)?;
         Ok(&p.meta)
     }
+    /// Counts as a modification of the entry.
     pub fn get_metadata_mut(&mut self, name: &str) -> Result<&mut PasswordMeta, Error> {
         let p = self.get_cypher_mut(name).ok_or(Error::PassNotFound)?;
+        p.times.modified = now();
         Ok(&mut p.meta)
     }
 
+    pub fn get_times(&self, name: &str) -> Result<&Times, Error> {
+        let p = self.get_cypher(name).ok_or(Error::PassNotFound)?;
+        Ok(&p.times)
+    }
+
     pub fn update_metadata(&mut self, name: &str, meta: PasswordMeta) -> Result<(), Error> {
         let p = self.passwords.get_mut(name).ok_or(Error::PassNotFound)?;
         p.update_meta(meta);
         Ok(())
     }
 
+    /// Gives the entry `name` the name `new_name`, its id and everything else is kept.
+    pub fn rename(&mut self, name: &str, new_name: &str) -> Result<(), Error> {
+        if name == new_name { return if self.contains(name) {Ok(())} else {Err(PassNotFound)} }
+        if self.passwords.contains_key(new_name) { return Err(PassExists) }
+        let mut p = self.passwords.remove(name).ok_or(PassNotFound)?;
+        p.times.modified = now();
+        self.passwords.insert(new_name.to_string(), p);
+        Ok(())
+    }
+
+    pub fn get_id(&self, name: &str) -> Result<Uuid, Error> {
+        let p = self.get_cypher(name).ok_or(PassNotFound)?;
+        Ok(p.id)
+    }
+
+    /// Current name of the entry with the id `id`.
+    pub fn get_name(&self, id: Uuid) -> Option<&str> {
+        self.passwords.iter().find(|(_, p)| p.id == id).map(|(name, _)| name.as_str())
+    }
+
+    /// Moves the entry to the trash, where it is kept for the trash retention period.
     pub fn remove_password(&mut self, name: &str) -> Result<(), Error> {
-        self.remove_cypher(name)
+        let entry = self.passwords.get(name).cloned().ok_or(PassNotFound)?;
+        self.remove_cypher(name)?;
+        let deleted = now();
+        self.trash.push(Trashed{name: name.to_string(), deleted, entry});
+        self.purge_expired(deleted);
+        Ok(())
+    }
+
+    /// Removed entries, oldest first. There can be several with the same name.
+    pub fn get_trash(&self) -> &[Trashed] {
+        &self.trash
+    }
+
+    /// Puts the most recently removed entry called `name` back.
+    pub fn restore(&mut self, name: &str) -> Result<(), Error> {
+        if self.passwords.contains_key(name) { return Err(PassExists) }
+        let index = self.trash.iter().rposition(|t| t.name == name).ok_or(PassNotFound)?;
+        let trashed = self.trash.remove(index);
+        self.passwords.insert(trashed.name, trashed.entry);
+        Ok(())
+    }
+
+    /// Drops every removed entry called `name`.
+    pub fn purge(&mut self, name: &str) -> Result<(), Error> {
+        let len = self.trash.len();
+        self.trash.retain(|t| t.name != name);
+        if self.trash.len() == len { return Err(PassNotFound) }
+        Ok(())
+    }
+
+    pub fn empty_trash(&mut self) {
+        self.trash.clear();
+    }
+
+    pub fn trash_retention(&self) -> Option<u64> {
+        self.trash_retention
+    }
+
+    /// Seconds removed entries are kept, `None` keeps them until they are purged.
+    pub fn set_trash_retention(&mut self, retention: Option<u64>) {
+        self.trash_retention = retention;
+        self.purge_expired(now());
+    }
+
+    /// Drops removed entries older than the retention period at `now`, returns their number.
+    pub fn purge_expired(&mut self, now: u64) -> usize {
+        let Some(retention) = self.trash_retention else { return 0 };
+        let len = self.trash.len();
+        self.trash.retain(|t| t.deleted.saturating_add(retention) > now);
+        len - self.trash.len()
     }
 
     pub fn get_names(&self) -> Keys<'_, String, Password>{
@@ -189,11 +701,330 @@ impl PassTable {
     pub fn contains(&self, name: &str) -> bool {
         self.passwords.contains_key(name)
     }
+
+    pub fn get_names_of_kind(&self, kind: EntryKind) -> Vec<String> {
+        let mut names: Vec<String> = self.passwords.iter()
+            .filter(|(_, p)| p.meta.kind == kind)
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    pub fn get_names_with_tag(&self, tag: &str) -> Vec<String> {
+        let mut names: Vec<String> = self.passwords.iter()
+            .filter(|(_, p)| p.meta.tags.iter().any(|t| t == tag))
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Moves the entry into `group`, see `group_path`.
+    pub fn move_to_group(&mut self, name: &str, group: &str) -> Result<(), Error> {
+        self.get_metadata_mut(name)?.group = group_path(group);
+        Ok(())
+    }
+
+    /// Entries in `group`, with those of its subgroups if `recursive`.
+    pub fn get_names_in_group(&self, group: &str, recursive: bool) -> Vec<String> {
+        let group = group_path(group);
+        let mut names: Vec<String> = self.passwords.iter()
+            .filter(|(_, p)| {
+                let path = group_path(&p.meta.group);
+                path == group || recursive && (group.is_empty() || path.starts_with(&format!("{group}/")))
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Every group holding entries and their parent groups, sorted.
+    pub fn get_groups(&self) -> Vec<String> {
+        let mut groups = std::collections::BTreeSet::new();
+        for p in self.passwords.values() {
+            let path = group_path(&p.meta.group);
+            let mut end = 0;
+            while let Some(slash) = path[end..].find('/') {
+                end += slash;
+                groups.insert(path[..end].to_string());
+                end += 1;
+            }
+            if !path.is_empty() { groups.insert(path); }
+        }
+        groups.into_iter().collect()
+    }
+
+    /// Every tag used by an entry, sorted.
+    pub fn get_tags(&self) -> Vec<String> {
+        let tags: std::collections::BTreeSet<&String> = self.passwords.values().flat_map(|p| &p.meta.tags).collect();
+        tags.into_iter().cloned().collect()
+    }
+
+    /// Groups and entries as a tree, only entries with `tag` if there is one.
+    pub fn get_tree(&self, tag: Option<&str>) -> Group {
+        let mut root = Group::default();
+        let mut entries: Vec<(&String, &Password)> = self.passwords.iter()
+            .filter(|(_, p)| tag.is_none_or(|tag| p.meta.tags.iter().any(|t| t == tag)))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, p) in entries {
+            let mut group = &mut root;
+            for part in group_path(&p.meta.group).split('/').filter(|part| !part.is_empty()) {
+                let index = match group.groups.iter().position(|g| g.name == part) {
+                    Some(index) => index,
+                    None => {
+                        let path = if group.path.is_empty() {part.to_string()} else {format!("{}/{part}", group.path)};
+                        group.groups.push(Group{name: part.to_string(), path, ..Default::default()});
+                        group.groups.len() - 1
+                    }
+                };
+                group = &mut group.groups[index];
+            }
+            group.entries.push(name.clone());
+        }
+        root.sort();
+        root
+    }
+
+    /// Entries expiring by `now + within` with their expiry time, soonest first.
+    /// Those expiring at or before `now` have expired.
+    pub fn get_expiring(&self, now: u64, within: u64) -> Vec<(String, u64)> {
+        let mut expiring: Vec<(String, u64)> = self.passwords.iter()
+            .filter_map(|(name, p)| Some((name.clone(), p.expires()?)))
+            .filter(|(_, expires)| *expires <= now.saturating_add(within))
+            .collect();
+        expiring.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        expiring
+    }
 }
 
 #[cfg(test)]
 mod tests{
     use super::*;
+    use hex_literal::hex;
+    use sha2::Sha512;
+    use aes_gcm_siv::aead::OsRng;
+
+    #[cfg(unix)]
+    #[test]
+    fn to_file_test() -> Result<(), Box<dyn std::error::Error>> {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("passtool-to-file-{}.pt", std::process::id()));
+        fs::write(&path, "old contents")?;
+        let mut pt = PassTable::new();
+        pt.add_password("a", "1", PasswordMeta::default(), "key")?;
+        pt.to_file(&path)?;
+        let mode = fs::metadata(&path)?.permissions().mode();
+        let loaded = PassTable::from_file(&path);
+        let temp_left = Path::new(&format!("{}.tmp", path.display())).exists();
+        fs::remove_file(&path)?;
+        assert_eq!(loaded?, pt);
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(!temp_left);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn vault_lock_test() -> Result<(), Box<dyn std::error::Error>> {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("passtool-lock-{}.pt", std::process::id()));
+        let lock = Path::new(&format!("{}.lock", path.display())).to_path_buf();
+        let first = VaultLock::shared(&path)?;
+        let second = VaultLock::shared(&path)?;
+        assert_eq!(fs::metadata(&lock)?.permissions().mode() & 0o777, 0o600);
+        drop(first);
+        assert!(lock.exists());
+        drop(second);
+        assert!(!lock.exists());
+
+        // writers waiting on a removed lock file take the new one
+        PassTable::new().to_file(&path)?;
+        let writers: Vec<_> = (0..4).map(|writer| {
+            let path = path.clone();
+            std::thread::spawn(move || (0..10).try_for_each(|n| {
+                PassTable::update_file(&path, |table| Ok(table.add_password(&format!("{writer}-{n}"), "x", PasswordMeta::default(), "key")?))
+            }).map_err(|e| e.to_string()))
+        }).collect();
+        for writer in writers {
+            writer.join().unwrap()?;
+        }
+        assert_eq!(PassTable::from_file(&path)?.get_names().count(), 40);
+        assert!(!lock.exists());
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn history_test() -> Result<(), Error> {
+        let mut pt = PassTable::new();
+        pt.add_password("mail", "first", PasswordMeta::new("desc".to_string(), Vec::new()), "key")?;
+        assert!(pt.get_versions("mail")?.is_empty());
+        pt.update_password("mail", "second", "key")?;
+        pt.update_password("mail", "third", "other key")?;
+        assert_eq!(pt.get_versions("mail")?.len(), 2);
+        assert_eq!(pt.get_password_version("mail", 0, "key")?, "second");
+        assert_eq!(pt.get_password_version("mail", 1, "key")?, "first");
+        assert_eq!(pt.get_password_version("mail", 1, "other key"), Err(IncorrectPass));
+        assert_eq!(pt.get_password_version("mail", 2, "key"), Err(VersionNotFound));
+
+        pt.rollback("mail", 1)?;
+        assert_eq!(pt.get_password("mail", "key")?, "first");
+        assert_eq!(pt.get_password_version("mail", 0, "other key")?, "third");
+        assert_eq!(pt.get_password_version("mail", 1, "key")?, "second");
+        assert_eq!(pt.get_versions("mail")?.len(), 2);
+        assert_eq!(pt.rollback("mail", 2), Err(VersionNotFound));
+        assert_eq!(pt.get_metadata("mail")?.description, "desc");
+
+        for i in 0..2 * HISTORY_LEN {
+            pt.update_password("mail", &i.to_string(), "key")?;
+        }
+        assert_eq!(pt.get_versions("mail")?.len(), HISTORY_LEN);
+        assert_eq!(pt.get_password_version("mail", 0, "key")?, (2 * HISTORY_LEN - 2).to_string());
+        assert_eq!(pt, PassTable::from_binary(&pt.encoded()).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn times_test() -> Result<(), Error> {
+        let mut pt = PassTable::new();
+        let start = now();
+        pt.add_password("mail", "first", PasswordMeta::default(), "key")?;
+        let times = *pt.get_times("mail")?;
+        assert!(times.created >= start && times.modified == times.created && times.password_changed == times.created);
+        assert_eq!(times.accessed, 0);
+
+        assert_eq!(pt.access_password("mail", "key")?, "first");
+        assert!(pt.get_times("mail")?.accessed >= start);
+        pt.get_metadata_mut("mail")?.description = "desc".to_string();
+        pt.update_password("mail", "second", "key")?;
+        assert_eq!(pt.get_times("mail")?.created, times.created);
+        assert!(pt.get_times("mail")?.password_changed >= times.password_changed);
+
+        pt.get_metadata_mut("mail")?.expiry = Some(Expiry::MaxAge(100));
+        pt.add_password("bank", "x", PasswordMeta{expiry: Some(Expiry::At(50)), ..Default::default()}, "key")?;
+        pt.add_password("forever", "x", PasswordMeta::default(), "key")?;
+        let changed = pt.get_times("mail")?.password_changed;
+        assert_eq!(pt.get_expiring(changed, 10), vec![("bank".to_string(), 50)]);
+        assert_eq!(pt.get_expiring(changed, 100), vec![("bank".to_string(), 50), ("mail".to_string(), changed + 100)]);
+        assert_eq!(pt.get_expiring(0, 10), Vec::new());
+        Ok(())
+    }
+
+    #[test]
+    fn rename_test() -> Result<(), Error> {
+        let mut pt = PassTable::new();
+        pt.add_password("mail", "secret", PasswordMeta::new("desc".to_string(), Vec::new()), "key")?;
+        pt.add_password("bank", "x", PasswordMeta::default(), "key")?;
+        pt.update_password("mail", "new secret", "key")?;
+        let id = pt.get_id("mail")?;
+        assert_ne!(id, pt.get_id("bank")?);
+
+        pt.rename("mail", "gmail")?;
+        assert!(!pt.contains("mail"));
+        assert_eq!(pt.get_id("gmail")?, id);
+        assert_eq!(pt.get_name(id), Some("gmail"));
+        assert_eq!(pt.get_password("gmail", "key")?, "new secret");
+        assert_eq!(pt.get_password_version("gmail", 0, "key")?, "secret");
+        assert_eq!(pt.get_metadata("gmail")?.description, "desc");
+        assert_eq!(pt.rename("gmail", "bank"), Err(PassExists));
+        assert_eq!(pt.rename("mail", "x"), Err(PassNotFound));
+        pt.rename("gmail", "gmail")?;
+
+        pt.remove_password("gmail")?;
+        assert_eq!(pt.get_name(id), None);
+        assert_eq!(pt.get_trash()[0].entry().id(), id);
+        pt.restore("gmail")?;
+        assert_eq!(pt.get_id("gmail")?, id);
+        Ok(())
+    }
+
+    #[test]
+    fn group_test() -> Result<(), Error> {
+        let mut pt = PassTable::new();
+        pt.add_password("db", "x", PasswordMeta{group: "work/servers".to_string(), tags: vec!["prod".to_string()], ..Default::default()}, "key")?;
+        pt.add_password("mail", "x", PasswordMeta{tags: vec!["prod".to_string(), "personal".to_string()], ..Default::default()}, "key")?;
+        pt.add_password("wiki", "x", PasswordMeta::default(), "key")?;
+        pt.add_password("bank", "x", PasswordMeta::default(), "key")?;
+        pt.move_to_group("wiki", "/work//")?;
+        assert_eq!(pt.get_metadata("wiki")?.group, "work");
+        assert_eq!(pt.move_to_group("nope", "work"), Err(PassNotFound));
+
+        assert_eq!(pt.get_groups(), ["work", "work/servers"]);
+        assert_eq!(pt.get_tags(), ["personal", "prod"]);
+        assert_eq!(pt.get_names_in_group("work", false), ["wiki"]);
+        assert_eq!(pt.get_names_in_group("work", true), ["db", "wiki"]);
+        assert_eq!(pt.get_names_in_group("", false), ["bank", "mail"]);
+        assert_eq!(pt.get_names_in_group("", true).len(), 4);
+        assert_eq!(pt.get_names_with_tag("prod"), ["db", "mail"]);
+
+        let tree = pt.get_tree(None);
+        assert_eq!(tree.entries, ["bank", "mail"]);
+        let servers = tree.find("work/servers").unwrap();
+        assert_eq!((servers.name.as_str(), servers.path.as_str(), &servers.entries[..]), ("servers", "work/servers", &["db".to_string()][..]));
+        assert_eq!(tree.find("work").unwrap().find("servers"), Some(servers));
+        assert_eq!(tree.find("home"), None);
+        let flat: Vec<(&str, &str)> = tree.flatten().into_iter().map(|(group, name)| (group.path.as_str(), name)).collect();
+        assert_eq!(flat, [("work/servers", "db"), ("work", "wiki"), ("", "bank"), ("", "mail")]);
+
+        let tree = pt.get_tree(Some("prod"));
+        assert_eq!(tree.entries, ["mail"]);
+        assert_eq!(tree.find("work").unwrap().entries, Vec::<String>::new());
+        assert_eq!(tree.find("work/servers").unwrap().entries, ["db"]);
+        Ok(())
+    }
+
+    #[test]
+    fn trash_test() -> Result<(), Error> {
+        let mut pt = PassTable::new();
+        pt.add_password("mail", "first", PasswordMeta::default(), "key")?;
+        pt.remove_password("mail")?;
+        assert!(!pt.contains("mail"));
+        pt.add_password("mail", "second", PasswordMeta::default(), "key")?;
+        pt.remove_password("mail")?;
+        assert_eq!(pt.get_trash().iter().map(|t| t.name.as_str()).collect::<Vec<_>>(), ["mail", "mail"]);
+        assert_eq!(pt.remove_password("mail"), Err(PassNotFound));
+
+        pt.restore("mail")?;
+        assert_eq!(pt.get_password("mail", "key")?, "second");
+        assert_eq!(pt.restore("mail"), Err(PassExists));
+        pt.purge("mail")?;
+        assert!(pt.get_trash().is_empty());
+        assert_eq!(pt.purge("mail"), Err(PassNotFound));
+        assert_eq!(pt.restore("bank"), Err(PassNotFound));
+
+        pt.remove_password("mail")?;
+        let deleted = pt.get_trash()[0].deleted;
+        assert_eq!(pt.purge_expired(deleted + DEFAULT_TRASH_RETENTION - 1), 0);
+        assert_eq!(pt, PassTable::from_binary(&pt.encoded()).unwrap());
+        pt.set_trash_retention(None);
+        assert_eq!(pt.purge_expired(u64::MAX), 0);
+        pt.set_trash_retention(Some(DEFAULT_TRASH_RETENTION));
+        assert_eq!(pt.purge_expired(deleted + DEFAULT_TRASH_RETENTION), 1);
+        assert!(pt.get_trash().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn expired_trash_test() -> Result<(), Box<dyn std::error::Error>> {
+        let path = std::env::temp_dir().join(format!("passtool-expired-{}.pt", std::process::id()));
+        let mut pt = PassTable::new();
+        pt.add_password("old", "1", PasswordMeta::default(), "key")?;
+        pt.add_password("new", "2", PasswordMeta::default(), "key")?;
+        pt.remove_password("old")?;
+        pt.remove_password("new")?;
+        pt.trash[0].deleted = 0;
+        pt.to_file(&path)?;
+        let loaded = PassTable::from_file(&path)?;
+        PassTable::update_file(&path, |_| Ok(()))?;
+        let saved = PassTable::from_binary(&fs::read(&path)?)?;
+        fs::remove_file(&path)?;
+        assert_eq!(loaded.get_trash().iter().map(|t| t.name.as_str()).collect::<Vec<_>>(), ["new"]);
+        assert_eq!(saved.get_trash().len(), 1);
+        Ok(())
+    }
 
     #[test]
     #[ignore]
@@ -1,5 +1,16 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
+#[cfg(windows)]
 mod app;
+mod cli;
 fn main() {
-    app::run();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    #[cfg(windows)]
+    {
+        if args.is_empty() {
+            app::run();
+            return;
+        }
+        unsafe { winapi::um::wincon::AttachConsole(winapi::um::wincon::ATTACH_PARENT_PROCESS); } // release builds have no console of their own
+    }
+    std::process::exit(cli::run(args));
 }
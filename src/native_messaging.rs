@@ -0,0 +1,344 @@
+//! Browser native-messaging host: length-prefixed JSON over stdin/stdout.
+//! Entries are offered for a page when their URL field has the page's origin (or a parent domain of it),
+//! passwords are only handed out once the host is unlocked with the key or an unlock agent holds it.
+use std::{io::{self, Read, Write}, net::IpAddr, path::PathBuf};
+
+use serde::{Serialize, Deserialize};
+
+use crate::PassTable;
+
+/// Loads the vault, with the key once the host is unlocked: a journal can't be read without it.
+pub type Source = Box<dyn Fn(Option<&str>) -> Result<PassTable, Box<dyn std::error::Error>> + Send>;
+
+/// Records that the password of an entry was handed out, with the key the host was unlocked with.
+pub type Recorder = Box<dyn Fn(&str, Option<&str>) -> Result<(), Box<dyn std::error::Error>> + Send>;
+
+/// Browsers refuse bigger messages from a host.
+pub const MAX_OUTGOING: usize = 1024 * 1024;
+pub const MAX_INCOMING: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub struct Origin {
+    pub scheme: String,
+    pub host: String,
+    pub port: u16
+}
+
+impl Origin {
+    /// Origin of `url`, a URL without scheme is taken as https.
+    pub fn parse(url: &str) -> Option<Self> {
+        let (scheme, rest) = url.trim().split_once("://").unwrap_or(("https", url.trim()));
+        let scheme = scheme.to_ascii_lowercase();
+        let authority = rest.split(['/', '?', '#']).next()?;
+        let authority = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(ipv6) => {
+                let (host, rest) = ipv6.split_once(']')?;
+                (host, rest.strip_prefix(':'))
+            }
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None)
+            }
+        };
+        let port = match port {
+            Some(port) => port.parse().ok()?,
+            None => match scheme.as_str() {
+                "http" => 80,
+                "https" => 443,
+                _ => 0
+            }
+        };
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        if host.is_empty() { return None; }
+        Some(Origin{scheme, host, port})
+    }
+
+    /// 0 for the same origin, 1 when the entry is for a parent domain of the page.
+    pub fn rank(&self, entry: &Origin) -> Option<u8> {
+        if self.scheme != entry.scheme || self.port != entry.port { return None; }
+        if self.host == entry.host { return Some(0); }
+        let is_ip = |host: &str| host.parse::<IpAddr>().is_ok();
+        let subdomain = self.host.strip_suffix(&entry.host).is_some_and(|prefix| prefix.ends_with('.'));
+        if subdomain && !is_ip(&self.host) && !is_ip(&entry.host) {Some(1)} else {None}
+    }
+}
+
+/// Entries for the page at `url`, exact origins first.
+pub fn find(table: &PassTable, url: &str) -> Vec<String> {
+    let Some(page) = Origin::parse(url) else { return Vec::new() };
+    let mut found: Vec<(u8, String)> = table.get_names()
+        .filter_map(|name| {
+            let entry = Origin::parse(&table.get_metadata(name).unwrap().url)?;
+            Some((page.rank(&entry)?, name.clone()))
+        })
+        .collect();
+    found.sort();
+    found.into_iter().map(|(_, name)| name).collect()
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Request {
+    Find{url: String},
+    Unlock{key: String},
+    Get{url: String, name: String},
+    Lock
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Candidate {
+    pub name: String,
+    pub username: String,
+    pub url: String
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Response {
+    Ok,
+    Entries{locked: bool, entries: Vec<Candidate>},
+    Credentials{name: String, username: String, password: String},
+    /// The extension has to ask the user for the key and send `unlock`.
+    Locked,
+    Error{message: String}
+}
+
+pub struct Host {
+    source: Source,
+    recorder: Option<Recorder>,
+    key: Option<String>,
+    /// The unlock agent is asked about this vault.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    vault: PathBuf,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    agent: Option<PathBuf>
+}
+
+impl Host {
+    /// Locked host for `vault`, a running unlock agent is asked for passwords until the host gets the key.
+    pub fn new(vault: PathBuf, source: Source) -> Self {
+        #[cfg(unix)]
+        let agent = Some(crate::unlock_agent::default_socket_path());
+        #[cfg(not(unix))]
+        let agent = None;
+        Host{source, recorder: None, key: None, vault, agent}
+    }
+
+    /// Has every password handed out recorded as an access to its entry.
+    pub fn on_access(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    #[cfg(unix)]
+    fn agent_password(&self, name: &str) -> Option<String> {
+        crate::unlock_agent::cached_password(self.agent.as_ref()?, &self.vault, name)
+    }
+
+    #[cfg(not(unix))]
+    fn agent_password(&self, _name: &str) -> Option<String> {
+        None
+    }
+
+    pub fn handle(&mut self, request: Request) -> Response {
+        if request == Request::Lock {
+            self.key = None;
+            return Response::Ok;
+        }
+        // the key to unlock with is checked on the vault it opens
+        let key = match &request {
+            Request::Unlock{key} => Some(key.as_str()),
+            _ => self.key.as_deref()
+        };
+        let table = match (self.source)(key) {
+            Ok(table) => table,
+            Err(e) => return Response::Error{message: e.to_string()}
+        };
+        match request {
+            Request::Find{url} => {
+                let entries = find(&table, &url).into_iter()
+                    .map(|name| {
+                        let meta = table.get_metadata(&name).unwrap();
+                        Candidate{username: meta.username.clone(), url: meta.url.clone(), name}
+                    })
+                    .collect();
+                Response::Entries{locked: self.key.is_none(), entries}
+            }
+            Request::Unlock{key} => {
+                if table.get_names().next().is_none() {
+                    return Response::Error{message: "the vault has no entries to check the key against".to_string()};
+                }
+                if !table.get_names().any(|name| table.get_password(name, &key).is_ok()) {
+                    return Response::Error{message: crate::IncorrectPass.to_string()};
+                }
+                self.key = Some(key);
+                Response::Ok
+            }
+            Request::Get{url, name} => {
+                // only entries offered for the page can be read
+                if !find(&table, &url).contains(&name) {
+                    return Response::Error{message: crate::PassNotFound.to_string()};
+                }
+                let password = match &self.key {
+                    Some(key) => table.get_password(&name, key).ok(),
+                    None => self.agent_password(&name)
+                };
+                let Some(password) = password else { return Response::Locked };
+                if let Some(recorder) = &self.recorder {
+                    // a vault that can't be written doesn't stop the password
+                    let _ = recorder(&name, self.key.as_deref());
+                }
+                Response::Credentials{username: table.get_metadata(&name).unwrap().username.clone(), name, password}
+            }
+            Request::Lock => unreachable!()
+        }
+    }
+}
+
+/// Reads one message, `None` when the browser closed the pipe.
+pub fn read_message<R: Read>(mut input: R) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0u8; 4];
+    match input.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e)
+    }
+    let length = u32::from_ne_bytes(length) as usize;
+    if length > MAX_INCOMING {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "message too long"));
+    }
+    let mut message = vec![0; length];
+    input.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+pub fn write_message<W: Write>(mut output: W, message: &[u8]) -> io::Result<()> {
+    if message.len() > MAX_OUTGOING {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "message too long"));
+    }
+    output.write_all(&(message.len() as u32).to_ne_bytes())?;
+    output.write_all(message)?;
+    output.flush()
+}
+
+/// Answers messages until the browser closes stdin.
+pub fn serve<R: Read, W: Write>(host: &mut Host, mut input: R, mut output: W) -> io::Result<()> {
+    while let Some(message) = read_message(&mut input)? {
+        let response = match serde_json::from_slice(&message) {
+            Ok(request) => host.handle(request),
+            Err(e) => Response::Error{message: format!("invalid request: {e}")}
+        };
+        write_message(&mut output, &serde_json::to_vec(&response)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::PasswordMeta;
+
+    fn origin(scheme: &str, host: &str, port: u16) -> Option<Origin> {
+        Some(Origin{scheme: scheme.to_string(), host: host.to_string(), port})
+    }
+
+    #[test]
+    fn origin_test() {
+        assert_eq!(Origin::parse("https://Mail.Example.com/login?x=1"), origin("https", "mail.example.com", 443));
+        assert_eq!(Origin::parse("example.com"), origin("https", "example.com", 443));
+        assert_eq!(Origin::parse("http://bob@localhost:8080"), origin("http", "localhost", 8080));
+        assert_eq!(Origin::parse("http://[::1]:3000/"), origin("http", "::1", 3000));
+        assert_eq!(Origin::parse("https://"), None);
+        assert_eq!(Origin::parse("https://host:port"), None);
+
+        let page = Origin::parse("https://login.example.com").unwrap();
+        assert_eq!(page.rank(&Origin::parse("https://login.example.com/").unwrap()), Some(0));
+        assert_eq!(page.rank(&Origin::parse("example.com").unwrap()), Some(1));
+        assert_eq!(page.rank(&Origin::parse("http://example.com").unwrap()), None);
+        assert_eq!(page.rank(&Origin::parse("https://ample.com").unwrap()), None);
+        assert_eq!(page.rank(&Origin::parse("https://example.com:8443").unwrap()), None);
+        assert_eq!(Origin::parse("http://10.0.0.1").unwrap().rank(&Origin::parse("http://0.0.1").unwrap()), None);
+    }
+
+    fn table() -> Result<PassTable, Box<dyn std::error::Error>> {
+        let mut pt = PassTable::new();
+        pt.add_password("example", "p1", PasswordMeta{url: "https://example.com".to_string(), username: "bob".to_string(), ..Default::default()}, "key")?;
+        pt.add_password("login", "p2", PasswordMeta{url: "https://login.example.com/".to_string(), ..Default::default()}, "key")?;
+        pt.add_password("other", "p3", PasswordMeta{url: "https://other.org".to_string(), ..Default::default()}, "key")?;
+        Ok(pt)
+    }
+
+    fn host() -> Host {
+        Host{source: Box::new(|_| table()), recorder: None, key: None, vault: PathBuf::new(), agent: None}
+    }
+
+    fn get(name: &str) -> Request {
+        Request::Get{url: "https://login.example.com/form".to_string(), name: name.to_string()}
+    }
+
+    #[test]
+    fn handle_test() {
+        let mut host = host();
+        let accessed = Arc::new(Mutex::new(Vec::new()));
+        let recorded = accessed.clone();
+        host.on_access(Box::new(move |name, _| {
+            recorded.lock().unwrap().push(name.to_string());
+            Ok(())
+        }));
+        let Response::Entries{locked, entries} = host.handle(Request::Find{url: "https://login.example.com/form".to_string()}) else { panic!() };
+        assert!(locked);
+        assert_eq!(entries.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), ["login", "example"]);
+        assert_eq!(entries[1].username, "bob");
+
+        assert_eq!(host.handle(get("login")), Response::Locked);
+        assert!(matches!(host.handle(Request::Unlock{key: "wrong".to_string()}), Response::Error{..}));
+        assert_eq!(host.handle(Request::Unlock{key: "key".to_string()}), Response::Ok);
+        assert_eq!(host.handle(get("example")), Response::Credentials{name: "example".to_string(), username: "bob".to_string(), password: "p1".to_string()});
+        assert_eq!(host.handle(get("other")), Response::Error{message: "password not found".to_string()});
+        assert_eq!(host.handle(Request::Lock), Response::Ok);
+        assert_eq!(host.handle(get("login")), Response::Locked);
+        assert_eq!(*accessed.lock().unwrap(), ["example"]);
+    }
+
+    #[test]
+    fn unlock_test() {
+        let mut host = Host{source: Box::new(|_| Ok(PassTable::new())), recorder: None, key: None, vault: PathBuf::new(), agent: None};
+        let unlock = || Request::Unlock{key: "key".to_string()};
+        assert_eq!(host.handle(unlock()), Response::Error{message: "the vault has no entries to check the key against".to_string()});
+
+        // like a journal, which needs the key to be read
+        host.source = Box::new(|key| match key {
+            Some("key") => table(),
+            Some(_) => Err(crate::IncorrectPass.into()),
+            None => Err("the vault is locked".into())
+        });
+        let find = || Request::Find{url: "https://example.com".to_string()};
+        assert_eq!(host.handle(find()), Response::Error{message: "the vault is locked".to_string()});
+        assert_eq!(host.handle(Request::Unlock{key: "wrong".to_string()}), Response::Error{message: "incorrect password".to_string()});
+        assert_eq!(host.handle(unlock()), Response::Ok);
+        assert!(matches!(host.handle(find()), Response::Entries{locked: false, ..}));
+        assert!(matches!(host.handle(get("example")), Response::Credentials{..}));
+    }
+
+    #[test]
+    fn serve_test() -> io::Result<()> {
+        let mut input = Vec::new();
+        for message in [r#"{"action":"unlock","key":"key"}"#, "{", r#"{"action":"get","url":"example.com","name":"example"}"#] {
+            input.extend((message.len() as u32).to_ne_bytes());
+            input.extend(message.as_bytes());
+        }
+        let mut output = Vec::new();
+        serve(&mut host(), input.as_slice(), &mut output)?;
+
+        let mut output = output.as_slice();
+        let mut responses = Vec::new();
+        while let Some(message) = read_message(&mut output)? {
+            responses.push(String::from_utf8(message).unwrap());
+        }
+        assert_eq!(responses[0], r#"{"status":"ok"}"#);
+        assert!(responses[1].starts_with(r#"{"status":"error","message":"invalid request"#));
+        assert_eq!(responses[2], r#"{"status":"credentials","name":"example","username":"bob","password":"p1"}"#);
+        Ok(())
+    }
+}
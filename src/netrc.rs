@@ -0,0 +1,267 @@
+use core::fmt;
+use std::{fs, path::Path};
+
+use crate::{PassTable, PasswordMeta};
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    MissingValue(String),
+    UnexpectedToken(String),
+    UnterminatedQuote
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingValue(keyword) => write!(f, "missing value after '{keyword}'"),
+            Self::UnexpectedToken(token) => write!(f, "unexpected token '{token}'"),
+            Self::UnterminatedQuote => f.write_str("unterminated quoted token")
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A single `machine` (or `default` when `host` is `None`) record.
+#[derive(Debug, Default, PartialEq)]
+pub struct Machine {
+    pub host: Option<String>,
+    pub login: Option<String>,
+    pub password: Option<String>,
+    pub account: Option<String>
+}
+
+fn tokenize(contents: &str) -> Result<Vec<String>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut lines = contents.lines();
+    while let Some(line) = lines.next() {
+        let mut chars = line.chars().peekable();
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            let Some(&c) = chars.peek() else { break };
+            if c == '#' { break; }
+            let mut token = String::new();
+            if c == '"' {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => token.push(chars.next().ok_or(ParseError::UnterminatedQuote)?),
+                        Some(c) => token.push(c),
+                        None => return Err(ParseError::UnterminatedQuote)
+                    }
+                }
+            }
+            else {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) { token.push(c); }
+            }
+            if token == "macdef" {
+                // macro body runs until the next empty line
+                for line in lines.by_ref() {
+                    if line.trim().is_empty() { break; }
+                }
+                break;
+            }
+            tokens.push(token);
+        }
+    }
+    Ok(tokens)
+}
+
+pub fn parse(contents: &str) -> Result<Vec<Machine>, ParseError> {
+    let mut machines: Vec<Machine> = Vec::new();
+    let mut tokens = tokenize(contents)?.into_iter();
+    while let Some(token) = tokens.next() {
+        let mut value = || tokens.next().ok_or(ParseError::MissingValue(token.clone()));
+        match token.as_str() {
+            "machine" => machines.push(Machine{host: Some(value()?), ..Default::default()}),
+            "default" => machines.push(Machine::default()),
+            "login" | "password" | "account" => {
+                let value = value()?;
+                let machine = machines.last_mut().ok_or(ParseError::UnexpectedToken(token.clone()))?;
+                match token.as_str() {
+                    "login" => machine.login = Some(value),
+                    "password" => machine.password = Some(value),
+                    _ => machine.account = Some(value)
+                }
+            }
+            _ => return Err(ParseError::UnexpectedToken(token))
+        }
+    }
+    Ok(machines)
+}
+
+/// Tag of the entry imported from the `default` record, it is exported as `default` again.
+pub const DEFAULT_TAG: &str = "netrc:default";
+
+/// Name of the entry a record is imported as: `login@host`, or just the host.
+pub fn entry_name(machine: &Machine) -> String {
+    let host = machine.host.as_deref().unwrap_or("default");
+    match &machine.login {
+        Some(login) => format!("{login}@{host}"),
+        None => host.to_string()
+    }
+}
+
+/// Adds every record with a password as an entry encrypted with `key`.
+/// Nothing is added if any of the entries already exists or two records have the same name.
+pub fn import(table: &mut PassTable, contents: &str, key: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let machines: Vec<Machine> = parse(contents)?.into_iter().filter(|m| m.password.is_some()).collect();
+    let names: Vec<String> = machines.iter().map(entry_name).collect();
+    let mut unique = std::collections::HashSet::new();
+    if names.iter().any(|name| table.contains(name) || !unique.insert(name)) {
+        return Err(Box::new(crate::PassExists));
+    }
+
+    for (machine, name) in machines.into_iter().zip(&names) {
+        let meta = PasswordMeta {
+            description: "Imported from .netrc".to_string(),
+            username: machine.login.unwrap_or_default(),
+            tags: if machine.host.is_none() { vec![DEFAULT_TAG.to_string()] } else { Vec::new() },
+            url: machine.host.unwrap_or_default(),
+            ..Default::default()
+        };
+        table.add_password(name, machine.password.as_deref().unwrap_or_default(), meta, key)?;
+    }
+    Ok(names)
+}
+
+pub fn import_file<P: AsRef<Path>>(table: &mut PassTable, filename: P, key: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    import(table, &fs::read_to_string(filename)?, key)
+}
+
+fn quote(token: &str) -> String {
+    if !token.is_empty() && !token.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\' || c == '#') {
+        return token.to_string();
+    }
+    let escaped: String = token.chars().flat_map(|c| match c {
+        '"' | '\\' => vec!['\\', c],
+        c => vec![c]
+    }).collect();
+    format!("\"{escaped}\"")
+}
+
+/// Host part of an entry URL: `https://user@example.com:8080/path` -> `example.com`.
+fn host_of(url: &str) -> &str {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let url = url.split(['/', '?', '#']).next().unwrap_or_default();
+    let url = url.rsplit_once('@').map_or(url, |(_, host)| host);
+    url.split(':').next().unwrap_or_default()
+}
+
+/// Renders the given entries as `.netrc` records, decrypting them with `key`.
+/// An entry tagged `DEFAULT_TAG` becomes the `default` record, which has to come last.
+pub fn export(table: &PassTable, names: &[String], key: &str) -> Result<String, crate::Error> {
+    let mut contents = String::new();
+    let mut names: Vec<(&String, bool)> = names.iter()
+        .map(|name| Ok((name, table.get_metadata(name)?.tags.iter().any(|tag| tag == DEFAULT_TAG))))
+        .collect::<Result<_, crate::Error>>()?;
+    names.sort_by_key(|(_, default)| *default);
+    for (name, default) in names {
+        let meta = table.get_metadata(name)?;
+        let password = table.get_password(name, key)?;
+        let host = if meta.url.is_empty() {name.as_str()} else {host_of(&meta.url)};
+        if default {
+            contents.push_str("default");
+        } else {
+            contents.push_str(&format!("machine {}", quote(host)));
+        }
+        if !meta.username.is_empty() {
+            contents.push_str(&format!(" login {}", quote(&meta.username)));
+        }
+        contents.push_str(&format!(" password {}\n", quote(&password)));
+    }
+    Ok(contents)
+}
+
+/// Same as `export`, the file is created with 0600 permissions.
+pub fn export_file<P: AsRef<Path>>(table: &PassTable, names: &[String], key: &str, filename: P) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = export(table, names, key)?;
+    crate::write_private(filename, contents.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NETRC: &str = "# build credentials
+machine example.com login alice password s3cret
+machine api.example.com
+    login bot
+    password \"with space \\\" quote\"
+    account ci
+
+macdef init
+cd /pub
+binary
+
+machine nopass.example.com login nobody
+default login anonymous password guest
+";
+
+    #[test]
+    fn parse_test() -> Result<(), ParseError> {
+        let machines = parse(NETRC)?;
+        assert_eq!(machines.len(), 4);
+        assert_eq!(machines[0], Machine{host: Some("example.com".to_string()), login: Some("alice".to_string()), password: Some("s3cret".to_string()), account: None});
+        assert_eq!(machines[1].password.as_deref(), Some("with space \" quote"));
+        assert_eq!(machines[1].account.as_deref(), Some("ci"));
+        assert_eq!(machines[2].password, None);
+        assert_eq!(machines[3].host, None);
+        assert_eq!(parse("login alice"), Err(ParseError::UnexpectedToken("login".to_string())));
+        assert_eq!(parse("machine"), Err(ParseError::MissingValue("machine".to_string())));
+        Ok(())
+    }
+
+    #[test]
+    fn import_export_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pt = PassTable::new();
+        let names = import(&mut pt, NETRC, "key")?;
+        assert_eq!(names, vec!["alice@example.com", "bot@api.example.com", "anonymous@default"]);
+        assert_eq!(pt.get_metadata("bot@api.example.com")?.username, "bot");
+        assert_eq!(pt.get_metadata("bot@api.example.com")?.url, "api.example.com");
+        assert_eq!(pt.get_password("bot@api.example.com", "key")?, "with space \" quote");
+        assert!(import(&mut pt, NETRC, "key").is_err());
+
+        let exported = export(&pt, &names[..2], "key")?;
+        assert_eq!(exported, "machine example.com login alice password s3cret\nmachine api.example.com login bot password \"with space \\\" quote\"\n");
+        let reparsed = parse(&exported)?;
+        assert_eq!(reparsed[1].password.as_deref(), Some("with space \" quote"));
+        // the default record stays last
+        let exported = export(&pt, &[names[2].clone(), names[0].clone()], "key")?;
+        assert_eq!(exported, "machine example.com login alice password s3cret\ndefault login anonymous password guest\n");
+
+        // records with the same name are rejected before anything is added
+        let mut pt = PassTable::new();
+        assert!(import(&mut pt, "machine a.com login x password 1\nmachine b.com password 2\nmachine a.com login x password 3", "key").is_err());
+        assert_eq!(pt.get_names().count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn export_url_host_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pt = PassTable::new();
+        let meta = PasswordMeta{url: "https://bob@git.example.com:8443/repo.git".to_string(), ..Default::default()};
+        pt.add_password("git", "token", meta, "key")?;
+        assert_eq!(export(&pt, &["git".to_string()], "key")?, "machine git.example.com password token\n");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn export_file_permissions_test() -> Result<(), Box<dyn std::error::Error>> {
+        use std::os::unix::fs::PermissionsExt;
+        let mut pt = PassTable::new();
+        import(&mut pt, NETRC, "key")?;
+        let filename = std::env::temp_dir().join(format!("passtool-netrc-{}", std::process::id()));
+        fs::write(&filename, "")?;
+        export_file(&pt, &["alice@example.com".to_string()], "key", &filename)?;
+        let mode = fs::metadata(&filename)?.permissions().mode();
+        let contents = fs::read_to_string(&filename)?;
+        fs::remove_file(&filename)?;
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(contents, "machine example.com login alice password s3cret\n");
+        Ok(())
+    }
+}
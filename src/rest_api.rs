@@ -0,0 +1,465 @@
+//! Opt-in HTTP API on 127.0.0.1 for local tooling.
+//! Requests carry `Authorization: Bearer <token>`, every token is limited to named entries or tags,
+//! only SHA-256 hashes of the tokens are stored and every request is appended to an audit log.
+//!
+//! `GET /entries[?q=text]` lists, `GET /entries/{name}` reads, `POST /entries` adds
+//! and `PUT /entries/{name}` updates entries, the vault is accessed under its `VaultLock`.
+use std::{fs, io::{self, Write}, path::{Path, PathBuf}, sync::Mutex, time::{SystemTime, UNIX_EPOCH}};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::{store, PasswordMeta};
+
+pub const DEFAULT_PORT: u16 = 8731;
+
+/// What a token may see and change.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Scope {
+    /// Every entry, regardless of `entries` and `tags`.
+    #[serde(default)]
+    pub all: bool,
+    #[serde(default)]
+    pub entries: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Allows adding and updating entries within the scope.
+    #[serde(default)]
+    pub write: bool
+}
+
+impl Scope {
+    pub fn allows(&self, name: &str, meta: &PasswordMeta) -> bool {
+        self.all || self.entries.iter().any(|entry| entry == name) || meta.tags.iter().any(|tag| self.tags.contains(tag))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Token {
+    /// Shown in the audit log.
+    pub name: String,
+    /// Hex SHA-256 of the bearer token.
+    pub hash: String,
+    pub scope: Scope
+}
+
+impl Token {
+    /// Creates a token and returns it with the bearer value, which is not stored anywhere.
+    pub fn generate(name: &str, scope: Scope) -> (Self, String) {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let secret = format!("pt_{}", URL_SAFE_NO_PAD.encode(bytes));
+        (Token{name: name.to_string(), hash: hash(&secret), scope}, secret)
+    }
+}
+
+fn hash(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes()).iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// `<vault>.tokens.json`
+pub fn tokens_path(vault: &Path) -> PathBuf {
+    let mut path = vault.as_os_str().to_owned();
+    path.push(".tokens.json");
+    PathBuf::from(path)
+}
+
+/// `<vault>.audit.log`
+pub fn audit_path(vault: &Path) -> PathBuf {
+    let mut path = vault.as_os_str().to_owned();
+    path.push(".audit.log");
+    PathBuf::from(path)
+}
+
+/// Opens the audit log for appending, only the current user can read it (mode 0600 on unix).
+pub fn open_audit<P: AsRef<Path>>(path: P) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        let file = options.open(path)?;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?; // mode is ignored for existing files
+        Ok(file)
+    }
+    #[cfg(not(unix))]
+    options.open(path)
+}
+
+/// Tokens stored in `path`, none if the file does not exist.
+pub fn load_tokens<P: AsRef<Path>>(path: P) -> Result<Vec<Token>, Box<dyn std::error::Error>> {
+    match fs::read(path) {
+        Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into())
+    }
+}
+
+pub fn save_tokens<P: AsRef<Path>>(path: P, tokens: &[Token]) -> Result<(), Box<dyn std::error::Error>> {
+    Ok(crate::write_private(path, &serde_json::to_vec_pretty(tokens)?)?)
+}
+
+/// Decodes `%XX` escapes, a `+` stays as it is.
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        }
+        else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct EntryInput {
+    name: Option<String>,
+    password: Option<String>,
+    username: Option<String>,
+    url: Option<String>,
+    description: Option<String>,
+    tags: Option<Vec<String>>,
+    group: Option<String>
+}
+
+impl EntryInput {
+    fn apply(self, meta: &mut PasswordMeta) {
+        if let Some(username) = self.username { meta.username = username; }
+        if let Some(url) = self.url { meta.url = url; }
+        if let Some(description) = self.description { meta.description = description; }
+        if let Some(tags) = self.tags { meta.tags = tags; }
+        if let Some(group) = self.group { meta.group = crate::group_path(&group); }
+    }
+}
+
+fn entry_json(name: &str, meta: &PasswordMeta) -> Value {
+    json!({"name": name, "username": meta.username, "url": meta.url, "description": meta.description, "tags": meta.tags, "group": meta.group})
+}
+
+fn matches(name: &str, meta: &PasswordMeta, query: &str) -> bool {
+    let query = query.to_lowercase();
+    [name, &meta.username, &meta.url, &meta.description].iter().any(|field| field.to_lowercase().contains(&query)) ||
+        meta.tags.iter().any(|tag| tag.to_lowercase() == query)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Reply {
+    pub status: u16,
+    pub body: Value
+}
+
+impl Reply {
+    fn new(status: u16, body: Value) -> Self {
+        Reply{status, body}
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Reply{status, body: json!({"error": message})}
+    }
+}
+
+impl From<crate::Error> for Reply {
+    fn from(e: crate::Error) -> Self {
+        let status = match e {
+            crate::PassNotFound | crate::VersionNotFound => 404,
+            crate::PassExists => 409,
+            crate::IncorrectPass => 403,
+            crate::AES => 500
+        };
+        Reply::error(status, &e.to_string())
+    }
+}
+
+impl From<Box<dyn std::error::Error>> for Reply {
+    fn from(e: Box<dyn std::error::Error>) -> Self {
+        match e.downcast::<crate::Error>() {
+            Ok(e) => Reply::from(*e),
+            Err(e) => Reply::error(500, &e.to_string())
+        }
+    }
+}
+
+pub struct Api {
+    vault: PathBuf,
+    key: String,
+    tokens: Vec<Token>,
+    audit: Mutex<Box<dyn Write + Send>>
+}
+
+impl Api {
+    /// Entries are decrypted and encrypted with `key`, `audit` receives one JSON line per request.
+    pub fn new(vault: PathBuf, key: String, tokens: Vec<Token>, audit: Box<dyn Write + Send>) -> Self {
+        Api{vault, key, tokens, audit: Mutex::new(audit)}
+    }
+
+    fn authorize(&self, authorization: Option<&str>) -> Option<&Token> {
+        let secret = authorization?.strip_prefix("Bearer ")?.trim();
+        let hash = hash(secret);
+        self.tokens.iter().find(|token| token.hash == hash)
+    }
+
+    /// Answers one request and records it in the audit log, bodies are never logged.
+    pub fn handle(&self, method: &str, url: &str, authorization: Option<&str>, body: &[u8]) -> Reply {
+        let token = self.authorize(authorization);
+        let reply = match token {
+            Some(token) => self.route(&token.scope, method, url, body).unwrap_or_else(|reply| reply),
+            None => Reply::error(401, "missing or unknown bearer token")
+        };
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let record = json!({"time": time, "token": token.map(|t| t.name.as_str()), "method": method, "path": url, "status": reply.status});
+        let mut audit = self.audit.lock().unwrap();
+        let _ = writeln!(audit, "{record}").and_then(|_| audit.flush());
+        reply
+    }
+
+    fn route(&self, scope: &Scope, method: &str, url: &str, body: &[u8]) -> Result<Reply, Reply> {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let name = match path.strip_prefix("/entries") {
+            Some("") | Some("/") => None,
+            Some(rest) => match rest.strip_prefix('/').and_then(percent_decode) {
+                Some(name) => Some(name),
+                None => return Err(Reply::error(404, "not found"))
+            },
+            None => return Err(Reply::error(404, "not found"))
+        };
+        let input = || -> Result<EntryInput, Reply> {
+            serde_json::from_slice(body).map_err(|e| Reply::error(400, &format!("invalid body: {e}")))
+        };
+        let writable = || if scope.write {Ok(())} else {Err(Reply::error(403, "token is read-only"))};
+
+        match (method, name) {
+            ("GET", None) => {
+                let query = query.split('&').find_map(|pair| pair.strip_prefix("q="))
+                    // form encoding, a space may be sent as `+`
+                    .and_then(|query| percent_decode(&query.replace('+', " ")));
+                let table = store::load(&self.vault, &mut || Ok(self.key.clone()))?;
+                let mut names: Vec<&String> = table.get_names()
+                    .filter(|name| {
+                        let meta = table.get_metadata(name).unwrap();
+                        scope.allows(name, meta) && query.as_ref().is_none_or(|query| matches(name, meta, query))
+                    })
+                    .collect();
+                names.sort();
+                Ok(Reply::new(200, names.into_iter().map(|name| entry_json(name, table.get_metadata(name).unwrap())).collect()))
+            }
+            ("GET", Some(name)) => {
+                let table = store::load(&self.vault, &mut || Ok(self.key.clone()))?;
+                // entries outside the scope look like missing ones
+                if !table.get_metadata(&name).is_ok_and(|meta| scope.allows(&name, meta)) {
+                    return Err(crate::PassNotFound.into());
+                }
+                let mut entry = entry_json(&name, table.get_metadata(&name)?);
+                entry["password"] = table.get_password(&name, &self.key)?.into();
+                store::mark_accessed(&self.vault, &mut || Ok(self.key.clone()), &[&name])?;
+                Ok(Reply::new(200, entry))
+            }
+            ("POST", None) => {
+                writable()?;
+                let mut input = input()?;
+                let (Some(name), Some(password)) = (input.name.take(), input.password.take()) else {
+                    return Err(Reply::error(400, "name and password are required"));
+                };
+                let mut meta = PasswordMeta::default();
+                input.apply(&mut meta);
+                if !scope.allows(&name, &meta) {
+                    return Err(Reply::error(403, "entry is outside of the token's scope"));
+                }
+                let entry = entry_json(&name, &meta);
+                store::update(&self.vault, &mut || Ok(self.key.clone()), |table| Ok(table.add_password(&name, &password, meta, &self.key)?))?;
+                Ok(Reply::new(201, entry))
+            }
+            ("PUT", Some(name)) => {
+                writable()?;
+                let mut input = input()?;
+                if input.name.is_some() {
+                    return Err(Reply::error(400, "entries can't be renamed"));
+                }
+                let password = input.password.take();
+                let entry = store::update(&self.vault, &mut || Ok(self.key.clone()), |table| {
+                    if !table.get_metadata(&name).is_ok_and(|meta| scope.allows(&name, meta)) {
+                        return Err(crate::PassNotFound.into());
+                    }
+                    let mut meta = std::mem::take(table.get_metadata_mut(&name)?);
+                    input.apply(&mut meta);
+                    if !scope.allows(&name, &meta) {
+                        return Err(Box::new(Reply::error(403, "entry would leave the token's scope")) as Box<dyn std::error::Error>);
+                    }
+                    let entry = entry_json(&name, &meta);
+                    *table.get_metadata_mut(&name)? = meta;
+                    if let Some(password) = &password {
+                        table.update_password(&name, password, &self.key)?;
+                    }
+                    Ok(entry)
+                }).map_err(|e| match e.downcast::<Reply>() {
+                    Ok(reply) => *reply,
+                    Err(e) => Reply::from(e)
+                })?;
+                Ok(Reply::new(200, entry))
+            }
+            _ => Err(Reply::error(405, "method not allowed"))
+        }
+    }
+}
+
+impl std::fmt::Display for Reply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for Reply {}
+
+/// Serves `api` on 127.0.0.1:`port` (0 picks a free port) until the process exits.
+/// `bound` is told the port once the socket is listening.
+pub fn serve(api: Api, port: u16, bound: impl FnOnce(u16)) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let server = tiny_http::Server::http(("127.0.0.1", port))?;
+    bound(server.server_addr().to_ip().map_or(port, |addr| addr.port()));
+    for mut request in server.incoming_requests() {
+        let mut body = Vec::new();
+        let reply = match io::Read::read_to_end(request.as_reader(), &mut body) {
+            Ok(_) => {
+                let authorization = request.headers().iter()
+                    .find(|h| h.field.equiv("Authorization"))
+                    .map(|h| h.value.as_str().to_string());
+                api.handle(&request.method().to_string(), request.url(), authorization.as_deref(), &body)
+            }
+            Err(e) => Reply::error(400, &e.to_string())
+        };
+        let mut response = tiny_http::Response::from_data(reply.body.to_string())
+            .with_status_code(reply.status)
+            .with_header(tiny_http::Header::from_bytes("Content-Type", "application/json").unwrap());
+        if reply.status == 401 {
+            response.add_header(tiny_http::Header::from_bytes("WWW-Authenticate", "Bearer").unwrap());
+        }
+        let _ = request.respond(response);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::PassTable;
+
+    #[derive(Clone, Default)]
+    struct Log(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Log {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn api(name: &str) -> (Api, Log, [String; 3]) {
+        let vault = std::env::temp_dir().join(format!("passtool-api-{name}-{}.pt", std::process::id()));
+        let mut pt = PassTable::new();
+        pt.add_password("db", "hunter2", PasswordMeta{tags: vec!["ci".to_string()], username: "app".to_string(), ..Default::default()}, "key").unwrap();
+        pt.add_password("mail", "s3cret", PasswordMeta::default(), "key").unwrap();
+        pt.to_file(&vault).unwrap();
+        let (ci, ci_secret) = Token::generate("ci", Scope{tags: vec!["ci".to_string()], write: true, ..Default::default()});
+        let (mail, mail_secret) = Token::generate("mail", Scope{entries: vec!["mail".to_string()], ..Default::default()});
+        let (all, all_secret) = Token::generate("all", Scope{all: true, ..Default::default()});
+        let log = Log::default();
+        (Api::new(vault, "key".to_string(), vec![ci, mail, all], Box::new(log.clone())), log, [ci_secret, mail_secret, all_secret].map(|s| format!("Bearer {s}")))
+    }
+
+    fn names(reply: &Reply) -> Vec<&str> {
+        reply.body.as_array().unwrap().iter().map(|e| e["name"].as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn scope_test() {
+        let (api, log, [ci, mail, all]) = api("scope");
+        assert_eq!(api.handle("GET", "/entries", None, b"").status, 401);
+        assert_eq!(api.handle("GET", "/entries", Some("Bearer pt_wrong"), b"").status, 401);
+        assert_eq!(names(&api.handle("GET", "/entries", Some(&ci), b"")), ["db"]);
+        assert_eq!(names(&api.handle("GET", "/entries", Some(&mail), b"")), ["mail"]);
+        assert_eq!(names(&api.handle("GET", "/entries", Some(&all), b"")), ["db", "mail"]);
+        assert_eq!(names(&api.handle("GET", "/entries?q=APP", Some(&all), b"")), ["db"]);
+
+        let db = api.handle("GET", "/entries/db", Some(&ci), b"");
+        assert_eq!(db.body["password"], "hunter2");
+        assert_eq!(db.body["username"], "app");
+        let table = PassTable::from_file(&api.vault).unwrap();
+        assert!(table.get_times("db").unwrap().accessed > 0);
+        assert_eq!(table.get_times("mail").unwrap().accessed, 0);
+        assert_eq!(api.handle("GET", "/entries/mail", Some(&ci), b"").status, 404);
+        assert_eq!(api.handle("GET", "/entries/nope", Some(&all), b"").status, 404);
+        assert_eq!(api.handle("DELETE", "/entries/db", Some(&all), b"").status, 405);
+
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        let records: Vec<Value> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(records.len(), 10);
+        assert_eq!(records[0]["token"], Value::Null);
+        assert_eq!(records[6]["token"], "ci");
+        assert_eq!(records[6]["path"], "/entries/db");
+        assert_eq!(records[6]["status"], 200);
+        assert!(!log.contains("hunter2"));
+        fs::remove_file(&api.vault).unwrap();
+    }
+
+    #[test]
+    fn write_test() {
+        let (api, _, [ci, mail, _]) = api("write");
+        assert_eq!(api.handle("POST", "/entries", Some(&mail), br#"{"name": "mail2", "password": "x"}"#).status, 403);
+        assert_eq!(api.handle("POST", "/entries", Some(&ci), br#"{"name": "cache", "password": "x"}"#).status, 403);
+        assert_eq!(api.handle("POST", "/entries", Some(&ci), br#"{"name": "cache", "password": "p", "tags": ["ci"]}"#).status, 201);
+        assert_eq!(api.handle("POST", "/entries", Some(&ci), br#"{"name": "cache", "password": "p", "tags": ["ci"]}"#).status, 409);
+        assert_eq!(api.handle("POST", "/entries", Some(&ci), br#"{"name": "x", "password": "p", "bogus": 1}"#).status, 400);
+        assert_eq!(api.handle("GET", "/entries/cache", Some(&ci), b"").body["password"], "p");
+        // a `+` in the path is part of the name
+        assert_eq!(api.handle("POST", "/entries", Some(&ci), br#"{"name": "c++", "password": "p", "tags": ["ci"]}"#).status, 201);
+        assert_eq!(api.handle("GET", "/entries/c++", Some(&ci), b"").body["name"], "c++");
+
+        assert_eq!(api.handle("PUT", "/entries/cache", Some(&ci), br#"{"password": "rotated", "url": "redis://cache"}"#).status, 200);
+        let cache = api.handle("GET", "/entries/cache", Some(&ci), b"");
+        assert_eq!((cache.body["password"].as_str(), cache.body["url"].as_str()), (Some("rotated"), Some("redis://cache")));
+        assert_eq!(api.handle("PUT", "/entries/cache", Some(&ci), br#"{"tags": []}"#).status, 403);
+        assert_eq!(api.handle("PUT", "/entries/mail", Some(&ci), br#"{"password": "x"}"#).status, 404);
+        assert_eq!(api.handle("PUT", "/entries/mail", Some(&mail), br#"{"password": "x"}"#).status, 403);
+
+        let table = PassTable::from_file(&api.vault).unwrap();
+        assert_eq!(table.get_metadata("cache").unwrap().tags, ["ci"]);
+        assert_eq!(table.get_password("mail", "key").unwrap(), "s3cret");
+        fs::remove_file(&api.vault).unwrap();
+    }
+
+    #[test]
+    fn percent_decode_test() {
+        assert_eq!(percent_decode("my%20entry%2Fx").as_deref(), Some("my entry/x"));
+        assert_eq!(percent_decode("a+b").as_deref(), Some("a+b"));
+        assert_eq!(percent_decode("bad%2"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn open_audit_test() -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("passtool-api-audit-{}.log", std::process::id()));
+        fs::write(&path, "")?;
+        writeln!(open_audit(&path)?, "first")?;
+        writeln!(open_audit(&path)?, "second")?;
+        let mode = fs::metadata(&path)?.permissions().mode();
+        let contents = fs::read_to_string(&path)?;
+        fs::remove_file(&path)?;
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(contents, "first\nsecond\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use std::{io::{self, Read, Write}, process::{Command, Stdio}, thread};
+
+use crate::PassTable;
+
+/// Written in place of secret values when output masking is enabled.
+pub const MASK: &[u8] = b"<concealed by passtool>";
+
+/// Parses a `VAR=entry-name` binding.
+pub fn parse_binding(binding: &str) -> Option<(String, String)> {
+    let (var, entry) = binding.split_once('=')?;
+    if var.is_empty() || entry.is_empty() { return None; }
+    Some((var.to_string(), entry.to_string()))
+}
+
+/// Decrypts the entries bound to environment variables.
+pub fn resolve(table: &PassTable, env: &[(String, String)], key: &str) -> Result<Vec<(String, String)>, crate::Error> {
+    env.iter()
+        .map(|(var, entry)| Ok((var.clone(), table.get_password(entry, key)?)))
+        .collect()
+}
+
+/// Writer that replaces every occurrence of the secrets with `MASK`.
+/// Bytes that may be the start of a secret are held back until it is clear they are not.
+pub struct Masker<W: Write> {
+    output: W,
+    secrets: Vec<Vec<u8>>,
+    pending: Vec<u8>
+}
+
+impl<W: Write> Masker<W> {
+    pub fn new(output: W, secrets: &[String]) -> Self {
+        let mut secrets: Vec<Vec<u8>> = secrets.iter().filter(|s| !s.is_empty()).map(|s| s.as_bytes().to_vec()).collect();
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len())); // longest match wins
+        Masker{output, secrets, pending: Vec::new()}
+    }
+
+    fn drain(&mut self, eof: bool) -> io::Result<()> {
+        let mut masked = Vec::with_capacity(self.pending.len());
+        let mut i = 0;
+        while i < self.pending.len() {
+            let rest = &self.pending[i..];
+            if let Some(secret) = self.secrets.iter().find(|s| rest.starts_with(s)) {
+                masked.extend_from_slice(MASK);
+                i += secret.len();
+            }
+            else if !eof && self.secrets.iter().any(|s| s.starts_with(rest)) {
+                break;
+            }
+            else {
+                masked.push(rest[0]);
+                i += 1;
+            }
+        }
+        self.pending.drain(..i);
+        self.output.write_all(&masked)?;
+        self.output.flush()
+    }
+
+    /// Writes out whatever is held back and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.drain(true)?;
+        Ok(self.output)
+    }
+}
+
+impl<W: Write> Write for Masker<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        self.drain(false)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+fn pipe<R: Read, W: Write>(mut input: R, output: W, secrets: &[String]) -> io::Result<()> {
+    let mut masker = Masker::new(output, secrets);
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = input.read(&mut buf)?;
+        if n == 0 { break; }
+        masker.write_all(&buf[..n])?;
+    }
+    masker.finish()?;
+    Ok(())
+}
+
+/// Runs `program` with the bound entries set in its environment and returns its exit code.
+/// With `mask` the child's stdout and stderr are piped through a `Masker`.
+pub fn run(table: &PassTable, key: &str, env: &[(String, String)], mask: bool, program: &str, args: &[String]) -> Result<i32, Box<dyn std::error::Error>> {
+    spawn(resolve(table, env, key)?, mask, program, args)
+}
+
+/// Same as `run` with the variables already decrypted.
+pub fn spawn(env: Vec<(String, String)>, mask: bool, program: &str, args: &[String]) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut command = Command::new(program);
+    command.args(args).envs(env.iter().map(|(var, value)| (var, value)));
+    if !mask {
+        return Ok(exit_code(command.status()?));
+    }
+
+    let secrets: Vec<String> = env.into_iter().map(|(_, value)| value).collect();
+    let mut child = command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let status = thread::scope(|s| {
+        let out = s.spawn(|| pipe(stdout, io::stdout(), &secrets));
+        let err = s.spawn(|| pipe(stderr, io::stderr(), &secrets));
+        let status = child.wait();
+        let _ = out.join();
+        let _ = err.join();
+        status
+    })?;
+    Ok(exit_code(status))
+}
+
+fn exit_code(status: std::process::ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() { return 128 + signal; }
+    }
+    status.code().unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PasswordMeta;
+
+    fn masked(chunks: &[&str], secrets: &[&str]) -> String {
+        let secrets: Vec<String> = secrets.iter().map(|s| s.to_string()).collect();
+        let mut masker = Masker::new(Vec::new(), &secrets);
+        for chunk in chunks { masker.write_all(chunk.as_bytes()).unwrap(); }
+        String::from_utf8(masker.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn masker_test() {
+        assert_eq!(masked(&["user=bob pass=hunter2\n"], &["hunter2"]), "user=bob pass=<concealed by passtool>\n");
+        assert_eq!(masked(&["pass=hun", "ter2 hunt"], &["hunter2"]), "pass=<concealed by passtool> hunt");
+        assert_eq!(masked(&["abcabc"], &["abc", "abcabc"]), "<concealed by passtool>");
+        assert_eq!(masked(&["nothing here"], &[""]), "nothing here");
+    }
+
+    #[test]
+    fn parse_binding_test() {
+        assert_eq!(parse_binding("DB_PASS=db-prod"), Some(("DB_PASS".to_string(), "db-prod".to_string())));
+        assert_eq!(parse_binding("A=b=c"), Some(("A".to_string(), "b=c".to_string())));
+        assert_eq!(parse_binding("DB_PASS"), None);
+        assert_eq!(parse_binding("=x"), None);
+    }
+
+    #[test]
+    fn resolve_test() -> Result<(), crate::Error> {
+        let mut pt = PassTable::new();
+        pt.add_password("db-prod", "hunter2", PasswordMeta::default(), "key")?;
+        let env = vec![("DB_PASS".to_string(), "db-prod".to_string())];
+        assert_eq!(resolve(&pt, &env, "key")?, vec![("DB_PASS".to_string(), "hunter2".to_string())]);
+        assert_eq!(resolve(&pt, &env, "wrong"), Err(crate::IncorrectPass));
+        assert_eq!(resolve(&pt, &[("X".to_string(), "missing".to_string())], "key"), Err(crate::PassNotFound));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_exit_code_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pt = PassTable::new();
+        pt.add_password("db-prod", "hunter2", PasswordMeta::default(), "key")?;
+        let env = vec![("DB_PASS".to_string(), "db-prod".to_string())];
+        let args = vec!["-c".to_string(), "test \"$DB_PASS\" = hunter2 && exit 7".to_string()];
+        assert_eq!(run(&pt, "key", &env, false, "sh", &args)?, 7);
+        assert_eq!(run(&pt, "key", &env, true, "sh", &args)?, 7);
+        assert!(std::env::var("DB_PASS").is_err());
+        Ok(())
+    }
+}
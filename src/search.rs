@@ -0,0 +1,188 @@
+//! Ranked search over the entries of a table. Queries are words matched fuzzily against the name,
+//! description, username, URL and app paths of an entry, `"quoted phrases"` that have to appear as they are
+//! and filters: `tag:work`, `app:chrome.exe`, `url:github.com` and `group:work/servers`.
+//! Results are ordered by relevance, recently used entries first among similar ones.
+use crate::{group_path, PassTable, Password};
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// Case-insensitive subsequence match of `query` in `text`, consecutive characters and word starts score higher.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<u32> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut score, mut start, mut previous) = (0, 0, None);
+    for c in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let i = start + text[start..].iter().position(|&t| t == c)?;
+        score += 1;
+        if i > 0 && previous == Some(i - 1) { score += 4; }
+        if i == 0 || !text[i - 1].is_alphanumeric() { score += 2; }
+        previous = Some(i);
+        start = i + 1;
+    }
+    Some(score)
+}
+
+#[derive(Default, Debug, PartialEq, Clone)]
+pub struct Query {
+    /// Each has to match one of the searched fields fuzzily.
+    pub words: Vec<String>,
+    /// Each has to be part of one of the searched fields, ignoring case.
+    pub phrases: Vec<String>,
+    pub tags: Vec<String>,
+    /// Parts of an app path, like the executable name.
+    pub apps: Vec<String>,
+    pub urls: Vec<String>,
+    /// The entry has to be in the group or below it.
+    pub groups: Vec<String>
+}
+
+/// Splits at whitespace outside of double quotes, the quotes are removed.
+/// The flag tells whether the token started with a quote.
+fn tokens(query: &str) -> Vec<(String, bool)> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let (mut token, mut quoted) = (String::new(), false);
+        while let Some(c) = chars.next_if(|&c| quoted || !c.is_whitespace()) {
+            if c == '"' { quoted = !quoted; } else { token.push(c); }
+        }
+        tokens.push((token, c == '"'));
+    }
+    tokens
+}
+
+impl Query {
+    /// Unknown `field:value` words are searched as they are, so URLs can be typed in.
+    pub fn parse(query: &str) -> Self {
+        let mut parsed = Query::default();
+        for (token, phrase) in tokens(query) {
+            if token.is_empty() { continue; }
+            if phrase {
+                parsed.phrases.push(token.to_lowercase());
+                continue;
+            }
+            let filter = token.split_once(':').and_then(|(field, value)| match field.to_lowercase().as_str() {
+                "tag" => Some(&mut parsed.tags),
+                "app" => Some(&mut parsed.apps),
+                "url" => Some(&mut parsed.urls),
+                "group" => Some(&mut parsed.groups),
+                _ => None
+            }.map(|filter| (filter, value.to_lowercase())));
+            match filter {
+                Some((filter, value)) => filter.push(value),
+                None => parsed.words.push(token)
+            }
+        }
+        parsed
+    }
+
+    /// Relevance of the entry, `None` if it doesn't match.
+    pub fn score(&self, name: &str, entry: &Password) -> Option<u32> {
+        let meta = entry.meta();
+        let contains = |text: &str, part: &str| text.to_lowercase().contains(part);
+        let group = group_path(&meta.group).to_lowercase();
+        let filtered = self.tags.iter().all(|tag| meta.tags.iter().any(|t| t.to_lowercase() == *tag)) &&
+            self.apps.iter().all(|app| meta.apps.iter().any(|a| contains(a, app))) &&
+            self.urls.iter().all(|url| contains(&meta.url, url)) &&
+            self.groups.iter().all(|g| {
+                let g = group_path(g);
+                group == g || g.is_empty() || group.starts_with(&format!("{g}/"))
+            });
+        if !filtered { return None; }
+
+        let fields: Vec<&str> = [&meta.description, &meta.username, &meta.url].into_iter().chain(&meta.apps).map(String::as_str).collect();
+        let mut score = 0;
+        for word in &self.words {
+            // a match in the name counts double
+            let name = fuzzy_score(word, name).map(|score| score * 2);
+            score += fields.iter().filter_map(|text| fuzzy_score(word, text)).chain(name).max()?;
+        }
+        for phrase in &self.phrases {
+            score += if contains(name, phrase) {20} else if fields.iter().any(|text| contains(text, phrase)) {10} else { return None };
+        }
+        Some(score)
+    }
+}
+
+/// Small bonus for entries used in the last month.
+fn recency(entry: &Password, now: u64) -> u32 {
+    let accessed = entry.times().accessed;
+    if accessed == 0 { return 0; }
+    match now.saturating_sub(accessed) {
+        age if age < DAY => 3,
+        age if age < 7 * DAY => 2,
+        age if age < 30 * DAY => 1,
+        _ => 0
+    }
+}
+
+/// Names of the entries matching `query`, best first.
+pub fn search(table: &PassTable, query: &Query) -> Vec<String> {
+    let now = crate::now();
+    let mut found: Vec<(u32, u64, &String)> = table.get_names()
+        .filter_map(|name| {
+            let entry = table.get_entry(name).unwrap();
+            Some((query.score(name, entry)? + recency(entry, now), entry.times().accessed, name))
+        })
+        .collect();
+    found.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| b.1.cmp(&a.1)).then_with(|| a.2.cmp(b.2)));
+    found.into_iter().map(|(_, _, name)| name.clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PasswordMeta;
+
+    #[test]
+    fn fuzzy_test() {
+        assert_eq!(fuzzy_score("", "github"), Some(0));
+        assert!(fuzzy_score("gh", "github").is_some());
+        assert_eq!(fuzzy_score("hg", "github"), None);
+        assert!(fuzzy_score("git", "github") > fuzzy_score("git", "gmail IT"));
+        assert!(fuzzy_score("Mail", "gmail").is_some());
+    }
+
+    #[test]
+    fn parse_test() {
+        let query = Query::parse(r#"tag:work app:Chrome.exe  url:github.com "exact Phrase" group:"my group" https://x.org gh"#);
+        assert_eq!(query, Query{
+            words: vec!["https://x.org".to_string(), "gh".to_string()],
+            phrases: vec!["exact phrase".to_string()],
+            tags: vec!["work".to_string()],
+            apps: vec!["chrome.exe".to_string()],
+            urls: vec!["github.com".to_string()],
+            groups: vec!["my group".to_string()]
+        });
+        assert_eq!(Query::parse("  "), Query::default());
+    }
+
+    #[test]
+    fn search_test() -> Result<(), crate::Error> {
+        let mut pt = PassTable::new();
+        pt.add_password("github", "x", PasswordMeta{url: "https://github.com".to_string(), tags: vec!["work".to_string()],
+            apps: vec![r"C:\Program Files\Google\Chrome\chrome.exe".to_string()], ..Default::default()}, "key")?;
+        pt.add_password("gitlab", "x", PasswordMeta{description: "self hosted git".to_string(), group: "work/servers".to_string(), ..Default::default()}, "key")?;
+        pt.add_password("mail", "x", PasswordMeta{username: "git@example.com".to_string(), ..Default::default()}, "key")?;
+        pt.add_password("bank", "x", PasswordMeta::default(), "key")?;
+
+        let search = |query: &str| search(&pt, &Query::parse(query));
+        assert_eq!(search("git"), ["github", "gitlab", "mail"]);
+        assert_eq!(search("tag:work"), ["github"]);
+        assert_eq!(search("app:chrome.exe url:github.com"), ["github"]);
+        assert_eq!(search("url:gitlab.com"), Vec::<String>::new());
+        assert_eq!(search("\"hosted git\""), ["gitlab"]);
+        assert_eq!(search("\"git hosted\""), Vec::<String>::new());
+        assert_eq!(search("group:work git"), ["gitlab"]);
+        assert_eq!(search(""), ["bank", "github", "gitlab", "mail"]);
+
+        // recently used entries win ties
+        pt.mark_accessed("mail")?;
+        let search = |query: &str| super::search(&pt, &Query::parse(query));
+        assert_eq!(search(""), ["mail", "bank", "github", "gitlab"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,708 @@
+//! freedesktop Secret Service provider (`org.freedesktop.secrets`) backed by a vault.
+//! Every entry is an item of the single `passtool` collection, which is also the `default` alias.
+//! Item attributes are kept in the entry metadata, the key is held while the service is unlocked.
+//! Items created through the service are tagged `secret-service`.
+use std::{collections::{BTreeMap, HashMap, HashSet}, path::PathBuf, sync::{Arc, Mutex}, thread};
+
+use cbc::cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use hex_literal::hex;
+use hkdf::Hkdf;
+use rand::Rng;
+use rsa::BigUint;
+use sha2::Sha256;
+use zbus::{fdo, interface, message::Header, object_server::SignalEmitter, zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value}, Connection, ObjectServer};
+use zeroize::Zeroize;
+
+use crate::{store, DerivedKey, PassTable, PasswordMeta};
+
+pub const BUS_NAME: &str = "org.freedesktop.secrets";
+pub const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+pub const COLLECTION_PATH: &str = "/org/freedesktop/secrets/collection/passtool";
+pub const DEFAULT_ALIAS_PATH: &str = "/org/freedesktop/secrets/aliases/default";
+
+pub const TAG: &str = "secret-service";
+
+pub const PLAIN: &str = "plain";
+pub const DH_AES: &str = "dh-ietf1024-sha256-aes128-cbc-pkcs7";
+
+/// Second Oakley group (RFC 2409), the generator is 2.
+const DH_PRIME: [u8; 128] = hex!("
+    FFFFFFFF FFFFFFFF C90FDAA2 2168C234 C4C6628B 80DC1CD1 29024E08 8A67CC74
+    020BBEA6 3B139B22 514A0879 8E3404DD EF9519B3 CD3A431B 302B0A6D F25F1437
+    4FE1356D 6D51C245 E485B576 625E7EC6 F44C42E9 A637ED6B 0BFF5CB6 F406B7ED
+    EE386BFB 5A899FA5 AE9F2411 7C4B1FE6 49286651 ECE65381 FFFFFFFF FFFFFFFF
+");
+
+/// `(session, parameters, value, content type)` as sent over the bus.
+pub type Secret = (OwnedObjectPath, Vec<u8>, Vec<u8>, String);
+
+#[derive(Debug, zbus::DBusError)]
+#[zbus(prefix = "org.freedesktop.Secret.Error")]
+pub enum Error {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    IsLocked(String),
+    NoSession(String),
+    NoSuchObject(String),
+    NotSupported(String),
+    Failed(String)
+}
+
+impl From<crate::Error> for Error {
+    fn from(e: crate::Error) -> Self {
+        match e {
+            crate::PassNotFound => Error::NoSuchObject(e.to_string()),
+            _ => Error::Failed(e.to_string())
+        }
+    }
+}
+
+impl From<Box<dyn std::error::Error>> for Error {
+    fn from(e: Box<dyn std::error::Error>) -> Self {
+        Error::Failed(e.to_string())
+    }
+}
+
+impl From<zbus::zvariant::Error> for Error {
+    fn from(e: zbus::zvariant::Error) -> Self {
+        Error::ZBus(e.into())
+    }
+}
+
+impl From<Error> for fdo::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::ZBus(e) => e.into(),
+            e => fdo::Error::Failed(e.to_string())
+        }
+    }
+}
+
+fn pad(bytes: Vec<u8>) -> Vec<u8> {
+    let mut padded = vec![0; DH_PRIME.len().saturating_sub(bytes.len())];
+    padded.extend(bytes);
+    padded
+}
+
+/// Diffie-Hellman key pair of the `dh-ietf1024-sha256-aes128-cbc-pkcs7` negotiation.
+pub struct KeyPair {
+    private: BigUint,
+    pub public: Vec<u8>
+}
+
+impl KeyPair {
+    pub fn generate() -> Self {
+        let prime = BigUint::from_bytes_be(&DH_PRIME);
+        let mut bytes = [0u8; 128];
+        rand::thread_rng().fill(&mut bytes[..]);
+        let private = BigUint::from_bytes_be(&bytes) % &prime;
+        bytes.zeroize();
+        let public = pad(BigUint::from(2u32).modpow(&private, &prime).to_bytes_be());
+        KeyPair{private, public}
+    }
+
+    /// AES session agreed on with the peer's public key.
+    pub fn session(&self, peer: &[u8]) -> Result<Session, Error> {
+        let prime = BigUint::from_bytes_be(&DH_PRIME);
+        let peer = BigUint::from_bytes_be(peer);
+        if peer <= BigUint::from(1u32) || peer >= &prime - 1u32 {
+            return Err(Error::Failed("invalid public key".to_string()));
+        }
+        let mut shared = pad(peer.modpow(&self.private, &prime).to_bytes_be());
+        let mut key = [0u8; 16];
+        Hkdf::<Sha256>::new(None, &shared).expand(&[], &mut key).unwrap();
+        shared.zeroize();
+        Ok(Session::Aes(key))
+    }
+}
+
+/// Transport encryption negotiated through `OpenSession`.
+pub enum Session {
+    Plain,
+    Aes([u8; 16])
+}
+
+impl Session {
+    /// Returns the parameters and the value of a secret sent to the client.
+    pub fn encrypt(&self, secret: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        match self {
+            Session::Plain => (Vec::new(), secret.to_vec()),
+            Session::Aes(key) => {
+                let iv: [u8; 16] = rand::random();
+                let value = cbc::Encryptor::<aes::Aes128>::new(key.into(), &iv.into()).encrypt_padded_vec_mut::<Pkcs7>(secret);
+                (iv.to_vec(), value)
+            }
+        }
+    }
+
+    pub fn decrypt(&self, parameters: &[u8], value: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Session::Plain => Ok(value.to_vec()),
+            Session::Aes(key) => cbc::Decryptor::<aes::Aes128>::new_from_slices(key, parameters)
+                .map_err(|_| Error::Failed("invalid parameters".to_string()))?
+                .decrypt_padded_vec_mut::<Pkcs7>(value)
+                .map_err(|_| Error::Failed("invalid secret".to_string()))
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Session::Aes(key) = self { key.zeroize(); }
+    }
+}
+
+/// Path element for an entry name, bytes other than ASCII letters and digits become `_xx`.
+pub fn encode_name(name: &str) -> String {
+    name.bytes().map(|b| if b.is_ascii_alphanumeric() {(b as char).to_string()} else {format!("_{b:02x}")}).collect()
+}
+
+pub fn decode_name(element: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    let mut rest = element.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'_' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        }
+        else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+pub fn item_path(name: &str) -> OwnedObjectPath {
+    ObjectPath::try_from(format!("{COLLECTION_PATH}/{}", encode_name(name))).unwrap().into()
+}
+
+/// Entry name of an item path, also accepted under the `default` alias.
+pub fn item_name(path: &str) -> Option<String> {
+    let element = path.strip_prefix(COLLECTION_PATH).or_else(|| path.strip_prefix(DEFAULT_ALIAS_PATH))?.strip_prefix('/')?;
+    decode_name(element)
+}
+
+fn root() -> OwnedObjectPath {
+    ObjectPath::from_static_str_unchecked("/").into()
+}
+
+/// Entries having all of `attributes`.
+pub fn search(table: &PassTable, attributes: &HashMap<String, String>) -> Vec<String> {
+    let mut names: Vec<String> = table.get_names()
+        .filter(|name| {
+            let meta = table.get_metadata(name).unwrap();
+            attributes.iter().all(|(k, v)| meta.attributes.get(k) == Some(v))
+        })
+        .cloned()
+        .collect();
+    names.sort();
+    names
+}
+
+/// Item created through the service with exactly `attributes`, which `CreateItem` may replace.
+/// Without attributes nothing matches, other entries are never replaced.
+fn replaceable(table: &PassTable, attributes: &BTreeMap<String, String>) -> Option<String> {
+    if attributes.is_empty() { return None; }
+    let mut names: Vec<&String> = table.get_names()
+        .filter(|name| {
+            let meta = table.get_metadata(name).unwrap();
+            meta.tags.iter().any(|tag| tag == TAG) && meta.attributes == *attributes
+        })
+        .collect();
+    names.sort();
+    names.first().map(|name| name.to_string())
+}
+
+/// `label`, or `label (n)` if an entry with that name exists.
+fn unique_name(table: &PassTable, label: &str) -> String {
+    let label = if label.is_empty() {"Secret"} else {label};
+    (1..).map(|n| if n == 1 {label.to_string()} else {format!("{label} ({n})")})
+        .find(|name| !table.contains(name))
+        .unwrap()
+}
+
+struct State {
+    vault: PathBuf,
+    key: Option<String>,
+    askpass: Option<String>,
+    sessions: HashMap<String, Session>,
+    registered: HashSet<String>,
+    counter: u64
+}
+
+type Shared = Arc<Mutex<State>>;
+
+impl State {
+    fn load(&self) -> Result<PassTable, Error> {
+        Ok(store::load(&self.vault, &mut || Ok(self.key()?.to_string()))?)
+    }
+
+    /// Applies `update` to the vault under its exclusive lock, so changes of other writers are kept.
+    fn update<T>(&self, update: impl FnOnce(&mut PassTable) -> Result<T, Error>) -> Result<T, Error> {
+        // the error of `update` is passed through as it is
+        let mut failed = None;
+        let result = store::update(&self.vault, &mut || Ok(self.key()?.to_string()), |table| update(table).map_err(|e| {
+            let message = e.to_string();
+            failed = Some(e);
+            message.into()
+        }));
+        match failed {
+            Some(e) => Err(e),
+            None => Ok(result?)
+        }
+    }
+
+    fn key(&self) -> Result<&str, Error> {
+        self.key.as_deref().ok_or_else(|| Error::IsLocked("the vault is locked".to_string()))
+    }
+
+    fn next_path(&mut self, kind: &str) -> OwnedObjectPath {
+        self.counter += 1;
+        ObjectPath::try_from(format!("{SERVICE_PATH}/{kind}/{}", self.counter)).unwrap().into()
+    }
+
+    /// Whether `key` opens the vault: a journal checks it when it is read, other vaults need an entry it decrypts.
+    /// `None` when nothing in the vault can check it.
+    fn check(&self, key: &str) -> Result<Option<bool>, Error> {
+        let table = match store::load(&self.vault, &mut || Ok(key.to_string())) {
+            Ok(table) => table,
+            Err(e) if e.downcast_ref() == Some(&crate::IncorrectPass) => return Ok(Some(false)),
+            Err(e) => return Err(e.into())
+        };
+        if store::is_journal(&self.vault) { return Ok(Some(true)); }
+        if table.get_names().next().is_none() { return Ok(None); }
+        let derived = DerivedKey::new(key);
+        Ok(Some(table.get_names().any(|name| table.get_password_with(name, &derived).is_ok())))
+    }
+
+    /// Keeps `key` if the vault checks it, a key nothing can check might be mistyped and is refused.
+    fn unlock(&mut self, key: String) -> Result<bool, Error> {
+        if self.check(&key)? != Some(true) { return Ok(false); }
+        self.lock();
+        self.key = Some(key);
+        Ok(true)
+    }
+
+    fn lock(&mut self) {
+        if let Some(mut key) = self.key.take() { key.zeroize(); }
+    }
+
+    fn open(&self, secret: &Secret) -> Result<String, Error> {
+        let session = self.sessions.get(secret.0.as_str()).ok_or_else(|| Error::NoSession(secret.0.to_string()))?;
+        let value = session.decrypt(&secret.1, &secret.2)?;
+        String::from_utf8(value).map_err(|_| Error::NotSupported("only text secrets can be stored".to_string()))
+    }
+
+    fn seal(&self, session: &OwnedObjectPath, name: &str) -> Result<Secret, Error> {
+        let cipher = self.sessions.get(session.as_str()).ok_or_else(|| Error::NoSession(session.to_string()))?;
+        let mut password = self.load()?.get_password(name, self.key()?)?;
+        let (parameters, value) = cipher.encrypt(password.as_bytes());
+        password.zeroize();
+        Ok((session.clone(), parameters, value, "text/plain; charset=utf8".to_string()))
+    }
+}
+
+/// Exports an item object for every entry and drops the ones of removed entries.
+async fn sync_items(server: &ObjectServer, shared: &Shared) -> Result<PassTable, Error> {
+    let (table, stale, fresh) = {
+        let mut state = shared.lock().unwrap();
+        let table = state.load()?;
+        let names: HashSet<String> = table.get_names().cloned().collect();
+        let stale: Vec<String> = state.registered.difference(&names).cloned().collect();
+        let fresh: Vec<String> = names.difference(&state.registered).cloned().collect();
+        state.registered = names;
+        (table, stale, fresh)
+    };
+    for name in stale {
+        let _ = server.remove::<Item, _>(item_path(&name)).await;
+    }
+    for name in fresh {
+        server.at(item_path(&name), Item{shared: shared.clone(), name}).await?;
+    }
+    Ok(table)
+}
+
+struct Service {
+    shared: Shared
+}
+
+#[interface(name = "org.freedesktop.Secret.Service")]
+impl Service {
+    #[zbus(out_args("output", "result"))]
+    async fn open_session(&self, algorithm: &str, input: OwnedValue, #[zbus(object_server)] server: &ObjectServer) -> Result<(OwnedValue, OwnedObjectPath), Error> {
+        let (session, output) = match algorithm {
+            PLAIN => (Session::Plain, OwnedValue::try_from(Value::from(""))?),
+            DH_AES => {
+                let keys = KeyPair::generate();
+                let session = keys.session(&Vec::<u8>::try_from(input)?)?;
+                (session, OwnedValue::try_from(Value::from(keys.public))?)
+            }
+            _ => return Err(Error::NotSupported(format!("unsupported algorithm {algorithm}")))
+        };
+        let path = {
+            let mut state = self.shared.lock().unwrap();
+            let path = state.next_path("session");
+            state.sessions.insert(path.to_string(), session);
+            path
+        };
+        server.at(&path, SessionObject{shared: self.shared.clone()}).await?;
+        Ok((output, path))
+    }
+
+    /// Only the vault collection exists, it is returned for the `default` alias.
+    #[zbus(out_args("collection", "prompt"))]
+    async fn create_collection(&self, _properties: HashMap<String, OwnedValue>, alias: &str) -> Result<(OwnedObjectPath, OwnedObjectPath), Error> {
+        match alias {
+            "" | "default" => Ok((ObjectPath::from_static_str_unchecked(COLLECTION_PATH).into(), root())),
+            _ => Err(Error::NotSupported("only the default collection is available".to_string()))
+        }
+    }
+
+    #[zbus(out_args("unlocked", "locked"))]
+    async fn search_items(&self, attributes: HashMap<String, String>, #[zbus(object_server)] server: &ObjectServer) -> Result<(Vec<OwnedObjectPath>, Vec<OwnedObjectPath>), Error> {
+        let table = sync_items(server, &self.shared).await?;
+        let items = search(&table, &attributes).iter().map(|name| item_path(name)).collect();
+        if self.shared.lock().unwrap().key.is_some() {Ok((items, Vec::new()))} else {Ok((Vec::new(), items))}
+    }
+
+    /// Unlocks right away while the key is held, otherwise through a prompt running the askpass program.
+    #[zbus(out_args("unlocked", "prompt"))]
+    async fn unlock(&self, objects: Vec<OwnedObjectPath>, #[zbus(object_server)] server: &ObjectServer) -> Result<(Vec<OwnedObjectPath>, OwnedObjectPath), Error> {
+        let path = {
+            let mut state = self.shared.lock().unwrap();
+            if state.key.is_some() { return Ok((objects, root())); }
+            if state.askpass.is_none() { return Ok((Vec::new(), root())); }
+            state.next_path("prompt")
+        };
+        server.at(&path, Prompt{shared: self.shared.clone(), objects}).await?;
+        Ok((Vec::new(), path))
+    }
+
+    #[zbus(out_args("locked", "Prompt"))]
+    async fn lock(&self, objects: Vec<OwnedObjectPath>) -> (Vec<OwnedObjectPath>, OwnedObjectPath) {
+        self.shared.lock().unwrap().lock();
+        (objects, root())
+    }
+
+    async fn get_secrets(&self, items: Vec<OwnedObjectPath>, session: OwnedObjectPath) -> Result<HashMap<OwnedObjectPath, Secret>, Error> {
+        let state = self.shared.lock().unwrap();
+        state.key()?;
+        Ok(items.into_iter()
+            .filter_map(|item| {
+                let secret = state.seal(&session, &item_name(item.as_str())?).ok()?;
+                Some((item, secret))
+            })
+            .collect())
+    }
+
+    async fn read_alias(&self, name: &str) -> OwnedObjectPath {
+        if name == "default" {ObjectPath::from_static_str_unchecked(COLLECTION_PATH).into()} else {root()}
+    }
+
+    async fn set_alias(&self, name: &str, collection: OwnedObjectPath) -> Result<(), Error> {
+        if name == "default" && collection.as_str() == COLLECTION_PATH { return Ok(()); }
+        Err(Error::NotSupported("aliases can't be changed".to_string()))
+    }
+
+    #[zbus(property)]
+    async fn collections(&self) -> Vec<OwnedObjectPath> {
+        vec![ObjectPath::from_static_str_unchecked(COLLECTION_PATH).into()]
+    }
+}
+
+#[derive(Clone)]
+struct Collection {
+    shared: Shared
+}
+
+#[interface(name = "org.freedesktop.Secret.Collection")]
+impl Collection {
+    async fn delete(&self) -> Result<OwnedObjectPath, Error> {
+        Err(Error::NotSupported("the vault collection can't be deleted".to_string()))
+    }
+
+    async fn search_items(&self, attributes: HashMap<String, String>, #[zbus(object_server)] server: &ObjectServer) -> Result<Vec<OwnedObjectPath>, Error> {
+        let table = sync_items(server, &self.shared).await?;
+        Ok(search(&table, &attributes).iter().map(|name| item_path(name)).collect())
+    }
+
+    /// The label becomes the entry name, `replace` overwrites the secret of an item created here with the same attributes.
+    #[zbus(out_args("item", "prompt"))]
+    async fn create_item(&self, properties: HashMap<String, OwnedValue>, secret: Secret, replace: bool, #[zbus(object_server)] server: &ObjectServer) -> Result<(OwnedObjectPath, OwnedObjectPath), Error> {
+        let label = match properties.get("org.freedesktop.Secret.Item.Label") {
+            Some(label) => String::try_from(label.try_clone()?)?,
+            None => String::new()
+        };
+        let attributes: BTreeMap<String, String> = match properties.get("org.freedesktop.Secret.Item.Attributes") {
+            Some(attributes) => HashMap::<String, String>::try_from(attributes.try_clone()?)?.into_iter().collect(),
+            None => BTreeMap::new()
+        };
+
+        let name = {
+            let state = self.shared.lock().unwrap();
+            let key = state.key()?;
+            let mut password = state.open(&secret)?;
+            let name = state.update(|table| Ok(match replaceable(table, &attributes).filter(|_| replace) {
+                Some(name) => {
+                    table.update_password(&name, &password, key)?;
+                    name
+                }
+                None => {
+                    let name = unique_name(table, &label);
+                    table.add_password(&name, &password, PasswordMeta{attributes, tags: vec![TAG.to_string()], ..Default::default()}, key)?;
+                    name
+                }
+            }));
+            password.zeroize();
+            name?
+        };
+        sync_items(server, &self.shared).await?;
+        Ok((item_path(&name), root()))
+    }
+
+    #[zbus(property)]
+    async fn items(&self, #[zbus(object_server)] server: &ObjectServer) -> fdo::Result<Vec<OwnedObjectPath>> {
+        let table = sync_items(server, &self.shared).await?;
+        let mut names: Vec<&String> = table.get_names().collect();
+        names.sort();
+        Ok(names.into_iter().map(|name| item_path(name)).collect())
+    }
+
+    #[zbus(property)]
+    async fn label(&self) -> String {
+        "passtool".to_string()
+    }
+
+    #[zbus(property)]
+    async fn locked(&self) -> bool {
+        self.shared.lock().unwrap().key.is_none()
+    }
+
+    #[zbus(property)]
+    async fn created(&self) -> u64 {
+        0
+    }
+
+    #[zbus(property)]
+    async fn modified(&self) -> u64 {
+        0
+    }
+}
+
+struct Item {
+    shared: Shared,
+    name: String
+}
+
+#[interface(name = "org.freedesktop.Secret.Item")]
+impl Item {
+    async fn delete(&self, #[zbus(object_server)] server: &ObjectServer) -> Result<OwnedObjectPath, Error> {
+        {
+            let state = self.shared.lock().unwrap();
+            state.key()?;
+            state.update(|table| Ok(table.remove_password(&self.name)?))?;
+        }
+        sync_items(server, &self.shared).await?;
+        Ok(root())
+    }
+
+    async fn get_secret(&self, session: OwnedObjectPath) -> Result<(Secret,), Error> {
+        Ok((self.shared.lock().unwrap().seal(&session, &self.name)?,))
+    }
+
+    async fn set_secret(&self, secret: Secret) -> Result<(), Error> {
+        let state = self.shared.lock().unwrap();
+        let mut password = state.open(&secret)?;
+        let updated = state.update(|table| Ok(table.update_password(&self.name, &password, state.key()?)?));
+        password.zeroize();
+        updated
+    }
+
+    #[zbus(property)]
+    async fn locked(&self) -> bool {
+        self.shared.lock().unwrap().key.is_none()
+    }
+
+    #[zbus(property)]
+    async fn attributes(&self) -> fdo::Result<HashMap<String, String>> {
+        let table = self.shared.lock().unwrap().load()?;
+        Ok(table.get_metadata(&self.name).map_err(Error::from)?.attributes.clone().into_iter().collect())
+    }
+
+    #[zbus(property)]
+    async fn set_attributes(&mut self, attributes: HashMap<String, String>) -> fdo::Result<()> {
+        let state = self.shared.lock().unwrap();
+        state.key()?;
+        Ok(state.update(|table| {
+            table.get_metadata_mut(&self.name)?.attributes = attributes.into_iter().collect();
+            Ok(())
+        })?)
+    }
+
+    #[zbus(property)]
+    async fn label(&self) -> String {
+        self.name.clone()
+    }
+
+    #[zbus(property)]
+    async fn created(&self) -> u64 {
+        0
+    }
+
+    #[zbus(property)]
+    async fn modified(&self) -> u64 {
+        0
+    }
+}
+
+struct SessionObject {
+    shared: Shared
+}
+
+#[interface(name = "org.freedesktop.Secret.Session")]
+impl SessionObject {
+    async fn close(&self, #[zbus(header)] header: Header<'_>, #[zbus(object_server)] server: &ObjectServer) -> Result<(), Error> {
+        let Some(path) = header.path() else { return Ok(()) };
+        self.shared.lock().unwrap().sessions.remove(path.as_str());
+        server.remove::<SessionObject, _>(path).await?;
+        Ok(())
+    }
+}
+
+struct Prompt {
+    shared: Shared,
+    objects: Vec<OwnedObjectPath>
+}
+
+/// Runs the askpass program and returns what it printed without the line break.
+fn ask_key(askpass: &str) -> Option<String> {
+    let output = std::process::Command::new(askpass).arg("passtool key: ").output().ok()?;
+    if !output.status.success() { return None; }
+    let key = String::from_utf8(output.stdout).ok()?;
+    Some(key.strip_suffix('\n').unwrap_or(&key).to_string())
+}
+
+#[interface(name = "org.freedesktop.Secret.Prompt")]
+impl Prompt {
+    /// Asks for the key in the background, `Completed` carries the unlocked objects.
+    async fn prompt(&self, _window_id: &str, #[zbus(header)] header: Header<'_>, #[zbus(connection)] connection: &Connection) -> Result<(), Error> {
+        let path = header.path().ok_or_else(|| Error::NoSuchObject("prompt".to_string()))?.to_owned();
+        let connection = zbus::blocking::Connection::from(connection.clone());
+        let shared = self.shared.clone();
+        let objects = self.objects.clone();
+        thread::spawn(move || {
+            let askpass = shared.lock().unwrap().askpass.clone().unwrap_or_default();
+            let unlocked = ask_key(&askpass).is_some_and(|key| shared.lock().unwrap().unlock(key).unwrap_or(false));
+            let result = if unlocked {Value::from(objects)} else {Value::from(Vec::<OwnedObjectPath>::new())};
+            let _ = connection.emit_signal(None::<&str>, &path, "org.freedesktop.Secret.Prompt", "Completed", &(!unlocked, result));
+            let _ = connection.object_server().remove::<Prompt, _>(&path);
+        });
+        Ok(())
+    }
+
+    async fn dismiss(&self, #[zbus(header)] header: Header<'_>, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>, #[zbus(object_server)] server: &ObjectServer) -> Result<(), Error> {
+        Prompt::completed(&emitter, true, Value::from("")).await?;
+        if let Some(path) = header.path() {
+            server.remove::<Prompt, _>(path).await?;
+        }
+        Ok(())
+    }
+
+    #[zbus(signal)]
+    async fn completed(emitter: &SignalEmitter<'_>, dismissed: bool, result: Value<'_>) -> zbus::Result<()>;
+}
+
+/// Exports the service on `connection` and claims the `org.freedesktop.secrets` name.
+/// Without a `key` the service starts locked, `askpass` is the program run to unlock it.
+pub fn start(connection: &zbus::blocking::Connection, vault: PathBuf, key: Option<String>, askpass: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let shared = Arc::new(Mutex::new(State{vault, key: None, askpass, sessions: HashMap::new(), registered: HashSet::new(), counter: 0}));
+    if let Some(key) = key {
+        // the key the service is started with is trusted like on the command line, even for an empty vault
+        let mut state = shared.lock().unwrap();
+        if state.check(&key)? == Some(false) { return Err(crate::IncorrectPass.into()); }
+        state.key = Some(key);
+    }
+    let server = connection.object_server();
+    server.at(SERVICE_PATH, Service{shared: shared.clone()})?;
+    let collection = Collection{shared: shared.clone()};
+    server.at(COLLECTION_PATH, collection.clone())?;
+    server.at(DEFAULT_ALIAS_PATH, collection)?;
+    zbus::block_on(sync_items(server.inner(), &shared))?;
+    connection.request_name_with_flags(BUS_NAME, zbus::fdo::RequestNameFlags::DoNotQueue.into())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dh_session_test() -> Result<(), Error> {
+        let client = KeyPair::generate();
+        let server = KeyPair::generate();
+        assert_eq!(client.public.len(), 128);
+        let (parameters, value) = server.session(&client.public)?.encrypt(b"hunter2");
+        assert_eq!(parameters.len(), 16);
+        assert_ne!(value, b"hunter2");
+        assert_eq!(client.session(&server.public)?.decrypt(&parameters, &value)?, b"hunter2");
+        assert!(client.session(&[1]).is_err());
+        assert!(KeyPair::generate().session(&server.public)?.decrypt(&parameters, &value).map_or(true, |v| v != b"hunter2"));
+        Ok(())
+    }
+
+    #[test]
+    fn unlock_test() -> Result<(), Box<dyn std::error::Error>> {
+        for ext in ["pt", "ptj"] {
+            let vault = std::env::temp_dir().join(format!("passtool-secret-unlock-{}.{ext}", std::process::id()));
+            let _ = std::fs::remove_file(&vault);
+            store::create(&vault, &mut || Ok("key".to_string()))?;
+            let mut state = State{vault: vault.clone(), key: None, askpass: None, sessions: HashMap::new(), registered: HashSet::new(), counter: 0};
+            // only a journal can check the key of an empty vault
+            assert_eq!(state.unlock("key".to_string())?, ext == "ptj");
+            assert!(!state.unlock("wrong".to_string())?);
+            store::update(&vault, &mut || Ok("key".to_string()), |table| Ok(table.add_password("a", "1", PasswordMeta::default(), "key")?))?;
+            assert!(!state.unlock("wrong".to_string())?);
+            assert!(state.unlock("key".to_string())?);
+            assert_eq!(state.key()?, "key");
+            std::fs::remove_file(&vault)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn item_path_test() {
+        assert_eq!(encode_name("mail"), "mail");
+        assert_eq!(encode_name("my_mail @home"), "my_5fmail_20_40home");
+        assert_eq!(item_path("a.b").as_str(), "/org/freedesktop/secrets/collection/passtool/a_2eb");
+        assert_eq!(item_name("/org/freedesktop/secrets/collection/passtool/my_5fmail_20_40home").as_deref(), Some("my_mail @home"));
+        assert_eq!(item_name("/org/freedesktop/secrets/aliases/default/mail").as_deref(), Some("mail"));
+        assert_eq!(item_name("/org/freedesktop/secrets/collection/other/mail"), None);
+        assert_eq!(item_name("/org/freedesktop/secrets/collection/passtool/bad_4"), None);
+    }
+
+    #[test]
+    fn search_test() -> Result<(), crate::Error> {
+        let mut pt = PassTable::new();
+        let attributes = |pairs: &[(&str, &str)]| pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<BTreeMap<_, _>>();
+        pt.add_password("a", "1", PasswordMeta{attributes: attributes(&[("service", "mail"), ("user", "bob")]), ..Default::default()}, "key")?;
+        pt.add_password("b", "2", PasswordMeta{attributes: attributes(&[("service", "mail")]), ..Default::default()}, "key")?;
+        pt.add_password("c", "3", PasswordMeta::default(), "key")?;
+        let query = |pairs: &[(&str, &str)]| attributes(pairs).into_iter().collect::<HashMap<_, _>>();
+        assert_eq!(search(&pt, &query(&[("service", "mail")])), vec!["a", "b"]);
+        assert_eq!(search(&pt, &query(&[("service", "mail"), ("user", "bob")])), vec!["a"]);
+        assert_eq!(search(&pt, &query(&[])), vec!["a", "b", "c"]);
+        assert_eq!(unique_name(&pt, "a"), "a (2)");
+        assert_eq!(unique_name(&pt, ""), "Secret");
+
+        // only items created through the service can be replaced
+        assert_eq!(replaceable(&pt, &attributes(&[("service", "mail")])), None);
+        assert_eq!(replaceable(&pt, &BTreeMap::new()), None);
+        pt.add_password("d", "4", PasswordMeta{attributes: attributes(&[("service", "mail")]), tags: vec![TAG.to_string()], ..Default::default()}, "key")?;
+        pt.add_password("e", "5", PasswordMeta{tags: vec![TAG.to_string()], ..Default::default()}, "key")?;
+        assert_eq!(replaceable(&pt, &attributes(&[("service", "mail")])).as_deref(), Some("d"));
+        assert_eq!(replaceable(&pt, &BTreeMap::new()), None);
+        Ok(())
+    }
+}
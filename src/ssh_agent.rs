@@ -0,0 +1,307 @@
+//! ssh-agent protocol (draft-miller-ssh-agent) serving OpenSSH keys stored in the vault.
+//! Keys are only decrypted while the agent is unlocked, `ssh-add -x` locks it and
+//! `ssh-add -X` unlocks it again with the vault key.
+use std::{io::{self, Read, Write}, sync::Mutex};
+
+use rsa::pkcs1v15;
+use sha2::{Sha256, Sha512};
+use signature::{SignatureEncoding, Signer};
+use ssh_encoding::Encode;
+use ssh_key::{private::{KeypairData, RsaKeypair}, Algorithm, HashAlg, LineEnding, Mpint, PrivateKey, Signature};
+
+use crate::{AccessRecorder, EntryKind, PassTable, PasswordMeta, TableSource};
+
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENT_SUCCESS: u8 = 6;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+const SSH_AGENTC_LOCK: u8 = 22;
+const SSH_AGENTC_UNLOCK: u8 = 23;
+
+const SSH_AGENT_RSA_SHA2_256: u32 = 2;
+const SSH_AGENT_RSA_SHA2_512: u32 = 4;
+
+/// Upper bound for a single message, same as OpenSSH's agent.
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+
+/// Validates an OpenSSH private key and stores it as an `EntryKind::SshKey` entry encrypted with `key`.
+/// Passphrase-protected keys have to be decrypted with `ssh-keygen -p` first.
+pub fn add_key(table: &mut PassTable, name: &str, openssh: &str, mut meta: PasswordMeta, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let private = PrivateKey::from_openssh(openssh)?;
+    if private.is_encrypted() {
+        return Err("key is protected with a passphrase".into());
+    }
+    meta.kind = EntryKind::SshKey;
+    table.add_password(name, &private.to_openssh(LineEnding::LF)?, meta, key)?;
+    Ok(())
+}
+
+pub fn get_key(table: &PassTable, name: &str, key: &str) -> Result<PrivateKey, Box<dyn std::error::Error>> {
+    Ok(PrivateKey::from_openssh(table.get_password(name, key)?)?)
+}
+
+/// ssh-key's own conversion passes `p` twice, so the key is rebuilt from its components.
+fn rsa_key(keypair: &RsaKeypair) -> Result<rsa::RsaPrivateKey, Box<dyn std::error::Error>> {
+    let int = |m: &Mpint| m.as_positive_bytes().map(rsa::BigUint::from_bytes_be).ok_or("invalid RSA key");
+    let primes = vec![int(&keypair.private.p)?, int(&keypair.private.q)?];
+    Ok(rsa::RsaPrivateKey::from_components(int(&keypair.public.n)?, int(&keypair.public.e)?, int(&keypair.private.d)?, primes)?)
+}
+
+/// Signs `data` the way `SSH_AGENTC_SIGN_REQUEST` expects, `flags` select the RSA hash.
+pub fn sign(private: &PrivateKey, data: &[u8], flags: u32) -> Result<Signature, Box<dyn std::error::Error>> {
+    match private.key_data() {
+        KeypairData::Rsa(keypair) => {
+            let (hash, bytes) = if flags & SSH_AGENT_RSA_SHA2_512 != 0 {
+                (HashAlg::Sha512, pkcs1v15::SigningKey::<Sha512>::new(rsa_key(keypair)?).try_sign(data)?.to_vec())
+            }
+            else if flags & SSH_AGENT_RSA_SHA2_256 != 0 {
+                (HashAlg::Sha256, pkcs1v15::SigningKey::<Sha256>::new(rsa_key(keypair)?).try_sign(data)?.to_vec())
+            }
+            else {
+                return Err("ssh-rsa (SHA-1) signatures are not supported".into());
+            };
+            Ok(Signature::new(Algorithm::Rsa{hash: Some(hash)}, bytes)?)
+        }
+        _ => Ok(private.try_sign(data)?)
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn u32(&mut self) -> Option<u32> {
+        let (bytes, rest) = self.0.split_first_chunk::<4>()?;
+        self.0 = rest;
+        Some(u32::from_be_bytes(*bytes))
+    }
+
+    fn string(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        if self.0.len() < len { return None; }
+        let (string, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(string)
+    }
+}
+
+fn put_string(out: &mut Vec<u8>, string: &[u8]) {
+    out.extend_from_slice(&(string.len() as u32).to_be_bytes());
+    out.extend_from_slice(string);
+}
+
+pub struct Agent {
+    source: TableSource,
+    recorder: Option<AccessRecorder>,
+    /// (entry name, key) pairs, empty while locked
+    keys: Vec<(String, PrivateKey)>,
+    locked: bool
+}
+
+impl Agent {
+    /// Creates a locked agent, `source` loads the vault on every unlock.
+    pub fn new(source: TableSource) -> Self {
+        Agent{source, recorder: None, keys: Vec::new(), locked: true}
+    }
+
+    /// Has every signature recorded as an access to its key's entry.
+    pub fn on_access(&mut self, recorder: AccessRecorder) {
+        self.recorder = Some(recorder);
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Decrypts every SSH key entry that opens with `key`, returns how many were loaded.
+    /// Fails if there are SSH key entries but none of them opens with `key`.
+    pub fn unlock(&mut self, key: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        let table = (self.source)()?;
+        let names = table.get_names_of_kind(EntryKind::SshKey);
+        let mut keys = Vec::new();
+        for name in &names {
+            match get_key(&table, name, key) {
+                Ok(private) => keys.push((name.clone(), private)),
+                Err(e) if e.downcast_ref() == Some(&crate::IncorrectPass) => continue,
+                Err(e) => return Err(e)
+            }
+        }
+        if keys.is_empty() && !names.is_empty() {
+            return Err(Box::new(crate::IncorrectPass));
+        }
+        self.keys = keys;
+        self.locked = false;
+        Ok(self.keys.len())
+    }
+
+    /// Forgets the decrypted keys.
+    pub fn lock(&mut self) {
+        self.keys.clear();
+        self.locked = true;
+    }
+
+    fn identities(&self) -> Vec<u8> {
+        let mut response = vec![SSH_AGENT_IDENTITIES_ANSWER];
+        response.extend_from_slice(&(self.keys.len() as u32).to_be_bytes());
+        for (name, private) in &self.keys {
+            put_string(&mut response, &private.public_key().to_bytes().unwrap_or_default());
+            put_string(&mut response, name.as_bytes());
+        }
+        response
+    }
+
+    fn sign_request(&self, mut request: Reader) -> Option<Vec<u8>> {
+        let blob = request.string()?;
+        let data = request.string()?;
+        let flags = request.u32().unwrap_or(0);
+        let (name, private) = self.keys.iter().find(|(_, k)| k.public_key().to_bytes().is_ok_and(|b| b == blob))?;
+        let signature = sign(private, data, flags).ok()?;
+        if let Some(recorder) = &self.recorder {
+            // a vault that can't be written doesn't stop the signature
+            let _ = recorder(name);
+        }
+        let mut encoded = Vec::new();
+        signature.encode(&mut encoded).ok()?;
+        let mut response = vec![SSH_AGENT_SIGN_RESPONSE];
+        put_string(&mut response, &encoded);
+        Some(response)
+    }
+
+    /// Answers one message (without the length prefix).
+    pub fn handle(&mut self, message: &[u8]) -> Vec<u8> {
+        let Some((&kind, body)) = message.split_first() else { return vec![SSH_AGENT_FAILURE] };
+        let mut body = Reader(body);
+        let response = match kind {
+            SSH_AGENTC_REQUEST_IDENTITIES => Some(self.identities()),
+            SSH_AGENTC_SIGN_REQUEST => self.sign_request(body),
+            SSH_AGENTC_LOCK if !self.locked => {
+                self.lock();
+                Some(vec![SSH_AGENT_SUCCESS])
+            }
+            SSH_AGENTC_UNLOCK if self.locked => body.string()
+                .and_then(|key| std::str::from_utf8(key).ok())
+                .and_then(|key| self.unlock(key).ok())
+                .map(|_| vec![SSH_AGENT_SUCCESS]),
+            _ => None // keys are managed through the vault, adding and removing is refused
+        };
+        response.unwrap_or(vec![SSH_AGENT_FAILURE])
+    }
+}
+
+/// Serves length-prefixed messages from `stream` until it is closed.
+pub fn serve<S: Read + Write>(agent: &Mutex<Agent>, mut stream: S) -> io::Result<()> {
+    loop {
+        let mut len = [0u8; 4];
+        match stream.read_exact(&mut len) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            r => r?
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_MESSAGE_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "message too long"));
+        }
+        let mut message = vec![0u8; len];
+        stream.read_exact(&mut message)?;
+        let response = agent.lock().unwrap().handle(&message);
+        stream.write_all(&(response.len() as u32).to_be_bytes())?;
+        stream.write_all(&response)?;
+    }
+}
+
+/// Listens on a unix socket only the current user can connect to, one thread per client.
+#[cfg(unix)]
+pub fn listen<P: AsRef<std::path::Path>>(path: P, agent: Agent) -> io::Result<()> {
+    use std::{fs, os::unix::{fs::PermissionsExt, net::UnixListener}, sync::Arc, thread};
+
+    let listener = UnixListener::bind(&path)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    let agent = Arc::new(Mutex::new(agent));
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let agent = agent.clone();
+        thread::spawn(move || serve(&agent, stream));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use signature::Verifier;
+
+    fn agent() -> (Agent, PrivateKey) {
+        let private = PrivateKey::random(&mut rand::thread_rng(), Algorithm::Ed25519).unwrap();
+        let pem = private.to_openssh(LineEnding::LF).unwrap().to_string();
+        let source: TableSource = Box::new(move || {
+            let mut pt = PassTable::new();
+            add_key(&mut pt, "laptop", &pem, PasswordMeta::default(), "key")?;
+            pt.add_password("not a key", "x", PasswordMeta::default(), "key")?;
+            Ok(pt)
+        });
+        (Agent::new(source), private)
+    }
+
+    fn request(kind: u8, strings: &[&[u8]]) -> Vec<u8> {
+        let mut message = vec![kind];
+        for s in strings { put_string(&mut message, s); }
+        message
+    }
+
+    #[test]
+    fn add_key_test() {
+        let mut pt = PassTable::new();
+        assert!(add_key(&mut pt, "bad", "not a key", PasswordMeta::default(), "key").is_err());
+        let (mut agent, private) = agent();
+        agent.unlock("key").unwrap();
+        assert_eq!(agent.keys.len(), 1);
+        assert_eq!(agent.keys[0].1.public_key(), private.public_key());
+    }
+
+    #[test]
+    fn identities_and_sign_test() {
+        let (mut agent, private) = agent();
+        let accessed = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let recorded = accessed.clone();
+        agent.on_access(Box::new(move |name| {
+            recorded.lock().unwrap().push(name.to_string());
+            Ok(())
+        }));
+        assert_eq!(agent.handle(&[SSH_AGENTC_REQUEST_IDENTITIES]), vec![SSH_AGENT_IDENTITIES_ANSWER, 0, 0, 0, 0]);
+        assert!(agent.unlock("wrong").is_err());
+        assert!(agent.is_locked());
+        agent.unlock("key").unwrap();
+
+        let answer = agent.handle(&[SSH_AGENTC_REQUEST_IDENTITIES]);
+        let mut reader = Reader(&answer[1..]);
+        assert_eq!(reader.u32(), Some(1));
+        let blob = reader.string().unwrap().to_vec();
+        assert_eq!(blob, private.public_key().to_bytes().unwrap());
+        assert_eq!(reader.string(), Some(&b"laptop"[..]));
+
+        let mut sign = request(SSH_AGENTC_SIGN_REQUEST, &[&blob, b"session data"]);
+        sign.extend_from_slice(&0u32.to_be_bytes());
+        let response = agent.handle(&sign);
+        assert_eq!(response[0], SSH_AGENT_SIGN_RESPONSE);
+        let encoded = Reader(&response[1..]).string().unwrap();
+        let mut reader = Reader(encoded);
+        assert_eq!(reader.string(), Some(&b"ssh-ed25519"[..]));
+        let signature = Signature::new(Algorithm::Ed25519, reader.string().unwrap()).unwrap();
+        assert!(private.public_key().key_data().verify(b"session data", &signature).is_ok());
+        assert_eq!(*accessed.lock().unwrap(), ["laptop"]);
+    }
+
+    #[test]
+    fn lock_unlock_test() {
+        let (mut agent, _) = agent();
+        agent.unlock("key").unwrap();
+        assert_eq!(agent.handle(&request(SSH_AGENTC_LOCK, &[b"anything"])), vec![SSH_AGENT_SUCCESS]);
+        assert!(agent.keys.is_empty());
+        assert_eq!(agent.handle(&request(SSH_AGENTC_LOCK, &[b"anything"])), vec![SSH_AGENT_FAILURE]);
+        assert_eq!(agent.handle(&request(SSH_AGENTC_UNLOCK, &[b"wrong"])), vec![SSH_AGENT_FAILURE]);
+        assert_eq!(agent.handle(&request(SSH_AGENTC_UNLOCK, &[b"key"])), vec![SSH_AGENT_SUCCESS]);
+        assert_eq!(agent.keys.len(), 1);
+        assert_eq!(agent.handle(&[17]), vec![SSH_AGENT_FAILURE]);
+        assert_eq!(agent.handle(&[]), vec![SSH_AGENT_FAILURE]);
+    }
+}
@@ -0,0 +1,472 @@
+//! Storage backends for the vault. `FileStore` is the single-file format written by `PassTable::to_file`
+//! and rewrites the whole table on every change, `SqliteStore` keeps one row per entry so large vaults
+//! are updated an entry at a time. Entries are stored encrypted, no backend needs the key.
+use std::{error::Error, path::{Path, PathBuf}, time::Duration};
+
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::{PassTable, Password};
+
+/// Body of a `VaultStore::transaction`, it reads and changes the store through the given view.
+pub type Operations<'a> = dyn FnMut(&mut dyn VaultStore) -> Result<(), Box<dyn Error>> + 'a;
+
+pub trait VaultStore {
+    /// All entries, the trash and its retention included.
+    fn load(&self) -> Result<PassTable, Box<dyn Error>>;
+    fn get(&self, name: &str) -> Result<Option<Password>, Box<dyn Error>>;
+    /// Inserts or replaces `name`.
+    fn put(&mut self, name: &str, entry: &Password) -> Result<(), Box<dyn Error>>;
+    /// Whether there was an entry to delete. Entries are removed for good, `put_trash` keeps them.
+    fn delete(&mut self, name: &str) -> Result<bool, Box<dyn Error>>;
+    /// Replaces the trash and its retention by those of `table`.
+    fn put_trash(&mut self, table: &PassTable) -> Result<(), Box<dyn Error>>;
+    /// Runs `operations` atomically, none of their changes are kept if they fail.
+    fn transaction(&mut self, operations: &mut Operations) -> Result<(), Box<dyn Error>>;
+}
+
+impl VaultStore for PassTable {
+    fn load(&self) -> Result<PassTable, Box<dyn Error>> {
+        Ok(self.clone())
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Password>, Box<dyn Error>> {
+        Ok(self.get_entry(name).cloned())
+    }
+
+    fn put(&mut self, name: &str, entry: &Password) -> Result<(), Box<dyn Error>> {
+        self.set_entry(name, entry.clone());
+        Ok(())
+    }
+
+    fn delete(&mut self, name: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(self.remove_entry(name).is_some())
+    }
+
+    fn put_trash(&mut self, table: &PassTable) -> Result<(), Box<dyn Error>> {
+        self.trash = table.trash.clone();
+        self.trash_retention = table.trash_retention;
+        Ok(())
+    }
+
+    fn transaction(&mut self, operations: &mut Operations) -> Result<(), Box<dyn Error>> {
+        let backup = self.clone();
+        let result = operations(self);
+        if result.is_err() {
+            *self = backup;
+        }
+        result
+    }
+}
+
+/// Vault file in the `PassTable::to_file` format, every call holds the `VaultLock`.
+pub struct FileStore {
+    path: PathBuf
+}
+
+impl FileStore {
+    /// Store for the existing vault at `path`.
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.into();
+        if !path.exists() {
+            return Err(format!("no vault at {}", path.display()).into());
+        }
+        Ok(FileStore{path})
+    }
+
+    /// Store for `path`, an empty vault is created if there is none.
+    pub fn create<P: Into<PathBuf>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.into();
+        if !path.exists() {
+            PassTable::new().to_file(&path)?;
+        }
+        Ok(FileStore{path})
+    }
+}
+
+impl VaultStore for FileStore {
+    fn load(&self) -> Result<PassTable, Box<dyn Error>> {
+        PassTable::from_file_locked(&self.path)
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Password>, Box<dyn Error>> {
+        Ok(self.load()?.get_entry(name).cloned())
+    }
+
+    fn put(&mut self, name: &str, entry: &Password) -> Result<(), Box<dyn Error>> {
+        PassTable::update_file(&self.path, |table| table.put(name, entry))
+    }
+
+    fn delete(&mut self, name: &str) -> Result<bool, Box<dyn Error>> {
+        PassTable::update_file(&self.path, |table| table.delete(name))
+    }
+
+    fn put_trash(&mut self, trash: &PassTable) -> Result<(), Box<dyn Error>> {
+        PassTable::update_file(&self.path, |table| table.put_trash(trash))
+    }
+
+    fn transaction(&mut self, operations: &mut Operations) -> Result<(), Box<dyn Error>> {
+        PassTable::update_file(&self.path, |table| operations(table))
+    }
+}
+
+/// SQLite database with a row per entry holding its bincode encoding,
+/// the trash and its retention are encoded together in a single row.
+pub struct SqliteStore {
+    conn: Connection
+}
+
+impl SqliteStore {
+    /// Opens or creates the database at `path`, only the current user can read it.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        // SQLite gives the journals it creates the mode of the database, a journal left by a crash keeps its own
+        crate::open_private(&path, false)?;
+        let mut journal = path.as_ref().as_os_str().to_owned();
+        journal.push("-journal");
+        if Path::new(&journal).exists() {
+            crate::open_private(&journal, false)?;
+        }
+        SqliteStore::with_connection(Connection::open(&path)?)
+    }
+
+    pub fn in_memory() -> Result<Self, Box<dyn Error>> {
+        SqliteStore::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, Box<dyn Error>> {
+        // other processes may hold the write lock for a moment
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute_batch("CREATE TABLE IF NOT EXISTS entries (name TEXT PRIMARY KEY NOT NULL, entry BLOB NOT NULL);
+            CREATE TABLE IF NOT EXISTS trash (id INTEGER PRIMARY KEY CHECK (id = 0), trash BLOB NOT NULL)")?;
+        Ok(SqliteStore{conn})
+    }
+}
+
+/// Queries on a connection, inside `transaction` they run in a savepoint.
+struct Sql<'a>(&'a Connection);
+
+impl VaultStore for Sql<'_> {
+    fn load(&self) -> Result<PassTable, Box<dyn Error>> {
+        let mut table = PassTable::new();
+        let mut statement = self.0.prepare("SELECT name, entry FROM entries")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let name: String = row.get(0)?;
+            table.set_entry(&name, bincode::deserialize(&row.get::<_, Vec<u8>>(1)?)?);
+        }
+        let trash: Option<Vec<u8>> = self.0.query_row("SELECT trash FROM trash WHERE id = 0", [], |row| row.get(0)).optional()?;
+        if let Some(trash) = trash {
+            (table.trash, table.trash_retention) = bincode::deserialize(&trash)?;
+        }
+        Ok(table)
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Password>, Box<dyn Error>> {
+        let entry: Option<Vec<u8>> = self.0.query_row("SELECT entry FROM entries WHERE name = ?1", [name], |row| row.get(0)).optional()?;
+        Ok(entry.map(|entry| bincode::deserialize(&entry)).transpose()?)
+    }
+
+    fn put(&mut self, name: &str, entry: &Password) -> Result<(), Box<dyn Error>> {
+        self.0.execute("INSERT INTO entries (name, entry) VALUES (?1, ?2) ON CONFLICT (name) DO UPDATE SET entry = excluded.entry",
+            rusqlite::params![name, bincode::serialize(entry)?])?;
+        Ok(())
+    }
+
+    fn delete(&mut self, name: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(self.0.execute("DELETE FROM entries WHERE name = ?1", [name])? > 0)
+    }
+
+    fn put_trash(&mut self, table: &PassTable) -> Result<(), Box<dyn Error>> {
+        self.0.execute("INSERT INTO trash (id, trash) VALUES (0, ?1) ON CONFLICT (id) DO UPDATE SET trash = excluded.trash",
+            [bincode::serialize(&(&table.trash, table.trash_retention))?])?;
+        Ok(())
+    }
+
+    fn transaction(&mut self, operations: &mut Operations) -> Result<(), Box<dyn Error>> {
+        // the outermost transaction takes the write lock up front, so writers wait for each other
+        // instead of failing when a read turns into a write, nested ones are savepoints
+        let (begin, commit, rollback) = if self.0.is_autocommit() {
+            ("BEGIN IMMEDIATE", "COMMIT", "ROLLBACK")
+        } else {
+            ("SAVEPOINT vault_store", "RELEASE vault_store", "ROLLBACK TO vault_store; RELEASE vault_store")
+        };
+        self.0.execute_batch(begin)?;
+        match operations(self) {
+            Ok(()) => {
+                self.0.execute_batch(commit)?;
+                Ok(())
+            }
+            Err(e) => {
+                self.0.execute_batch(rollback)?;
+                Err(e)
+            }
+        }
+    }
+}
+
+impl VaultStore for SqliteStore {
+    fn load(&self) -> Result<PassTable, Box<dyn Error>> {
+        Sql(&self.conn).load()
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Password>, Box<dyn Error>> {
+        Sql(&self.conn).get(name)
+    }
+
+    fn put(&mut self, name: &str, entry: &Password) -> Result<(), Box<dyn Error>> {
+        Sql(&self.conn).put(name, entry)
+    }
+
+    fn delete(&mut self, name: &str) -> Result<bool, Box<dyn Error>> {
+        Sql(&self.conn).delete(name)
+    }
+
+    fn put_trash(&mut self, table: &PassTable) -> Result<(), Box<dyn Error>> {
+        Sql(&self.conn).put_trash(table)
+    }
+
+    fn transaction(&mut self, operations: &mut Operations) -> Result<(), Box<dyn Error>> {
+        Sql(&self.conn).transaction(operations)
+    }
+}
+
+/// Whether `path` names a SQLite database: `.db`, `.sqlite` or `.sqlite3`.
+pub fn is_sqlite(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ["db", "sqlite", "sqlite3"].iter().any(|sqlite| ext.eq_ignore_ascii_case(sqlite)))
+}
+
+/// Whether `path` names a `journal`: `.ptj`.
+pub fn is_journal(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("ptj"))
+}
+
+/// Store for the existing vault at `path`: a SQLite database, a journal opened with `key()` or a vault file.
+pub fn open<P: AsRef<Path>>(path: P, key: &mut dyn FnMut() -> Result<String, Box<dyn Error>>) -> Result<Box<dyn VaultStore>, Box<dyn Error>> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Err(format!("no vault at {}", path.display()).into());
+    }
+    create(path, key)
+}
+
+/// Like `open`, an empty vault is created if there is none.
+pub fn create<P: AsRef<Path>>(path: P, key: &mut dyn FnMut() -> Result<String, Box<dyn Error>>) -> Result<Box<dyn VaultStore>, Box<dyn Error>> {
+    let path = path.as_ref();
+    Ok(if is_sqlite(path) {
+        Box::new(SqliteStore::open(path)?)
+    } else if is_journal(path) {
+        Box::new(crate::journal::JournalStore::open(path, &key()?)?)
+    } else {
+        Box::new(FileStore::create(path)?)
+    })
+}
+
+/// All entries of the vault at `path`, `key()` is only asked for by journals.
+pub fn load<P: AsRef<Path>>(path: P, key: &mut dyn FnMut() -> Result<String, Box<dyn Error>>) -> Result<PassTable, Box<dyn Error>> {
+    let path = path.as_ref();
+    if !is_sqlite(path) && !is_journal(path) {
+        return PassTable::from_file_locked(path);
+    }
+    let mut table = open(path, key)?.load()?;
+    table.purge_expired(crate::now());
+    Ok(table)
+}
+
+/// `PassTable::update_file` for any backend: `update` changes the loaded table and the entries
+/// it changed are written back in one transaction, the trash if it changed. Nothing is saved if `update` fails.
+pub fn update<P: AsRef<Path>, T>(path: P, key: &mut dyn FnMut() -> Result<String, Box<dyn Error>>, update: impl FnOnce(&mut PassTable) -> Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+    let path = path.as_ref();
+    if !is_sqlite(path) && !is_journal(path) {
+        return PassTable::update_file(path, update);
+    }
+    let (mut update, mut result) = (Some(update), None);
+    open(path, key)?.transaction(&mut |store| {
+        let before = store.load()?;
+        let mut table = before.clone();
+        table.purge_expired(crate::now());
+        result = Some((update.take().unwrap())(&mut table)?);
+        for name in table.get_names() {
+            let entry = table.get_entry(name).unwrap();
+            if before.get_entry(name) != Some(entry) {
+                store.put(name, entry)?;
+            }
+        }
+        for name in before.get_names().filter(|name| !table.contains(name)) {
+            store.delete(name)?;
+        }
+        if (&table.trash, table.trash_retention) != (&before.trash, before.trash_retention) {
+            store.put_trash(&table)?;
+        }
+        Ok(())
+    })?;
+    Ok(result.unwrap())
+}
+
+/// Records that the secrets of `names` were decrypted.
+pub fn mark_accessed<P: AsRef<Path>>(path: P, key: &mut dyn FnMut() -> Result<String, Box<dyn Error>>, names: &[impl AsRef<str>]) -> Result<(), Box<dyn Error>> {
+    if names.is_empty() { return Ok(()); }
+    update(path, key, |table| {
+        for name in names {
+            table.mark_accessed(name.as_ref())?;
+        }
+        Ok(())
+    })
+}
+
+/// Copies every entry and the trash of `from` into `to` in one transaction, returns the number of entries.
+pub fn copy(from: &dyn VaultStore, to: &mut dyn VaultStore) -> Result<usize, Box<dyn Error>> {
+    let table = from.load()?;
+    to.transaction(&mut |store| {
+        for name in table.get_names() {
+            store.put(name, table.get_entry(name).unwrap())?;
+        }
+        store.put_trash(&table)
+    })?;
+    Ok(table.get_names().count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::PasswordMeta;
+
+    fn entry(password: &str) -> Password {
+        let mut table = PassTable::new();
+        table.add_password("x", password, PasswordMeta::new(password.to_string(), Vec::new()), "key").unwrap();
+        table.get_entry("x").unwrap().clone()
+    }
+
+    fn check(store: &mut dyn VaultStore) -> Result<(), Box<dyn Error>> {
+        store.put("a", &entry("1"))?;
+        store.put("b", &entry("2"))?;
+        store.put("a", &entry("3"))?;
+        assert_eq!(store.get("a")?.unwrap().meta().description, "3");
+        assert_eq!(store.get("c")?, None);
+        assert!(store.delete("b")?);
+        assert!(!store.delete("b")?);
+        assert_eq!(store.load()?.get_password("a", "key")?, "3");
+
+        let failed = store.transaction(&mut |store| {
+            store.put("c", &entry("4"))?;
+            store.delete("a")?;
+            Err("abort".into())
+        });
+        assert!(failed.is_err());
+        assert!(store.get("a")?.is_some() && store.get("c")?.is_none());
+
+        store.transaction(&mut |store| {
+            store.put("c", &entry("4"))?;
+            // a failed nested transaction only undoes its own changes
+            let _ = store.transaction(&mut |store| {
+                store.delete("c")?;
+                Err("abort".into())
+            });
+            assert!(store.get("c")?.is_some());
+            store.delete("a")?;
+            Ok(())
+        })?;
+        let mut names: Vec<String> = store.load()?.get_names().cloned().collect();
+        names.sort();
+        assert_eq!(names, ["c"]);
+        Ok(())
+    }
+
+    #[test]
+    fn table_store_test() -> Result<(), Box<dyn Error>> {
+        check(&mut PassTable::new())
+    }
+
+    #[test]
+    fn file_store_test() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("passtool-store-{}.pt", std::process::id()));
+        assert!(FileStore::open(&path).is_err());
+        check(&mut FileStore::create(&path)?)?;
+        assert!(PassTable::from_file(&path)?.contains("c"));
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn sqlite_store_test() -> Result<(), Box<dyn Error>> {
+        check(&mut SqliteStore::in_memory()?)?;
+
+        for ext in ["db", "ptj"] {
+            let path = std::env::temp_dir().join(format!("passtool-store-{}.{ext}", std::process::id()));
+            let mut from = PassTable::new();
+            from.put("a", &entry("1"))?;
+            from.put("b", &entry("2"))?;
+            from.put("t", &entry("3"))?;
+            from.remove_password("t")?;
+            from.set_trash_retention(Some(60 * 60));
+            let mut key = || Ok("vault key".to_string());
+            assert!(open(&path, &mut key).is_err());
+            assert_eq!(copy(&from, create(&path, &mut key)?.as_mut())?, 2);
+            assert_eq!(open(&path, &mut key)?.load()?, from);
+
+            // only the changed entries are written back
+            let renamed = update(&path, &mut key, |table| {
+                table.rename("a", "c")?;
+                Ok(table.get_id("c")?)
+            })?;
+            assert_eq!(load(&path, &mut key)?.get_id("c")?, renamed);
+            assert!(!load(&path, &mut key)?.contains("a"));
+            assert!(update(&path, &mut key, |table| {
+                table.remove_entry("b");
+                Err::<(), _>("abort".into())
+            }).is_err());
+            assert!(load(&path, &mut key)?.contains("b"));
+
+            // so is the trash
+            update(&path, &mut key, |table| Ok(table.remove_password("b")?))?;
+            assert_eq!(load(&path, &mut key)?.get_trash().len(), 2);
+            update(&path, &mut key, |table| Ok(table.restore("t")?))?;
+            let table = load(&path, &mut key)?;
+            assert!(table.contains("t") && table.get_trash().len() == 1);
+            assert_eq!(table.trash_retention(), Some(60 * 60));
+
+            // expired entries are dropped when the vault is read and for good on the next update
+            update(&path, &mut key, |table| {
+                table.trash[0].deleted = 0;
+                Ok(())
+            })?;
+            assert!(load(&path, &mut key)?.get_trash().is_empty());
+            assert_eq!(open(&path, &mut key)?.load()?.get_trash().len(), 1);
+            update(&path, &mut key, |_| Ok(()))?;
+            assert!(open(&path, &mut key)?.load()?.get_trash().is_empty());
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sqlite_writers_test() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("passtool-writers-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        SqliteStore::open(&path)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        }
+        // every writer reads before it writes, none of them is turned away or loses an update
+        let writers: Vec<_> = (0..4).map(|writer| {
+            let path = path.clone();
+            std::thread::spawn(move || -> Result<(), String> {
+                let mut store = SqliteStore::open(&path).map_err(|e| e.to_string())?;
+                for _ in 0..10 {
+                    store.transaction(&mut |store| {
+                        let count = store.load()?.get_names().count();
+                        store.put(&format!("{writer}-{count}"), &entry("1"))
+                    }).map_err(|e| e.to_string())?;
+                }
+                Ok(())
+            })
+        }).collect();
+        for writer in writers {
+            writer.join().unwrap()?;
+        }
+        assert_eq!(SqliteStore::open(&path)?.load()?.get_names().count(), 40);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
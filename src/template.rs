@@ -0,0 +1,210 @@
+use core::fmt;
+use std::{fs, io, path::Path};
+
+use crate::PassTable;
+
+#[derive(Debug, PartialEq)]
+pub enum TemplateError {
+    /// Malformed placeholder on the given line (1-based).
+    Syntax(usize, String),
+    UnknownField(usize, String),
+    MissingEntry(usize, String),
+    Decrypt(usize, crate::Error)
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax(line, message) => write!(f, "line {line}: {message}"),
+            Self::UnknownField(line, field) => write!(f, "line {line}: unknown field '{field}'"),
+            Self::MissingEntry(line, name) => write!(f, "line {line}: entry '{name}' not found"),
+            Self::Decrypt(line, e) => write!(f, "line {line}: {e}")
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+pub const FIELDS: [&str; 4] = ["password", "username", "url", "description"];
+
+/// A `{{ passtool "name" "field" }}` placeholder, the field defaults to `password`.
+#[derive(Debug, PartialEq)]
+pub struct Reference {
+    pub name: String,
+    pub field: String,
+    pub line: usize
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    Placeholder(Reference)
+}
+
+/// Parses the arguments of a placeholder: one or two double-quoted strings.
+fn parse_args(args: &str, line: usize) -> Result<Vec<String>, TemplateError> {
+    let mut values = Vec::new();
+    let mut chars = args.trim().chars();
+    loop {
+        match chars.next() {
+            None => break,
+            Some(c) if c.is_whitespace() => continue,
+            Some('"') => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => value.push(chars.next().ok_or(TemplateError::Syntax(line, "unterminated string".to_string()))?),
+                        Some(c) => value.push(c),
+                        None => return Err(TemplateError::Syntax(line, "unterminated string".to_string()))
+                    }
+                }
+                values.push(value);
+            }
+            Some(c) => return Err(TemplateError::Syntax(line, format!("unexpected '{c}', arguments must be quoted")))
+        }
+    }
+    Ok(values)
+}
+
+fn parse(template: &str) -> Result<Vec<Segment<'_>>, TemplateError> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+    let mut line = 1;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let line_here = line + rest[..start].matches('\n').count();
+        let Some(end) = after.find("}}") else {
+            if after.trim_start().starts_with("passtool") {
+                return Err(TemplateError::Syntax(line_here, "unclosed '{{'".to_string()));
+            }
+            break;
+        };
+        let inner = after[..end].trim();
+        let consumed = start + 2 + end + 2;
+
+        let args = inner.strip_prefix("passtool").filter(|a| a.is_empty() || a.starts_with(char::is_whitespace));
+        match args {
+            Some(args) => {
+                segments.push(Segment::Text(&rest[..start]));
+                let mut args = parse_args(args, line_here)?.into_iter();
+                let name = args.next().ok_or(TemplateError::Syntax(line_here, "missing entry name".to_string()))?;
+                let field = args.next().unwrap_or("password".to_string());
+                if args.next().is_some() {
+                    return Err(TemplateError::Syntax(line_here, "too many arguments".to_string()));
+                }
+                if !FIELDS.contains(&field.as_str()) {
+                    return Err(TemplateError::UnknownField(line_here, field));
+                }
+                segments.push(Segment::Placeholder(Reference{name, field, line: line_here}));
+            }
+            None => segments.push(Segment::Text(&rest[..consumed])) // someone else's placeholder
+        }
+        line += rest[..consumed].matches('\n').count();
+        rest = &rest[consumed..];
+    }
+    segments.push(Segment::Text(rest));
+    Ok(segments)
+}
+
+pub fn references(template: &str) -> Result<Vec<Reference>, TemplateError> {
+    Ok(parse(template)?.into_iter().filter_map(|s| match s {
+        Segment::Placeholder(r) => Some(r),
+        Segment::Text(_) => None
+    }).collect())
+}
+
+/// Validates the template without decrypting anything, returns references to entries that do not exist.
+pub fn check(template: &str, table: &PassTable) -> Result<Vec<Reference>, TemplateError> {
+    Ok(references(template)?.into_iter().filter(|r| !table.contains(&r.name)).collect())
+}
+
+fn lookup(table: &PassTable, r: &Reference, key: &str) -> Result<String, TemplateError> {
+    let meta = table.get_metadata(&r.name).or(Err(TemplateError::MissingEntry(r.line, r.name.clone())))?;
+    match r.field.as_str() {
+        "username" => Ok(meta.username.clone()),
+        "url" => Ok(meta.url.clone()),
+        "description" => Ok(meta.description.clone()),
+        _ => table.get_password(&r.name, key).map_err(|e| TemplateError::Decrypt(r.line, e))
+    }
+}
+
+/// Replaces every placeholder with the referenced field, failing on the first missing entry.
+pub fn render(template: &str, table: &PassTable, key: &str) -> Result<String, TemplateError> {
+    let mut rendered = String::with_capacity(template.len());
+    for segment in parse(template)? {
+        match segment {
+            Segment::Text(text) => rendered.push_str(text),
+            Segment::Placeholder(r) => rendered.push_str(&lookup(table, &r, key)?)
+        }
+    }
+    Ok(rendered)
+}
+
+/// Writes rendered output to a file only readable by its owner.
+pub fn write_output<P: AsRef<Path>>(filename: P, rendered: &str) -> io::Result<()> {
+    crate::write_private(filename, rendered.as_bytes())
+}
+
+pub fn render_file<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q, table: &PassTable, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let rendered = render(&fs::read_to_string(input)?, table, key)?;
+    write_output(output, &rendered)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PasswordMeta;
+
+    const TEMPLATE: &str = "db:
+  user: {{ passtool \"db-prod\" \"username\" }}
+  password: {{passtool \"db-prod\"}}
+  note: {{ .Values.untouched }}
+  other: {{ passtool \"api \\\"key\\\"\" \"password\" }}
+";
+
+    fn table() -> PassTable {
+        let mut pt = PassTable::new();
+        pt.add_password("db-prod", "hunter2", PasswordMeta{username: "admin".to_string(), ..Default::default()}, "key").unwrap();
+        pt
+    }
+
+    #[test]
+    fn references_test() -> Result<(), TemplateError> {
+        let refs = references(TEMPLATE)?;
+        assert_eq!(refs, vec![
+            Reference{name: "db-prod".to_string(), field: "username".to_string(), line: 2},
+            Reference{name: "db-prod".to_string(), field: "password".to_string(), line: 3},
+            Reference{name: "api \"key\"".to_string(), field: "password".to_string(), line: 5},
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn render_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pt = table();
+        assert_eq!(render(TEMPLATE, &pt, "key"), Err(TemplateError::MissingEntry(5, "api \"key\"".to_string())));
+        assert_eq!(check(TEMPLATE, &pt)?.len(), 1);
+
+        pt.add_password("api \"key\"", "token", PasswordMeta::default(), "key")?;
+        assert!(check(TEMPLATE, &pt)?.is_empty());
+        assert_eq!(render(TEMPLATE, &pt, "key")?, "db:
+  user: admin
+  password: hunter2
+  note: {{ .Values.untouched }}
+  other: token
+");
+        assert_eq!(render(TEMPLATE, &pt, "wrong"), Err(TemplateError::Decrypt(3, crate::IncorrectPass)));
+        Ok(())
+    }
+
+    #[test]
+    fn syntax_error_test() {
+        let pt = table();
+        assert_eq!(render("a\n{{ passtool \"db-prod\" \"secret\" }}", &pt, "key"), Err(TemplateError::UnknownField(2, "secret".to_string())));
+        assert!(matches!(render("{{ passtool db-prod }}", &pt, "key"), Err(TemplateError::Syntax(1, _))));
+        assert!(matches!(render("{{ passtool }}", &pt, "key"), Err(TemplateError::Syntax(1, _))));
+        assert!(matches!(render("x {{ passtool \"db-prod\"", &pt, "key"), Err(TemplateError::Syntax(1, _))));
+        assert_eq!(render("x {{ y", &pt, "key"), Ok("x {{ y".to_string()));
+    }
+}
@@ -0,0 +1,682 @@
+//! Full-screen terminal browser with the panes of the Windows overlay: entries of the current app,
+//! all entries narrowed by a `search` query, the apps of the selected entry and the key prompt.
+//! Copied text goes to the `clipboard` service, over SSH that is the terminal's clipboard.
+use std::{error::Error, path::{Path, PathBuf}};
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, Clear, List, ListState, Paragraph},
+    Frame
+};
+
+use crate::{app_rules, clipboard::ClipboardService, generator, search::{self, Query}, store, EntryKind, IncorrectPass, PassTable, PasswordMeta};
+
+const HELP: &str = "/ search  tab pane  enter copy  r reveal  u username  n new  e edit  d delete  g generate  a add app  x remove app  L lock  q quit";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Pane {
+    Recommended,
+    All,
+    Apps
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Action {
+    Copy,
+    Reveal
+}
+
+const FORM_FIELDS: [&str; 7] = ["Name", "Password", "Key", "Repeat key", "Description", "Username", "URL"];
+const PASSWORD: usize = 1;
+
+struct Form {
+    /// Entry being edited, it is renamed if the name changes.
+    editing: Option<String>,
+    fields: [String; 7],
+    focus: usize
+}
+
+struct Generator {
+    length: String,
+    letters: bool,
+    digits: bool,
+    special: bool,
+    focus: usize
+}
+
+enum Dialog {
+    Key{name: String, action: Action, input: String},
+    Form(Form),
+    Generator(Generator),
+    Delete(String)
+}
+
+fn edit_text(text: &mut String, key: KeyEvent) {
+    match key.code {
+        KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => text.push(c),
+        KeyCode::Backspace => { text.pop(); }
+        _ => {}
+    }
+}
+
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let (width, height) = (width.min(area.width), height.min(area.height));
+    Rect::new(area.x + (area.width - width) / 2, area.y + (area.height - height) / 2, width, height)
+}
+
+pub struct Browser {
+    vault: PathBuf,
+    /// Opens the vault if it is a journal.
+    vault_key: Option<String>,
+    table: PassTable,
+    /// Entries listing this app are offered in the first pane.
+    app: Option<String>,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    agent: Option<PathBuf>,
+    key: Option<String>,
+    query: String,
+    searching: bool,
+    pane: Pane,
+    /// Pane the selected entry is taken from.
+    entry_pane: Pane,
+    recommended: Vec<String>,
+    all: Vec<String>,
+    selected: [usize; 3],
+    revealed: Option<(String, String)>,
+    dialogs: Vec<Dialog>,
+    status: String,
+    /// Handed to the clipboard after the next draw.
+    copied: Option<String>,
+    quit: bool
+}
+
+impl Browser {
+    /// Browser for `vault`, an empty vault is created if there is none.
+    pub fn open(vault: PathBuf, vault_key: Option<String>, app: Option<String>) -> Result<Self, Box<dyn Error>> {
+        let table = store::create(&vault, &mut || vault_key.clone().ok_or_else(|| "missing key".into()))?.load()?;
+        #[cfg(unix)]
+        let agent = Some(crate::unlock_agent::default_socket_path());
+        #[cfg(not(unix))]
+        let agent = None;
+        let pane = if app.is_some() {Pane::Recommended} else {Pane::All};
+        let mut browser = Browser{vault, vault_key, table, app, agent, key: None, query: String::new(), searching: false, pane, entry_pane: pane,
+            recommended: Vec::new(), all: Vec::new(), selected: [0; 3], revealed: None, dialogs: Vec::new(), status: String::new(), copied: None, quit: false};
+        browser.refresh();
+        if browser.pane == Pane::Recommended && browser.recommended.is_empty() {
+            browser.pane = Pane::All;
+            browser.entry_pane = Pane::All;
+        }
+        Ok(browser)
+    }
+
+    /// Recomputes the entry lists from the table and the search query.
+    fn refresh(&mut self) {
+        self.all = search::search(&self.table, &Query::parse(&self.query));
+        self.recommended = match &self.app {
+            Some(app) => app_rules::rank(&self.table, app, None).into_iter().filter(|name| self.all.contains(name)).collect(),
+            None => Vec::new()
+        };
+        for pane in [Pane::Recommended, Pane::All, Pane::Apps] {
+            self.selected[pane as usize] = self.selected[pane as usize].min(self.len(pane).saturating_sub(1));
+        }
+    }
+
+    fn apps(&self) -> &[String] {
+        match self.selected_entry() {
+            Some(name) => &self.table.get_metadata(name).unwrap().apps,
+            None => &[]
+        }
+    }
+
+    fn len(&self, pane: Pane) -> usize {
+        match pane {
+            Pane::Recommended => self.recommended.len(),
+            Pane::All => self.all.len(),
+            Pane::Apps => self.apps().len()
+        }
+    }
+
+    fn selected_entry(&self) -> Option<&String> {
+        let names = if self.entry_pane == Pane::Recommended {&self.recommended} else {&self.all};
+        names.get(self.selected[self.entry_pane as usize])
+    }
+
+    fn select(&mut self, pane: Pane) {
+        self.pane = pane;
+        if pane != Pane::Apps {
+            self.entry_pane = pane;
+            self.selected[Pane::Apps as usize] = 0;
+        }
+    }
+
+    fn next_pane(&mut self) {
+        let next = match self.pane {
+            Pane::Recommended => Pane::All,
+            Pane::All => Pane::Apps,
+            Pane::Apps if self.app.is_some() => Pane::Recommended,
+            Pane::Apps => Pane::All
+        };
+        self.select(next);
+    }
+
+    fn move_selection(&mut self, down: bool) {
+        let len = self.len(self.pane);
+        let selected = &mut self.selected[self.pane as usize];
+        *selected = if down {(*selected + 1).min(len.saturating_sub(1))} else {selected.saturating_sub(1)};
+        if self.pane != Pane::Apps {
+            self.selected[Pane::Apps as usize] = 0;
+        }
+    }
+
+    /// Applies `update` to the vault file under its lock and reloads it, so changes made elsewhere are kept.
+    fn modify(&mut self, update: impl FnOnce(&mut PassTable) -> Result<(), Box<dyn Error>>) -> bool {
+        let mut vault_key = || self.vault_key.clone().ok_or_else(|| "missing key".into());
+        match store::update(&self.vault, &mut vault_key, update).and_then(|()| store::load(&self.vault, &mut vault_key)) {
+            Ok(table) => {
+                self.table = table;
+                self.refresh();
+                true
+            }
+            Err(e) => {
+                self.status = e.to_string();
+                false
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn agent_password(&self, name: &str) -> Option<String> {
+        crate::unlock_agent::cached_password(self.agent.as_ref()?, &self.vault, name)
+    }
+
+    #[cfg(not(unix))]
+    fn agent_password(&self, _name: &str) -> Option<String> {
+        None
+    }
+
+    /// Decrypts the selected entry with the known key or the unlock agent, asks for the key otherwise.
+    fn request(&mut self, action: Action) {
+        let Some(name) = self.selected_entry().cloned() else { return };
+        let password = match &self.key {
+            Some(key) => self.table.get_password(&name, key).ok(),
+            None => None
+        };
+        match password.or_else(|| self.agent_password(&name)) {
+            Some(password) => self.finish(name, action, password),
+            None => self.dialogs.push(Dialog::Key{name, action, input: String::new()})
+        }
+    }
+
+    fn finish(&mut self, name: String, action: Action, password: String) {
+        match action {
+            Action::Copy => {
+                self.modify(|table| Ok(table.mark_accessed(&name)?));
+                self.status = format!("Password of '{name}' copied");
+                self.copied = Some(password);
+            }
+            Action::Reveal => self.revealed = Some((name, password))
+        }
+    }
+
+    fn new_form(&self) -> Form {
+        Form{editing: None, fields: Default::default(), focus: 0}
+    }
+
+    fn edit_form(&self, name: &str) -> Form {
+        let meta = self.table.get_metadata(name).unwrap();
+        let fields = [name.to_string(), String::new(), String::new(), String::new(), meta.description.clone(), meta.username.clone(), meta.url.clone()];
+        Form{editing: Some(name.to_string()), fields, focus: PASSWORD}
+    }
+
+    /// Saves the form, the same rules as the overlay's add/edit window apply.
+    fn submit(&mut self, form: &Form) -> bool {
+        let [name, password, key, repeat, description, username, url] = form.fields.clone();
+        let error = if name.is_empty() {
+            Some("Empty name is not allowed!")
+        } else if form.editing.as_ref() != Some(&name) && self.table.contains(&name) {
+            Some("Password with this name already exists!")
+        } else if form.editing.is_none() && password.is_empty() {
+            Some("Empty password is not allowed!")
+        } else if form.editing.is_some() && password.is_empty() != (key.is_empty() && repeat.is_empty()) {
+            Some("It's impossible to update password without specifying the key and vice versa!")
+        } else if !password.is_empty() && key.is_empty() {
+            Some("Empty key is not allowed!")
+        } else if key != repeat {
+            Some("Keys do not match!")
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            self.status = error.to_string();
+            return false;
+        }
+        let editing = form.editing.clone();
+        let saved = self.modify(|table| {
+            let Some(old) = editing else {
+                table.add_password(&name, &password, PasswordMeta{description, username, url, ..Default::default()}, &key)?;
+                return Ok(());
+            };
+            table.rename(&old, &name)?;
+            let meta = table.get_metadata_mut(&name)?;
+            meta.description = description;
+            meta.username = username;
+            meta.url = url;
+            if !password.is_empty() {
+                table.update_password(&name, &password, &key)?;
+            }
+            Ok(())
+        });
+        if saved {
+            self.status = format!("Saved '{name}'");
+            self.revealed = None;
+        }
+        saved
+    }
+
+    pub fn handle(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+        self.status.clear();
+        match self.dialogs.pop() {
+            Some(dialog) => self.handle_dialog(dialog, key),
+            None if self.searching => self.handle_search(key),
+            None => self.handle_browse(key)
+        }
+    }
+
+    fn handle_search(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Enter => self.searching = false,
+            KeyCode::Esc => {
+                self.searching = false;
+                self.query.clear();
+            }
+            KeyCode::Up | KeyCode::Down => return self.move_selection(key.code == KeyCode::Down),
+            _ => edit_text(&mut self.query, key)
+        }
+        self.refresh();
+    }
+
+    fn handle_browse(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Char('/') => {
+                self.searching = true;
+                if self.pane == Pane::Apps { self.select(self.entry_pane); }
+            }
+            KeyCode::Tab => self.next_pane(),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(false),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(true),
+            KeyCode::Enter | KeyCode::Char('c') => self.request(Action::Copy),
+            KeyCode::Char('r') => match (&self.revealed, self.selected_entry()) {
+                (Some((revealed, _)), Some(name)) if revealed == name => self.revealed = None,
+                _ => self.request(Action::Reveal)
+            },
+            KeyCode::Char('u') => if let Some(name) = self.selected_entry().cloned() {
+                self.copied = Some(self.table.get_metadata(&name).unwrap().username.clone());
+                self.status = format!("Username of '{name}' copied");
+            },
+            KeyCode::Char('n') => self.dialogs.push(Dialog::Form(self.new_form())),
+            KeyCode::Char('e') => if let Some(name) = self.selected_entry().cloned() {
+                let form = self.edit_form(&name);
+                self.dialogs.push(Dialog::Form(form));
+            },
+            KeyCode::Char('d') => if let Some(name) = self.selected_entry().cloned() {
+                self.dialogs.push(Dialog::Delete(name));
+            },
+            KeyCode::Char('g') => self.dialogs.push(Dialog::Generator(Generator{length: "16".to_string(), letters: true, digits: true, special: true, focus: 0})),
+            KeyCode::Char('a') => if let (Some(app), Some(name)) = (self.app.clone(), self.selected_entry().cloned()) {
+                self.modify(|table| {
+                    let apps = &mut table.get_metadata_mut(&name)?.apps;
+                    if !apps.contains(&app) { apps.push(app); }
+                    Ok(())
+                });
+            },
+            KeyCode::Char('x') | KeyCode::Delete if self.pane == Pane::Apps => if let Some(name) = self.selected_entry().cloned() {
+                let index = self.selected[Pane::Apps as usize];
+                if index < self.apps().len() {
+                    self.modify(|table| {
+                        table.get_metadata_mut(&name)?.remove_app(index);
+                        Ok(())
+                    });
+                }
+            },
+            KeyCode::Char('L') => {
+                self.key = None;
+                self.revealed = None;
+                self.status = "Key forgotten".to_string();
+            }
+            _ => {}
+        }
+    }
+
+    /// `dialog` was taken off the stack, it is put back while it stays open.
+    fn handle_dialog(&mut self, dialog: Dialog, key: KeyEvent) {
+        match dialog {
+            Dialog::Key{name, action, mut input} => match key.code {
+                KeyCode::Esc => {}
+                KeyCode::Enter => match self.table.get_password(&name, &input) {
+                    Ok(password) => {
+                        self.key = Some(input);
+                        self.finish(name, action, password);
+                    }
+                    Err(IncorrectPass) => {
+                        self.status = "Incorrect key!".to_string();
+                        self.dialogs.push(Dialog::Key{name, action, input: String::new()});
+                    }
+                    Err(e) => self.status = e.to_string()
+                },
+                _ => {
+                    edit_text(&mut input, key);
+                    self.dialogs.push(Dialog::Key{name, action, input});
+                }
+            },
+            Dialog::Form(mut form) => match key.code {
+                KeyCode::Esc => {}
+                KeyCode::Enter => if !self.submit(&form) {
+                    self.dialogs.push(Dialog::Form(form));
+                },
+                KeyCode::Char('g') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    self.dialogs.push(Dialog::Form(form));
+                    self.dialogs.push(Dialog::Generator(Generator{length: "16".to_string(), letters: true, digits: true, special: true, focus: 0}));
+                }
+                code => {
+                    match code {
+                        KeyCode::Tab | KeyCode::Down => form.focus = (form.focus + 1) % FORM_FIELDS.len(),
+                        KeyCode::BackTab | KeyCode::Up => form.focus = (form.focus + FORM_FIELDS.len() - 1) % FORM_FIELDS.len(),
+                        _ => edit_text(&mut form.fields[form.focus], key)
+                    }
+                    self.dialogs.push(Dialog::Form(form));
+                }
+            },
+            Dialog::Generator(mut generator) => match key.code {
+                KeyCode::Esc => {}
+                KeyCode::Enter => {
+                    let (letters, digits, special) = (generator.letters, generator.digits, generator.special);
+                    match generator.length.parse::<u16>() {
+                        _ if !letters && !digits && !special => self.status = "Choose at least one option!".to_string(),
+                        Ok(length) if length > 0 => {
+                            let password = generator::generate_password(length, letters, digits, special);
+                            if let Some(Dialog::Form(form)) = self.dialogs.last_mut() {
+                                form.fields[PASSWORD] = password;
+                            } else {
+                                self.status = "Generated password copied".to_string();
+                                self.copied = Some(password);
+                            }
+                            return;
+                        }
+                        _ => self.status = "Enter password length!".to_string()
+                    }
+                    self.dialogs.push(Dialog::Generator(generator));
+                }
+                code => {
+                    match code {
+                        KeyCode::Tab | KeyCode::Down => generator.focus = (generator.focus + 1) % 4,
+                        KeyCode::BackTab | KeyCode::Up => generator.focus = (generator.focus + 3) % 4,
+                        KeyCode::Char(' ') if generator.focus > 0 => {
+                            let option = [&mut generator.letters, &mut generator.digits, &mut generator.special].into_iter().nth(generator.focus - 1).unwrap();
+                            *option = !*option;
+                        }
+                        KeyCode::Char(c) if generator.focus == 0 && !c.is_ascii_digit() => {}
+                        _ if generator.focus == 0 => edit_text(&mut generator.length, key),
+                        _ => {}
+                    }
+                    self.dialogs.push(Dialog::Generator(generator));
+                }
+            },
+            Dialog::Delete(name) => match key.code {
+                KeyCode::Char('y') => if self.modify(|table| Ok(table.remove_password(&name)?)) {
+                    self.status = format!("Moved '{name}' to the trash");
+                },
+                KeyCode::Char('n') | KeyCode::Esc => {}
+                _ => self.dialogs.push(Dialog::Delete(name))
+            }
+        }
+    }
+
+    fn entry_list(&self, frame: &mut Frame, area: Rect, pane: Pane, title: String) {
+        let names = if pane == Pane::Recommended {&self.recommended} else {&self.all};
+        let items = names.iter().map(|name| {
+            let description = &self.table.get_metadata(name).unwrap().description;
+            Line::from(if description.is_empty() {name.clone()} else {format!("{name}  ({description})")})
+        });
+        self.list(frame, area, pane, title, items.collect());
+    }
+
+    fn list(&self, frame: &mut Frame, area: Rect, pane: Pane, title: String, items: Vec<Line>) {
+        let focused = self.pane == pane && self.dialogs.is_empty();
+        let border = if focused {Style::new().fg(Color::Yellow)} else {Style::new()};
+        let list = List::new(items)
+            .block(Block::bordered().title(title).border_style(border))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        let selected = (pane == self.pane || pane == self.entry_pane) && self.len(pane) > 0;
+        let mut state = ListState::default().with_selected(selected.then_some(self.selected[pane as usize]));
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+
+    fn details(&self, frame: &mut Frame, area: Rect) {
+        let mut lines = Vec::new();
+        if let Some(name) = self.selected_entry() {
+            let meta = self.table.get_metadata(name).unwrap();
+            let password = match &self.revealed {
+                Some((revealed, password)) if revealed == name => password.clone(),
+                _ => "********".to_string()
+            };
+            lines.push(Line::from(format!("Name:        {name}")));
+            if meta.kind == EntryKind::SshKey { lines.push(Line::from("Kind:        SSH key")); }
+            lines.push(Line::from(format!("Username:    {}", meta.username)));
+            lines.push(Line::from(format!("URL:         {}", meta.url)));
+            lines.push(Line::from(format!("Description: {}", meta.description)));
+            lines.push(Line::from(format!("Tags:        {}", meta.tags.join(", "))));
+            lines.extend(password.lines().enumerate().map(|(i, line)| Line::from(format!("{}{line}", if i == 0 {"Password:    "} else {"             "}))));
+        }
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title("Details")), area);
+    }
+
+    fn draw_dialog(&self, frame: &mut Frame, dialog: &Dialog) {
+        let (title, lines, height) = match dialog {
+            Dialog::Key{name, input, ..} => (format!("Key for '{name}'"), vec![Line::from("*".repeat(input.chars().count()))], 3),
+            Dialog::Form(form) => {
+                let lines = FORM_FIELDS.iter().zip(&form.fields).enumerate().map(|(i, (label, value))| {
+                    let value = if (1..=3).contains(&i) {"*".repeat(value.chars().count())} else {value.clone()};
+                    let line = Line::from(format!("{label:>12}: {value}"));
+                    if i == form.focus {line.style(Style::new().add_modifier(Modifier::REVERSED))} else {line}
+                });
+                let mut lines: Vec<Line> = lines.collect();
+                lines.push(Line::from("enter save  tab next field  ctrl-g generate  esc cancel"));
+                let title = if form.editing.is_some() {"Edit password"} else {"Add password"};
+                (title.to_string(), lines, FORM_FIELDS.len() as u16 + 3)
+            }
+            Dialog::Generator(generator) => {
+                let check = |on: bool| if on {"[x]"} else {"[ ]"};
+                let lines = [format!("Length:  {}", generator.length), format!("{} Letters", check(generator.letters)),
+                    format!("{} Digits", check(generator.digits)), format!("{} Special", check(generator.special))];
+                let mut lines: Vec<Line> = lines.into_iter().enumerate()
+                    .map(|(i, line)| if i == generator.focus {Line::from(line).style(Style::new().add_modifier(Modifier::REVERSED))} else {Line::from(line)})
+                    .collect();
+                lines.push(Line::from("enter generate  space toggle  esc cancel"));
+                ("Generate password".to_string(), lines, 7)
+            }
+            Dialog::Delete(name) => ("Delete password".to_string(), vec![Line::from(format!("Move the password '{name}' to the trash? (y/n)"))], 3)
+        };
+        let area = centered(frame.area(), 64, height);
+        frame.render_widget(Clear, area);
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(title)), area);
+    }
+
+    pub fn draw(&self, frame: &mut Frame) {
+        let [main, search, status] = Layout::vertical([Constraint::Min(0), Constraint::Length(3), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] = Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)]).areas(main);
+        match &self.app {
+            Some(app) => {
+                let [recommended, all] = Layout::vertical([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(left);
+                let app = Path::new(app).file_name().map_or(app.clone(), |name| name.to_string_lossy().into_owned());
+                self.entry_list(frame, recommended, Pane::Recommended, format!("App-specific passwords ({app})"));
+                self.entry_list(frame, all, Pane::All, "All passwords".to_string());
+            }
+            None => self.entry_list(frame, left, Pane::All, "All passwords".to_string())
+        }
+        let [details, apps] = Layout::vertical([Constraint::Min(9), Constraint::Percentage(40)]).areas(right);
+        self.details(frame, details);
+        self.list(frame, apps, Pane::Apps, "Password's apps".to_string(), self.apps().iter().map(|app| Line::from(app.clone())).collect());
+
+        let border = if self.searching {Style::new().fg(Color::Yellow)} else {Style::new()};
+        frame.render_widget(Paragraph::new(self.query.as_str()).block(Block::bordered().title("Search").border_style(border)), search);
+        frame.render_widget(Paragraph::new(if self.status.is_empty() {HELP} else {self.status.as_str()}), status);
+        for dialog in &self.dialogs {
+            self.draw_dialog(frame, dialog);
+        }
+    }
+}
+
+/// Runs the browser on the terminal until it is quit, `vault_key` is needed for journals.
+pub fn run(vault: PathBuf, vault_key: Option<String>, app: Option<String>) -> Result<(), Box<dyn Error>> {
+    let mut browser = Browser::open(vault, vault_key, app)?;
+    let clipboard = ClipboardService::default();
+    let mut terminal = ratatui::init();
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        while !browser.quit {
+            terminal.draw(|frame| browser.draw(frame))?;
+            if let Some(text) = browser.copied.take() {
+                clipboard.copy(&text)?;
+            }
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press { browser.handle(key); }
+            }
+        }
+        Ok(())
+    })();
+    ratatui::restore();
+    clipboard.shutdown();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::{backend::TestBackend, Terminal};
+
+    fn press(browser: &mut Browser, keys: &str) {
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                '\t' => KeyCode::Tab,
+                '\x1b' => KeyCode::Esc,
+                c => KeyCode::Char(c)
+            };
+            browser.handle(KeyEvent::from(code));
+        }
+    }
+
+    fn browser(name: &str) -> Result<Browser, Box<dyn Error>> {
+        let vault = std::env::temp_dir().join(format!("passtool-tui-{name}-{}.pt", std::process::id()));
+        let mut pt = PassTable::new();
+        pt.add_password("github", "hunter2", PasswordMeta{username: "alice".to_string(), url: "https://github.com".to_string(), apps: vec!["/usr/bin/git".to_string()], ..Default::default()}, "key")?;
+        pt.add_password("gmail", "p2", PasswordMeta::new("mail".to_string(), Vec::new()), "key")?;
+        pt.add_password("bank", "p3", PasswordMeta::default(), "key")?;
+        pt.to_file(&vault)?;
+        let mut browser = Browser::open(vault, None, Some("/usr/bin/git".to_string()))?;
+        browser.agent = None;
+        Ok(browser)
+    }
+
+    #[test]
+    fn browse_test() -> Result<(), Box<dyn Error>> {
+        let mut browser = browser("browse")?;
+        assert_eq!(browser.recommended, ["github"]);
+        assert_eq!(browser.all, ["bank", "github", "gmail"]);
+        assert_eq!(browser.pane, Pane::Recommended);
+
+        press(&mut browser, "\t/gma\n");
+        assert_eq!(browser.all, ["gmail"]);
+        press(&mut browser, "\n");
+        assert!(matches!(browser.dialogs.last(), Some(Dialog::Key{..})));
+        press(&mut browser, "wrong\n");
+        assert_eq!(browser.status, "Incorrect key!");
+        press(&mut browser, "key\n");
+        assert_eq!(browser.copied.take().as_deref(), Some("p2"));
+        assert!(PassTable::from_file(&browser.vault)?.get_times("gmail")?.accessed > 0);
+
+        // the key is remembered for the session
+        press(&mut browser, "r");
+        assert_eq!(browser.revealed, Some(("gmail".to_string(), "p2".to_string())));
+        press(&mut browser, "r");
+        assert_eq!(browser.revealed, None);
+        press(&mut browser, "L/\x1b\t\t");
+        assert_eq!(browser.pane, Pane::Recommended);
+        press(&mut browser, "c");
+        assert!(matches!(browser.dialogs.last(), Some(Dialog::Key{..})));
+        press(&mut browser, "\x1bu");
+        assert_eq!(browser.copied.as_deref(), Some("alice"));
+        std::fs::remove_file(&browser.vault)?;
+        Ok(())
+    }
+
+    #[test]
+    fn edit_test() -> Result<(), Box<dyn Error>> {
+        let mut browser = browser("edit")?;
+        press(&mut browser, "nnew\tsecret\tkey\tkeys\n");
+        assert_eq!(browser.status, "Keys do not match!");
+        browser.handle(KeyEvent::from(KeyCode::Backspace));
+        press(&mut browser, "\tnotes\n");
+        assert!(browser.dialogs.is_empty());
+        let saved = PassTable::from_file(&browser.vault)?;
+        assert_eq!(saved.get_password("new", "key")?, "secret");
+        assert_eq!(saved.get_metadata("new")?.description, "notes");
+
+        // edit the description of the selected entry, the password stays
+        press(&mut browser, "e\t\t\t!\n");
+        let saved = PassTable::from_file(&browser.vault)?;
+        assert_eq!(saved.get_metadata("github")?.description, "!");
+        assert_eq!(saved.get_password("github", "key")?, "hunter2");
+
+        // renaming keeps the entry's id
+        let id = saved.get_id("github")?;
+        press(&mut browser, "e");
+        browser.handle(KeyEvent::from(KeyCode::BackTab));
+        press(&mut browser, "2\n");
+        assert_eq!(PassTable::from_file(&browser.vault)?.get_id("github2")?, id);
+        press(&mut browser, "e");
+        browser.handle(KeyEvent::from(KeyCode::BackTab));
+        browser.handle(KeyEvent::from(KeyCode::Backspace));
+        press(&mut browser, "\n");
+        assert_eq!(PassTable::from_file(&browser.vault)?.get_id("github")?, id);
+
+        // generated passwords fill the form
+        press(&mut browser, "e");
+        browser.handle(KeyEvent::new(KeyCode::Char('g'), KeyModifiers::CONTROL));
+        press(&mut browser, "\n");
+        let Some(Dialog::Form(form)) = browser.dialogs.last() else { panic!() };
+        assert_eq!(form.fields[PASSWORD].len(), 16);
+        press(&mut browser, "\x1b");
+
+        press(&mut browser, "\tdn");
+        assert!(browser.table.contains("bank"));
+        press(&mut browser, "dy");
+        assert!(!PassTable::from_file(&browser.vault)?.contains("bank"));
+
+        press(&mut browser, "\tx");
+        assert!(PassTable::from_file(&browser.vault)?.get_metadata("github")?.apps.is_empty());
+        std::fs::remove_file(&browser.vault)?;
+        Ok(())
+    }
+
+    #[test]
+    fn draw_test() -> Result<(), Box<dyn Error>> {
+        let mut browser = browser("draw")?;
+        press(&mut browser, "d");
+        let mut terminal = Terminal::new(TestBackend::new(100, 30))?;
+        terminal.draw(|frame| browser.draw(frame))?;
+        let screen: String = terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect();
+        for text in ["App-specific passwords (git)", "All passwords", "Password's apps", "Username:    alice", "Delete password"] {
+            assert!(screen.contains(text), "{text}");
+        }
+        std::fs::remove_file(&browser.vault)?;
+        Ok(())
+    }
+}
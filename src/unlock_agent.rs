@@ -0,0 +1,476 @@
+//! Background agent that keeps derived keys between command line invocations.
+//! Clients talk newline-delimited JSON over a unix socket only the owner can open,
+//! the keys are forgotten after an idle timeout or an explicit `lock`.
+//! Both sides check that the socket is in a directory only the user can access and that the peer is the same user.
+//! An agent serves the vault it was started for, requests name the vault they are about.
+use std::{io::{self, BufRead, BufReader, Write}, path::{Path, PathBuf}, sync::Mutex, time::{Duration, Instant}};
+
+use serde::{Serialize, Deserialize};
+use zeroize::Zeroize;
+
+use crate::{DerivedKey, PassTable, TableSource};
+
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "cmd", rename_all = "lowercase")]
+pub enum Request {
+    Unlock{vault: PathBuf, key: String},
+    Get{vault: PathBuf, name: String},
+    Lock,
+    Status
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Response {
+    Ok,
+    Password{password: String},
+    /// None of the held keys opens the entry, the client has to unlock with its key.
+    Locked,
+    Status{locked: bool},
+    /// The agent serves another vault, the client has to decrypt on its own.
+    OtherVault,
+    Error{message: String}
+}
+
+/// Canonical form of `vault`, the way agents and clients compare vaults.
+pub fn vault_id(vault: &Path) -> PathBuf {
+    vault.canonicalize().unwrap_or_else(|_| vault.to_path_buf())
+}
+
+/// A page of its own, so unlocking one key's memory leaves the others locked.
+#[repr(align(4096))]
+struct Page(DerivedKey);
+
+/// Derived key on the heap, kept out of swap while it is held if the system allows it.
+struct LockedKey {
+    page: Box<Page>,
+    locked: bool
+}
+
+impl LockedKey {
+    /// Moves `key` into locked memory and leaves zeros where it was.
+    fn new(key: &mut DerivedKey) -> Self {
+        let mut page = Box::new(Page(DerivedKey::zeroed()));
+        #[cfg(unix)]
+        let locked = unsafe { libc::mlock(&*page as *const Page as *const libc::c_void, std::mem::size_of::<Page>()) } == 0;
+        #[cfg(not(unix))]
+        let locked = false;
+        std::mem::swap(&mut page.0, key);
+        LockedKey{page, locked}
+    }
+
+    fn get(&self) -> &DerivedKey {
+        &self.page.0
+    }
+}
+
+impl Drop for LockedKey {
+    fn drop(&mut self) {
+        // wiped while still locked, the memory is only freed after it is unlocked
+        self.page.0.wipe();
+        if self.locked {
+            #[cfg(unix)]
+            unsafe { libc::munlock(&*self.page as *const Page as *const libc::c_void, std::mem::size_of::<Page>()); }
+        }
+    }
+}
+
+pub struct UnlockAgent {
+    /// As returned by `vault_id`.
+    vault: PathBuf,
+    source: TableSource,
+    keys: Vec<LockedKey>,
+    idle_timeout: Duration,
+    last_used: Instant
+}
+
+impl UnlockAgent {
+    /// Creates a locked agent for `vault`, `source` loads it for every request.
+    pub fn new(vault: &Path, source: TableSource, idle_timeout: Duration) -> Self {
+        UnlockAgent{vault: vault_id(vault), source, keys: Vec::new(), idle_timeout, last_used: Instant::now()}
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Forgets the keys if nothing was unlocked or read for the idle timeout.
+    pub fn expire(&mut self, now: Instant) {
+        if now.duration_since(self.last_used) >= self.idle_timeout {
+            self.keys.clear();
+        }
+    }
+
+    pub fn handle(&mut self, request: Request, now: Instant) -> Response {
+        self.expire(now);
+        let table = match request {
+            Request::Lock => {
+                self.keys.clear();
+                return Response::Ok;
+            }
+            Request::Status => return Response::Status{locked: self.is_locked()},
+            Request::Unlock{ref vault, ..} | Request::Get{ref vault, ..} if vault_id(vault) != self.vault => return Response::OtherVault,
+            _ => match (self.source)() {
+                Ok(table) => table,
+                Err(e) => return Response::Error{message: e.to_string()}
+            }
+        };
+
+        match request {
+            Request::Unlock{mut key, ..} => {
+                let mut derived = DerivedKey::new(&key);
+                key.zeroize();
+                let key = &mut derived;
+                let mut names = table.get_names().peekable();
+                let opens = names.peek().is_none() || names.any(|name| table.get_password_with(name, key).is_ok());
+                if !opens {
+                    return Response::Error{message: crate::IncorrectPass.to_string()};
+                }
+                if !self.keys.iter().any(|held| held.get() == key) {
+                    self.keys.push(LockedKey::new(key));
+                }
+                self.last_used = now;
+                Response::Ok
+            }
+            Request::Get{name, ..} => {
+                for key in &self.keys {
+                    match table.get_password_with(&name, key.get()) {
+                        Ok(password) => {
+                            self.last_used = now;
+                            return Response::Password{password};
+                        }
+                        Err(crate::IncorrectPass) => continue,
+                        Err(e) => return Response::Error{message: e.to_string()}
+                    }
+                }
+                Response::Locked
+            }
+            Request::Lock | Request::Status => unreachable!()
+        }
+    }
+}
+
+/// Answers JSON requests, one per line, until the stream is closed.
+pub fn serve<S: io::Read + Write>(agent: &Mutex<UnlockAgent>, stream: S) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? != 0 {
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => agent.lock().unwrap().handle(request, Instant::now()),
+            Err(e) => Response::Error{message: format!("invalid request: {e}")}
+        };
+        line.zeroize(); // may hold a key
+        let stream = reader.get_mut();
+        serde_json::to_writer(&mut *stream, &response)?;
+        stream.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// `$PASSTOOL_AGENT_SOCK`, `$XDG_RUNTIME_DIR/passtool-agent.sock` or a per-user directory in the temp dir.
+#[cfg(unix)]
+pub fn default_socket_path() -> std::path::PathBuf {
+    use std::path::PathBuf;
+    if let Some(path) = std::env::var_os("PASSTOOL_AGENT_SOCK") {
+        return PathBuf::from(path);
+    }
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        return PathBuf::from(dir).join("passtool-agent.sock");
+    }
+    let uid = unsafe { libc::getuid() };
+    std::env::temp_dir().join(format!("passtool-{uid}")).join("agent.sock")
+}
+
+/// Fails unless `dir` is a directory of the current user that nobody else can access,
+/// otherwise another user could put their own socket there.
+#[cfg(unix)]
+fn check_private_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    let meta = std::fs::symlink_metadata(dir)?;
+    if !meta.is_dir() || meta.uid() != unsafe { libc::getuid() } || meta.permissions().mode() & 0o077 != 0 {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+            format!("{} is not a directory only the current user can access", dir.display())));
+    }
+    Ok(())
+}
+
+/// User id of the process on the other end of `stream`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &std::os::unix::net::UnixStream) -> io::Result<u32> {
+    use std::os::fd::AsRawFd;
+    let mut cred = libc::ucred{pid: 0, uid: 0, gid: 0};
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(stream.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PEERCRED, &mut cred as *mut libc::ucred as *mut libc::c_void, &mut len)
+    };
+    if result != 0 { return Err(io::Error::last_os_error()); }
+    Ok(cred.uid)
+}
+
+/// User id of the process on the other end of `stream`.
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn peer_uid(stream: &std::os::unix::net::UnixStream) -> io::Result<u32> {
+    use std::os::fd::AsRawFd;
+    let (mut uid, mut gid) = (0, 0);
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 { return Err(io::Error::last_os_error()); }
+    Ok(uid)
+}
+
+#[cfg(unix)]
+fn is_same_user(stream: &std::os::unix::net::UnixStream) -> bool {
+    peer_uid(stream).is_ok_and(|uid| uid == unsafe { libc::getuid() })
+}
+
+/// Listens on `path`, the socket is only accessible by the current user.
+/// Blocks forever, expiring keys in the background.
+#[cfg(unix)]
+pub fn listen<P: AsRef<std::path::Path>>(path: P, agent: UnlockAgent) -> io::Result<()> {
+    use std::{fs, os::unix::{fs::{DirBuilderExt, PermissionsExt}, net::{UnixListener, UnixStream}}, sync::Arc, thread};
+
+    let path = path.as_ref();
+    if let Some(dir) = path.parent() {
+        // an existing directory has to pass the check, whoever created it
+        match fs::DirBuilder::new().mode(0o700).create(dir) {
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+            _ => check_private_dir(dir)?
+        }
+    }
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, "agent is already running"));
+        }
+        fs::remove_file(path)?; // left over from an agent that died
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+    let agent = Arc::new(Mutex::new(agent));
+    let expiring = agent.clone();
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(1));
+        expiring.lock().unwrap().expire(Instant::now());
+    });
+    for stream in listener.incoming() {
+        let stream = stream?;
+        if !is_same_user(&stream) { continue; }
+        let agent = agent.clone();
+        thread::spawn(move || serve(&agent, stream));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub struct Client {
+    reader: BufReader<std::os::unix::net::UnixStream>
+}
+
+#[cfg(unix)]
+impl Client {
+    /// Connects to the agent on `path` after checking that it can be trusted with keys.
+    pub fn connect<P: AsRef<std::path::Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        check_private_dir(path.parent().unwrap_or(Path::new(".")))?;
+        let stream = std::os::unix::net::UnixStream::connect(path)?;
+        if !is_same_user(&stream) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} belongs to another user", path.display())));
+        }
+        Ok(Client{reader: BufReader::new(stream)})
+    }
+
+    pub fn request(&mut self, request: &Request) -> io::Result<Response> {
+        let stream = self.reader.get_mut();
+        serde_json::to_writer(&mut *stream, request)?;
+        stream.write_all(b"\n")?;
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+/// Password of `name` in `vault` if an agent for the vault is running on `socket` and holds its key,
+/// it is never prompted for.
+#[cfg(unix)]
+pub fn cached_password<P: AsRef<Path>>(socket: P, vault: &Path, name: &str) -> Option<String> {
+    let mut client = Client::connect(socket).ok()?;
+    match client.request(&Request::Get{vault: vault_id(vault), name: name.to_string()}).ok()? {
+        Response::Password{password} => Some(password),
+        _ => None
+    }
+}
+
+/// Decrypts `name` of the `table` loaded from `vault` through the agent on the default socket,
+/// unlocking it with `key()` when none of its keys fits.
+/// Without a running agent for the vault the entry is decrypted locally.
+#[cfg(unix)]
+pub fn get_password(vault: &Path, table: &PassTable, name: &str, key: &mut dyn FnMut() -> Result<String, Box<dyn std::error::Error>>) -> Result<String, Box<dyn std::error::Error>> {
+    if !table.contains(name) { return Err(crate::PassNotFound.into()); }
+    let Ok(mut client) = Client::connect(default_socket_path()) else {
+        return Ok(table.get_password(name, &key()?)?);
+    };
+    let vault = vault_id(vault);
+    let get = Request::Get{vault: vault.clone(), name: name.to_string()};
+    let mut response = client.request(&get)?;
+    if response == Response::Locked {
+        let mut unlock = Request::Unlock{vault, key: key()?};
+        let unlocked = client.request(&unlock);
+        if let Request::Unlock{key, ..} = &mut unlock { key.zeroize(); }
+        match unlocked? {
+            Response::Ok => response = client.request(&get)?,
+            other => response = other
+        }
+    }
+    match response {
+        Response::Password{password} => Ok(password),
+        Response::OtherVault => Ok(table.get_password(name, &key()?)?),
+        Response::Error{message} => Err(message.into()),
+        Response::Locked => Err(crate::IncorrectPass.into()),
+        other => Err(format!("unexpected agent response {other:?}").into())
+    }
+}
+
+#[cfg(not(unix))]
+pub fn get_password(_vault: &Path, table: &PassTable, name: &str, key: &mut dyn FnMut() -> Result<String, Box<dyn std::error::Error>>) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(table.get_password(name, &key()?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PasswordMeta;
+
+    const VAULT: &str = "/vaults/a.pt";
+
+    fn agent() -> UnlockAgent {
+        UnlockAgent::new(Path::new(VAULT), Box::new(|| {
+            let mut pt = PassTable::new();
+            pt.add_password("mail", "hunter2", PasswordMeta::default(), "key1")?;
+            pt.add_password("bank", "s3cret", PasswordMeta::default(), "key2")?;
+            Ok(pt)
+        }), Duration::from_secs(60))
+    }
+
+    fn get(name: &str) -> Request {
+        Request::Get{vault: VAULT.into(), name: name.to_string()}
+    }
+
+    fn unlock(key: &str) -> Request {
+        Request::Unlock{vault: VAULT.into(), key: key.to_string()}
+    }
+
+    #[test]
+    fn unlock_get_lock_test() {
+        let mut agent = agent();
+        let now = Instant::now();
+        assert_eq!(agent.handle(get("mail"), now), Response::Locked);
+        assert!(matches!(agent.handle(unlock("wrong"), now), Response::Error{..}));
+        assert_eq!(agent.handle(unlock("key1"), now), Response::Ok);
+        assert_eq!(agent.handle(get("mail"), now), Response::Password{password: "hunter2".to_string()});
+        assert_eq!(agent.handle(get("bank"), now), Response::Locked);
+        assert_eq!(agent.handle(get("missing"), now), Response::Error{message: "password not found".to_string()});
+
+        assert_eq!(agent.handle(unlock("key2"), now), Response::Ok);
+        assert_eq!(agent.handle(get("bank"), now), Response::Password{password: "s3cret".to_string()});
+        assert_eq!(agent.handle(Request::Status, now), Response::Status{locked: false});
+        assert_eq!(agent.handle(Request::Lock, now), Response::Ok);
+        assert_eq!(agent.handle(get("mail"), now), Response::Locked);
+    }
+
+    #[test]
+    fn other_vault_test() {
+        let mut agent = agent();
+        let now = Instant::now();
+        agent.handle(unlock("key1"), now);
+        let other = PathBuf::from("/vaults/b.pt");
+        assert_eq!(agent.handle(Request::Get{vault: other.clone(), name: "mail".to_string()}, now), Response::OtherVault);
+        assert_eq!(agent.handle(Request::Unlock{vault: other, key: "key2".to_string()}, now), Response::OtherVault);
+        assert_eq!(agent.handle(get("bank"), now), Response::Locked);
+    }
+
+    #[test]
+    fn locked_key_test() {
+        let mut key = DerivedKey::new("key1");
+        let locked = LockedKey::new(&mut key);
+        assert!(*locked.get() == DerivedKey::new("key1"));
+        assert!(key == DerivedKey::zeroed());
+        assert_eq!(locked.get() as *const DerivedKey as usize % 4096, 0);
+    }
+
+    #[test]
+    fn unlock_twice_test() {
+        let mut agent = agent();
+        let now = Instant::now();
+        agent.handle(unlock("key1"), now);
+        agent.handle(unlock("key1"), now);
+        assert_eq!(agent.keys.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_dir_test() -> io::Result<()> {
+        use std::{fs, os::unix::fs::PermissionsExt};
+        let dir = std::env::temp_dir().join(format!("passtool-agent-dir-{}", std::process::id()));
+        fs::create_dir(&dir)?;
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755))?;
+        let shared = check_private_dir(&dir);
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+        let private = check_private_dir(&dir);
+        fs::remove_dir(&dir)?;
+        assert!(shared.is_err());
+        assert!(private.is_ok());
+        assert!(Client::connect(std::env::temp_dir().join("agent.sock")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn idle_timeout_test() {
+        let mut agent = agent();
+        let start = Instant::now();
+        agent.handle(unlock("key1"), start);
+        let later = start + Duration::from_secs(50);
+        assert_eq!(agent.handle(get("mail"), later), Response::Password{password: "hunter2".to_string()});
+        agent.expire(later + Duration::from_secs(59));
+        assert!(!agent.is_locked());
+        agent.expire(later + Duration::from_secs(60));
+        assert!(agent.is_locked());
+    }
+
+    struct Duplex<'a> {
+        input: &'a [u8],
+        output: Vec<u8>
+    }
+
+    impl io::Read for Duplex<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Duplex<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn serve_test() {
+        let mut agent = agent();
+        agent.handle(unlock("key1"), Instant::now());
+        let agent = Mutex::new(agent);
+        let mut stream = Duplex{input: b"{\"cmd\":\"get\",\"vault\":\"/vaults/a.pt\",\"name\":\"mail\"}\nnot json\n{\"cmd\":\"lock\"}\n{\"cmd\":\"status\"}\n", output: Vec::new()};
+        serve(&agent, &mut stream).unwrap();
+        let responses: Vec<Response> = stream.output.split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(responses[0], Response::Password{password: "hunter2".to_string()});
+        assert!(matches!(responses[1], Response::Error{..}));
+        assert_eq!(responses[2], Response::Ok);
+        assert_eq!(responses[3], Response::Status{locked: true});
+    }
+}
@@ -0,0 +1,45 @@
+//! Runs passtool-askpass the way ssh, sudo and git do: prompt as argument, answer on stdout.
+use std::process::{Command, Stdio};
+
+use passtool::*;
+
+#[test]
+fn askpass_test() -> Result<(), Box<dyn std::error::Error>> {
+    let vault = std::env::temp_dir().join(format!("passtool-askpass-{}.pt", std::process::id()));
+    let mut pt = PassTable::new();
+    pt.add_password("git github.com", "hunter2", PasswordMeta{username: "alice".to_string(), ..Default::default()}, "key")?;
+    pt.to_file(&vault)?;
+    let rules = askpass::rules_path(&vault);
+    std::fs::write(&rules, "git $1 / username = ^Username for 'https://([^']+)'\ngit $1 = ^Password for 'https://(?:[^@]+@)?([^']+)'\n")?;
+
+    let askpass = |prompt: &str| Command::new(env!("CARGO_BIN_EXE_passtool-askpass"))
+        .arg(prompt)
+        .env("PASSTOOL_VAULT", &vault)
+        .env("PASSTOOL_KEY", "key")
+        .env("PASSTOOL_AGENT_SOCK", std::env::temp_dir().join("passtool-no-agent.sock"))
+        .env_remove("PASSTOOL_ASKPASS_RULES")
+        .stdin(Stdio::null())
+        .output();
+    let output = askpass("Username for 'https://github.com': ")?;
+    assert_eq!(String::from_utf8(output.stdout)?, "alice\n");
+    let output = askpass("Password for 'https://alice@github.com': ")?;
+    assert_eq!(String::from_utf8(output.stdout)?, "hunter2\n");
+    // a rule naming a missing entry is reported before the terminal prompt
+    let output = askpass("Password for 'https://gitlab.com': ")?;
+    assert!(String::from_utf8(output.stderr)?.starts_with("passtool-askpass: entry 'git gitlab.com' not found\n"));
+
+    // the terminal prompt fails without a tty
+    let output = Command::new(env!("CARGO_BIN_EXE_passtool-askpass"))
+        .arg("Enter passphrase: ")
+        .env("PASSTOOL_VAULT", &vault)
+        .env_remove("PASSTOOL_ASKPASS_RULES")
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        assert!(output.stdout.is_empty());
+    }
+
+    std::fs::remove_file(&vault)?;
+    std::fs::remove_file(&rules)?;
+    Ok(())
+}
@@ -0,0 +1,199 @@
+#![cfg(unix)]
+use std::{path::PathBuf, process::Command};
+
+use passtool::*;
+
+fn vault(name: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = std::env::temp_dir().join(format!("passtool-cli-{name}-{}.pt", std::process::id()));
+    let mut pt = PassTable::new();
+    pt.add_password("db-prod", "hunter2", PasswordMeta::default(), "key")?;
+    pt.to_file(&path)?;
+    Ok(path)
+}
+
+fn passtool(vault: &PathBuf) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_passtool"));
+    command.arg("--vault").arg(vault).env("PASSTOOL_KEY", "key");
+    command
+}
+
+#[test]
+fn run_masked_test() -> Result<(), Box<dyn std::error::Error>> {
+    let vault = vault("run")?;
+    let output = passtool(&vault)
+        .args(["run", "--env", "DB_PASS=db-prod", "--mask", "--", "sh", "-c", "echo pass=$DB_PASS; echo $DB_PASS >&2; exit 3"])
+        .output()?;
+    std::fs::remove_file(&vault)?;
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8(output.stdout)?, "pass=<concealed by passtool>\n");
+    assert_eq!(String::from_utf8(output.stderr)?, "<concealed by passtool>\n");
+    Ok(())
+}
+
+#[test]
+fn run_missing_entry_test() -> Result<(), Box<dyn std::error::Error>> {
+    let vault = vault("missing")?;
+    let output = passtool(&vault).args(["run", "--env", "X=nope", "--", "true"]).output()?;
+    std::fs::remove_file(&vault)?;
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stderr)?, "passtool: password not found\n");
+    Ok(())
+}
+
+#[test]
+fn inject_test() -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+    let vault = vault("inject")?;
+    let dir = std::env::temp_dir();
+    let input = dir.join(format!("passtool-cli-template-{}", std::process::id()));
+    let output = dir.join(format!("passtool-cli-rendered-{}", std::process::id()));
+    std::fs::write(&input, "password={{ passtool \"db-prod\" }}\nother={{ passtool \"api\" }}\n")?;
+
+    let check = passtool(&vault).arg("inject").arg("--check").arg("-i").arg(&input).output()?;
+    assert_eq!(check.status.code(), Some(1));
+    assert_eq!(String::from_utf8(check.stderr)?, "line 2: entry 'api' not found\n");
+
+    std::fs::write(&input, "password={{ passtool \"db-prod\" }}\n")?;
+    let status = passtool(&vault).arg("inject").arg("-i").arg(&input).arg("-o").arg(&output).status()?;
+    let rendered = std::fs::read_to_string(&output)?;
+    let mode = std::fs::metadata(&output)?.permissions().mode();
+    for file in [&vault, &input, &output] { std::fs::remove_file(file)?; }
+    assert!(status.success());
+    assert_eq!(rendered, "password=hunter2\n");
+    assert_eq!(mode & 0o777, 0o600);
+    Ok(())
+}
+
+#[test]
+fn expiring_test() -> Result<(), Box<dyn std::error::Error>> {
+    let vault = vault("expiring")?;
+    assert!(passtool(&vault).args(["expire", "db-prod", "30"]).status()?.success());
+    let soon = passtool(&vault).args(["expiring", "30"]).output()?;
+    let later = passtool(&vault).arg("expiring").output()?;
+    assert!(passtool(&vault).args(["expire", "db-prod", "0"]).status()?.success());
+    let expired = passtool(&vault).arg("expiring").output()?;
+    let overflow = passtool(&vault).args(["expiring", "999999999999999999"]).output()?;
+    std::fs::remove_file(&vault)?;
+    assert_eq!(String::from_utf8(soon.stdout)?, "db-prod\texpires in 30 days\n");
+    assert_eq!(String::from_utf8(later.stdout)?, "");
+    assert_eq!(String::from_utf8(expired.stdout)?, "db-prod\texpired\n");
+    assert_eq!(overflow.status.code(), Some(1));
+    assert_eq!(String::from_utf8(overflow.stderr)?, "passtool: invalid number of days '999999999999999999'\n");
+    Ok(())
+}
+
+#[test]
+fn trash_test() -> Result<(), Box<dyn std::error::Error>> {
+    let vault = vault("trash")?;
+    PassTable::update_file(&vault, |table| Ok(table.remove_password("db-prod")?))?;
+    let list = passtool(&vault).arg("trash").output()?;
+    assert!(passtool(&vault).args(["trash", "restore", "db-prod"]).status()?.success());
+    let restored = passtool(&vault).args(["get", "db-prod"]).output()?;
+    let missing = passtool(&vault).args(["trash", "purge", "db-prod"]).output()?;
+    let overflow = passtool(&vault).args(["trash", "retention", "999999999999999999"]).output()?;
+    std::fs::remove_file(&vault)?;
+    assert_eq!(String::from_utf8(list.stdout)?, "db-prod\tremoved 0 days ago\n");
+    assert_eq!(String::from_utf8(restored.stdout)?, "hunter2\n");
+    assert_eq!(String::from_utf8(missing.stderr)?, "passtool: password not found\n");
+    assert_eq!(String::from_utf8(overflow.stderr)?, "passtool: invalid number of days '999999999999999999'\n");
+    Ok(())
+}
+
+#[test]
+fn list_test() -> Result<(), Box<dyn std::error::Error>> {
+    let vault = vault("list")?;
+    PassTable::update_file(&vault, |table| Ok(table.add_password("mail", "x", PasswordMeta::default(), "key")?))?;
+    assert!(passtool(&vault).args(["move", "db-prod", "work/servers"]).status()?.success());
+    assert!(passtool(&vault).args(["tag", "db-prod", "prod", "ci"]).status()?.success());
+    assert!(passtool(&vault).args(["untag", "db-prod", "ci"]).status()?.success());
+    let all = passtool(&vault).arg("list").output()?;
+    let work = passtool(&vault).args(["list", "work"]).output()?;
+    let prod = passtool(&vault).args(["list", "--tag", "prod"]).output()?;
+    std::fs::remove_file(&vault)?;
+    assert_eq!(String::from_utf8(all.stdout)?, "work/\n  servers/\n    db-prod\nmail\n");
+    assert_eq!(String::from_utf8(work.stdout)?, "servers/\n  db-prod\n");
+    assert_eq!(String::from_utf8(prod.stdout)?, "work/\n  servers/\n    db-prod\n");
+    Ok(())
+}
+
+#[test]
+fn search_test() -> Result<(), Box<dyn std::error::Error>> {
+    let vault = vault("search")?;
+    PassTable::update_file(&vault, |table| Ok(table.add_password("db-staging", "x", PasswordMeta{tags: vec!["ci".to_string()], ..Default::default()}, "key")?))?;
+    let fuzzy = passtool(&vault).args(["search", "dbprod"]).output()?;
+    let tagged = passtool(&vault).args(["search", "db", "tag:ci"]).output()?;
+    std::fs::remove_file(&vault)?;
+    assert_eq!(String::from_utf8(fuzzy.stdout)?, "db-prod\n");
+    assert_eq!(String::from_utf8(tagged.stdout)?, "db-staging\n");
+    Ok(())
+}
+
+#[test]
+fn app_rule_test() -> Result<(), Box<dyn std::error::Error>> {
+    let vault = vault("app")?;
+    assert!(passtool(&vault).args(["app", "add", "db-prod", "exe:psql | title:prod"]).status()?.success());
+    let invalid = passtool(&vault).args(["app", "add", "db-prod", "regex:("]).output()?;
+    let apps = PassTable::from_file(&vault)?.get_metadata("db-prod")?.apps.clone();
+    let exe = std::env::current_exe()?;
+    let exe = exe.to_str().unwrap();
+    let pinned = passtool(&vault).args(["app", "pin", "db-prod", "exe:psql | title:prod", exe]).output()?;
+    let unknown = passtool(&vault).args(["app", "pin", "db-prod", "exe:other", exe]).status()?;
+    let fingerprints = PassTable::from_file(&vault)?.get_metadata("db-prod")?.fingerprints.clone();
+    assert!(passtool(&vault).args(["app", "remove", "db-prod", "exe:psql | title:prod"]).status()?.success());
+    let meta = PassTable::from_file(&vault)?.get_metadata("db-prod")?.clone();
+    let removed = meta.apps.is_empty() && meta.fingerprints.is_empty();
+    std::fs::remove_file(&vault)?;
+    assert_eq!(apps, ["exe:psql | title:prod"]);
+    assert_eq!(invalid.status.code(), Some(1));
+    assert!(pinned.status.success() && String::from_utf8(pinned.stdout)?.starts_with("sha256:"));
+    assert_eq!(unknown.code(), Some(1));
+    assert_eq!(fingerprints.len(), 1);
+    assert!(removed);
+    Ok(())
+}
+
+#[test]
+fn convert_test() -> Result<(), Box<dyn std::error::Error>> {
+    let vault = vault("convert")?;
+    let missing = passtool(&vault.with_extension("missing")).args(["convert", "unused.db"]).status()?;
+    let mut outputs = Vec::new();
+    for ext in ["db", "ptj"] {
+        let dest = vault.with_extension(ext);
+        assert!(passtool(&vault).arg("convert").arg(&dest).status()?.success());
+        assert!(passtool(&dest).args(["tag", "db-prod", "ci"]).status()?.success());
+        outputs.push(passtool(&dest).args(["get", "db-prod"]).output()?);
+        outputs.push(passtool(&dest).args(["list", "--tag", "ci"]).output()?);
+        std::fs::remove_file(&dest)?;
+    }
+    std::fs::remove_file(&vault)?;
+    assert_eq!(missing.code(), Some(1));
+    assert!(!std::path::Path::new("unused.db").exists());
+    let outputs = outputs.into_iter().map(|output| String::from_utf8(output.stdout)).collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(outputs, ["hunter2\n", "db-prod\n", "hunter2\n", "db-prod\n"]);
+    Ok(())
+}
+
+#[test]
+fn access_test() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir();
+    let (username, password) = (dir.join(format!("passtool-cli-username-{}", std::process::id())), dir.join(format!("passtool-cli-password-{}", std::process::id())));
+    std::fs::write(&username, "{{ passtool \"db-prod\" \"username\" }}")?;
+    std::fs::write(&password, "{{ passtool \"db-prod\" }}")?;
+    let mut accessed = Vec::new();
+    for (i, args) in [
+        vec!["list"],
+        vec!["inject", "-i", username.to_str().unwrap()],
+        vec!["inject", "-i", password.to_str().unwrap()],
+        vec!["get", "db-prod"],
+        vec!["run", "--env", "X=db-prod", "--", "true"]
+    ].into_iter().enumerate() {
+        let vault = vault(&format!("access{i}"))?;
+        assert!(passtool(&vault).args(&args).output()?.status.success());
+        accessed.push(PassTable::from_file(&vault)?.get_times("db-prod")?.accessed > 0);
+        std::fs::remove_file(&vault)?;
+    }
+    for file in [&username, &password] { std::fs::remove_file(file)?; }
+    // reading metadata isn't an access
+    assert_eq!(accessed, [false, false, true, true, true]);
+    Ok(())
+}
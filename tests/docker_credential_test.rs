@@ -0,0 +1,35 @@
+use std::{io::Write, path::Path, process::{Command, Output, Stdio}};
+
+use passtool::*;
+
+fn helper(vault: &Path, action: &str, input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_docker-credential-passtool"))
+        .arg(action)
+        .env("PASSTOOL_VAULT", vault)
+        .env("PASSTOOL_KEY", "key")
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn helper_stdin_test() -> Result<(), Box<dyn std::error::Error>> {
+    let vault = std::env::temp_dir().join(format!("passtool-docker-{}.pt", std::process::id()));
+    PassTable::new().to_file(&vault)?;
+
+    let get = helper(&vault, "get", "registry.example.com\n");
+    assert!(!get.status.success());
+    assert_eq!(String::from_utf8(get.stdout)?.trim(), "credentials not found in native keychain");
+
+    assert!(helper(&vault, "store", r#"{"ServerURL":"registry.example.com","Username":"ci","Secret":"hunter2"}"#).status.success());
+    let get = helper(&vault, "get", "registry.example.com\n");
+    assert!(get.status.success());
+    assert_eq!(String::from_utf8(get.stdout)?, r#"{"ServerURL":"registry.example.com","Username":"ci","Secret":"hunter2"}"#);
+    assert_eq!(String::from_utf8(helper(&vault, "list", "").stdout)?, r#"{"registry.example.com":"ci"}"#);
+
+    assert!(helper(&vault, "erase", "registry.example.com\n").status.success());
+    assert_eq!(String::from_utf8(helper(&vault, "list", "").stdout)?, "{}");
+    std::fs::remove_file(&vault)?;
+    Ok(())
+}
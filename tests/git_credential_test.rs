@@ -0,0 +1,100 @@
+#![cfg(unix)]
+//! Clones a local bare repository through a dumb-HTTP stand-in that requires basic auth,
+//! with passtool as the only credential source.
+use std::{io::{BufRead, BufReader, Write}, net::TcpListener, path::{Path, PathBuf}, process::Command, thread};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use passtool::*;
+
+fn git(dir: &Path) -> Command {
+    let mut command = Command::new("git");
+    command.current_dir(dir)
+        .env("HOME", dir)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GIT_AUTHOR_NAME", "test").env("GIT_AUTHOR_EMAIL", "test@example.com")
+        .env("GIT_COMMITTER_NAME", "test").env("GIT_COMMITTER_EMAIL", "test@example.com");
+    command
+}
+
+fn run(command: &mut Command) -> std::process::Output {
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+/// Serves files of `root` under `/repo.git/`, answering 401 without the expected credentials.
+fn serve(root: PathBuf, username: &str, password: &str) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let expected = format!("Basic {}", STANDARD.encode(format!("{username}:{password}")));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let mut authorized = false;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() { break; }
+                if let Some((name, value)) = header.split_once(':') {
+                    authorized |= name.eq_ignore_ascii_case("authorization") && value.trim() == expected;
+                }
+            }
+            let path = request.split_whitespace().nth(1).unwrap_or("/");
+            let path = path.split('?').next().unwrap().trim_start_matches("/repo.git/");
+            let response = if !authorized {
+                b"HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"test\"\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec()
+            }
+            else if let Ok(body) = std::fs::read(root.join(path)) {
+                let mut response = format!("HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).into_bytes();
+                response.extend(body);
+                response
+            }
+            else {
+                b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec()
+            };
+            let _ = stream.write_all(&response);
+        }
+    });
+    port
+}
+
+#[test]
+fn clone_with_vault_credentials_test() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join(format!("passtool-git-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+
+    run(git(&dir).args(["init", "-q", "src"]));
+    std::fs::write(dir.join("src/README"), "hello\n")?;
+    run(git(&dir.join("src")).args(["add", "README"]));
+    run(git(&dir.join("src")).args(["commit", "-q", "-m", "init"]));
+    run(git(&dir).args(["clone", "-q", "--bare", "src", "bare.git"]));
+    run(git(&dir.join("bare.git")).args(["update-server-info"]));
+    let url = format!("http://127.0.0.1:{}/repo.git", serve(dir.join("bare.git"), "alice", "s3cret"));
+
+    let vault = dir.join("passwords.pt");
+    PassTable::new().to_file(&vault)?;
+    let helper = format!("!'{}' --vault '{}' git-credential", env!("CARGO_BIN_EXE_passtool"), vault.display());
+
+    // nothing in the vault yet
+    let clone = git(&dir).args(["-c", &format!("credential.helper={helper}"), "clone", "-q", &url, "first"]).env("PASSTOOL_KEY", "key").output()?;
+    assert!(!clone.status.success());
+
+    // another helper answers, passtool gets the approved credentials stored
+    let answer = "!f() { echo username=alice; echo password=s3cret; }; f";
+    run(git(&dir).args(["-c", &format!("credential.helper={answer}"), "-c", &format!("credential.helper={helper}"), "clone", "-q", &url, "second"]).env("PASSTOOL_KEY", "key"));
+    let table = PassTable::from_file(&vault)?;
+    let name = format!("http://alice@127.0.0.1:{}", url.split(':').nth(2).unwrap().split('/').next().unwrap());
+    assert_eq!(table.get_password(&name, "key")?, "s3cret");
+
+    // passtool alone is enough now
+    run(git(&dir).args(["-c", &format!("credential.helper={helper}"), "clone", "-q", &url, "third"]).env("PASSTOOL_KEY", "key"));
+    assert_eq!(std::fs::read_to_string(dir.join("third/README"))?, "hello\n");
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,90 @@
+//! Talks to the native-messaging host binary the way a browser does.
+use std::{io::{Read, Write}, path::Path, process::{Child, ChildStdin, ChildStdout, Command, Stdio}};
+
+use passtool::*;
+
+fn frame(message: &str) -> Vec<u8> {
+    let mut framed = (message.len() as u32).to_ne_bytes().to_vec();
+    framed.extend(message.as_bytes());
+    framed
+}
+
+struct Browser {
+    host: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout
+}
+
+impl Browser {
+    fn start(vault: &Path) -> std::io::Result<Self> {
+        let mut host = Command::new(env!("CARGO_BIN_EXE_passtool-native-host"))
+            .arg("chrome-extension://abcdef/")
+            .env("PASSTOOL_VAULT", vault)
+            .env_remove("PASSTOOL_KEY")
+            .env("PASSTOOL_AGENT_SOCK", std::env::temp_dir().join("passtool-no-agent.sock"))
+            .stdin(Stdio::piped()).stdout(Stdio::piped())
+            .spawn()?;
+        let (stdin, stdout) = (host.stdin.take().unwrap(), host.stdout.take().unwrap());
+        Ok(Browser{host, stdin, stdout})
+    }
+
+    fn ask(&mut self, message: &str) -> String {
+        self.stdin.write_all(&frame(message)).unwrap();
+        let mut length = [0u8; 4];
+        self.stdout.read_exact(&mut length).unwrap();
+        let mut response = vec![0; u32::from_ne_bytes(length) as usize];
+        self.stdout.read_exact(&mut response).unwrap();
+        String::from_utf8(response).unwrap()
+    }
+
+    fn close(self) -> std::io::Result<bool> {
+        drop(self.stdin);
+        let mut host = self.host;
+        Ok(host.wait()?.success())
+    }
+}
+
+fn table() -> Result<PassTable, Error> {
+    let mut pt = PassTable::new();
+    pt.add_password("github", "hunter2", PasswordMeta{url: "https://github.com".to_string(), username: "alice".to_string(), ..Default::default()}, "key")?;
+    Ok(pt)
+}
+
+#[test]
+fn native_host_test() -> Result<(), Box<dyn std::error::Error>> {
+    let vault = std::env::temp_dir().join(format!("passtool-native-{}.pt", std::process::id()));
+    table()?.to_file(&vault)?;
+
+    let mut browser = Browser::start(&vault)?;
+    assert_eq!(browser.ask(r#"{"action":"find","url":"https://github.com/login"}"#),
+        r#"{"status":"entries","locked":true,"entries":[{"name":"github","username":"alice","url":"https://github.com"}]}"#);
+    assert_eq!(browser.ask(r#"{"action":"get","url":"https://github.com/login","name":"github"}"#), r#"{"status":"locked"}"#);
+    assert_eq!(browser.ask(r#"{"action":"unlock","key":"key"}"#), r#"{"status":"ok"}"#);
+    assert_eq!(browser.ask(r#"{"action":"get","url":"https://github.com/login","name":"github"}"#),
+        r#"{"status":"credentials","name":"github","username":"alice","password":"hunter2"}"#);
+    assert_eq!(browser.ask(r#"{"action":"find","url":"https://evil.example"}"#), r#"{"status":"entries","locked":false,"entries":[]}"#);
+
+    assert!(browser.close()?);
+    std::fs::remove_file(&vault)?;
+    Ok(())
+}
+
+#[test]
+fn journal_host_test() -> Result<(), Box<dyn std::error::Error>> {
+    let vault = std::env::temp_dir().join(format!("passtool-native-{}.ptj", std::process::id()));
+    let _ = std::fs::remove_file(&vault);
+    store::copy(&table()?, store::create(&vault, &mut || Ok("key".to_string()))?.as_mut())?;
+
+    // the journal is opened with the key sent to unlock
+    let mut browser = Browser::start(&vault)?;
+    assert_eq!(browser.ask(r#"{"action":"find","url":"https://github.com/login"}"#), r#"{"status":"error","message":"the vault is locked"}"#);
+    assert_eq!(browser.ask(r#"{"action":"unlock","key":"wrong"}"#), r#"{"status":"error","message":"incorrect password"}"#);
+    assert_eq!(browser.ask(r#"{"action":"unlock","key":"key"}"#), r#"{"status":"ok"}"#);
+    assert_eq!(browser.ask(r#"{"action":"get","url":"https://github.com/login","name":"github"}"#),
+        r#"{"status":"credentials","name":"github","username":"alice","password":"hunter2"}"#);
+
+    assert!(browser.close()?);
+    assert!(store::load(&vault, &mut || Ok("key".to_string()))?.get_times("github")?.accessed > 0);
+    std::fs::remove_file(&vault)?;
+    Ok(())
+}
@@ -0,0 +1,60 @@
+//! Runs `passtool api serve` and calls it over plain HTTP.
+use std::{io::{BufRead, BufReader, Read, Write}, net::TcpStream, path::Path, process::{Command, Stdio}};
+
+use passtool::*;
+
+fn passtool(vault: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_passtool"));
+    command.arg("--vault").arg(vault).env("PASSTOOL_KEY", "key");
+    command
+}
+
+fn request(port: u16, method: &str, path: &str, token: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    write!(stream, "{method} {path} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {token}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, response.split_once("\r\n\r\n").unwrap().1.to_string())
+}
+
+#[test]
+fn api_test() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join(format!("passtool-api-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let vault = dir.join("passwords.pt");
+    let mut pt = PassTable::new();
+    pt.add_password("deploy", "hunter2", PasswordMeta{tags: vec!["ci".to_string()], ..Default::default()}, "key")?;
+    pt.add_password("personal", "s3cret", PasswordMeta::default(), "key")?;
+    pt.to_file(&vault)?;
+
+    let token = passtool(&vault).args(["api", "token", "add", "ci", "--tag", "ci", "--write"]).output()?;
+    assert!(token.status.success());
+    let token = String::from_utf8(token.stdout)?.trim().to_string();
+    assert!(!std::fs::read_to_string(dir.join("passwords.pt.tokens.json"))?.contains(&token));
+    assert_eq!(String::from_utf8(passtool(&vault).args(["api", "token", "list"]).output()?.stdout)?, "ci\tread-write\ttag:ci\n");
+
+    let mut server = passtool(&vault).args(["api", "serve", "--port", "0"]).stdout(Stdio::piped()).spawn()?;
+    let mut line = String::new();
+    BufReader::new(server.stdout.take().unwrap()).read_line(&mut line)?;
+    let port: u16 = line.trim().rsplit(':').next().unwrap().parse()?;
+
+    assert_eq!(request(port, "GET", "/entries", "wrong", "").0, 401);
+    let (status, body) = request(port, "GET", "/entries", &token, "");
+    assert_eq!(status, 200);
+    assert_eq!(body, r#"[{"description":"","group":"","name":"deploy","tags":["ci"],"url":"","username":""}]"#);
+    assert!(request(port, "GET", "/entries/deploy", &token, "").1.contains(r#""password":"hunter2""#));
+    assert_eq!(request(port, "GET", "/entries/personal", &token, "").0, 404);
+    assert_eq!(request(port, "POST", "/entries", &token, r#"{"name":"cache key","password":"p","tags":["ci"]}"#).0, 201);
+    assert!(request(port, "GET", "/entries/cache%20key", &token, "").1.contains(r#""password":"p""#));
+
+    server.kill()?;
+    server.wait()?;
+    assert_eq!(PassTable::from_file(&vault)?.get_password("cache key", "key")?, "p");
+    let audit = std::fs::read_to_string(dir.join("passwords.pt.audit.log"))?;
+    assert_eq!(audit.lines().count(), 6);
+    assert!(audit.lines().last().unwrap().contains(r#""path":"/entries/cache%20key""#));
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,136 @@
+#![cfg(target_os = "linux")]
+//! Serves the vault on a private session bus and talks to it like libsecret clients do.
+use std::{collections::HashMap, path::Path, process::{Child, Command, Stdio}, thread, time::Duration};
+
+use passtool::{*, secret_service::*};
+use zbus::{blocking::{fdo::DBusProxy, Proxy}, zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value}};
+
+struct Kill(Child);
+
+impl Drop for Kill {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn wait_for(what: impl Fn() -> bool) {
+    for _ in 0..250 {
+        if what() { return; }
+        thread::sleep(Duration::from_millis(20));
+    }
+    panic!("timed out");
+}
+
+fn bus(dir: &Path) -> Option<(Kill, String)> {
+    let socket = dir.join("bus.sock");
+    let address = format!("unix:path={}", socket.display());
+    let config = dir.join("bus.conf");
+    std::fs::write(&config, format!("<busconfig><type>session</type><listen>{address}</listen><auth>EXTERNAL</auth>\
+        <policy context=\"default\"><allow send_destination=\"*\" eavesdrop=\"true\"/><allow eavesdrop=\"true\"/><allow own=\"*\"/></policy></busconfig>")).unwrap();
+    let daemon = Command::new("dbus-daemon").arg(format!("--config-file={}", config.display())).arg("--nofork").stderr(Stdio::null()).spawn().ok()?;
+    // the socket file appears before the daemon accepts connections
+    wait_for(|| std::os::unix::net::UnixStream::connect(&socket).is_ok());
+    Some((Kill(daemon), address))
+}
+
+fn secret(session: &OwnedObjectPath, value: &[u8]) -> Secret {
+    (session.clone(), Vec::new(), value.to_vec(), "text/plain".to_string())
+}
+
+#[test]
+fn secret_service_test() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join(format!("passtool-secrets-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let Some((_daemon, address)) = bus(&dir) else {
+        eprintln!("dbus-daemon not found, skipping");
+        return Ok(());
+    };
+
+    let vault = dir.join("passwords.pt");
+    let mut pt = PassTable::new();
+    pt.add_password("unrelated", "x", PasswordMeta::default(), "key")?;
+    pt.to_file(&vault)?;
+    let askpass = dir.join("askpass");
+    std::fs::write(&askpass, "#!/bin/sh\necho key\n")?;
+    std::fs::set_permissions(&askpass, std::os::unix::fs::PermissionsExt::from_mode(0o700))?;
+
+    let _service = Kill(Command::new(env!("CARGO_BIN_EXE_passtool"))
+        .arg("--vault").arg(&vault)
+        .args(["secret-service", "--askpass"]).arg(&askpass)
+        .env("PASSTOOL_KEY", "key")
+        .env("DBUS_SESSION_BUS_ADDRESS", &address)
+        .stdin(Stdio::null())
+        .spawn()?);
+    let connection = zbus::blocking::connection::Builder::address(address.as_str())?.build()?;
+    let dbus = DBusProxy::new(&connection)?;
+    wait_for(|| dbus.name_has_owner(BUS_NAME.try_into().unwrap()).unwrap());
+
+    let service = Proxy::new(&connection, BUS_NAME, SERVICE_PATH, "org.freedesktop.Secret.Service")?;
+    let collection = Proxy::new(&connection, BUS_NAME, DEFAULT_ALIAS_PATH, "org.freedesktop.Secret.Collection")?;
+    let (_, session): (OwnedValue, OwnedObjectPath) = service.call("OpenSession", &(PLAIN, Value::from("")))?;
+
+    let attributes = HashMap::from([("service", "mail"), ("user", "bob")]);
+    let properties = HashMap::from([
+        ("org.freedesktop.Secret.Item.Label", Value::from("Mail")),
+        ("org.freedesktop.Secret.Item.Attributes", Value::from(attributes.clone()))
+    ]);
+    let (item, _): (OwnedObjectPath, OwnedObjectPath) = collection.call("CreateItem", &(&properties, secret(&session, b"hunter2"), true))?;
+    assert_eq!(item.as_str(), "/org/freedesktop/secrets/collection/passtool/Mail");
+    assert_eq!(PassTable::from_file(&vault)?.get_password("Mail", "key")?, "hunter2");
+
+    let (unlocked, locked): (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) = service.call("SearchItems", &(HashMap::from([("service", "mail")]),))?;
+    assert_eq!((unlocked, locked), (vec![item.clone()], vec![]));
+    let item_proxy = Proxy::new(&connection, BUS_NAME, item.as_str(), "org.freedesktop.Secret.Item")?;
+    let (value,): (Secret,) = item_proxy.call("GetSecret", &(&session,))?;
+    assert_eq!(value.2, b"hunter2");
+    assert_eq!(item_proxy.get_property::<String>("Label")?, "Mail");
+
+    // same attributes with replace overwrite the secret
+    let (replaced, _): (OwnedObjectPath, OwnedObjectPath) = collection.call("CreateItem", &(&properties, secret(&session, b"rotated"), true))?;
+    assert_eq!(replaced, item);
+    // without attributes nothing is replaced
+    let unlabeled = HashMap::from([("org.freedesktop.Secret.Item.Attributes", Value::from(HashMap::<&str, &str>::new()))]);
+    let (created, _): (OwnedObjectPath, OwnedObjectPath) = collection.call("CreateItem", &(&unlabeled, secret(&session, b"new"), true))?;
+    assert_ne!(created, item);
+    assert_eq!(PassTable::from_file(&vault)?.get_password("unrelated", "key")?, "x");
+
+    let keys = KeyPair::generate();
+    let (output, dh_session): (OwnedValue, OwnedObjectPath) = service.call("OpenSession", &(DH_AES, Value::from(keys.public.clone())))?;
+    let cipher = keys.session(&Vec::<u8>::try_from(output)?)?;
+    let secrets: HashMap<OwnedObjectPath, Secret> = service.call("GetSecrets", &(vec![&item], &dh_session))?;
+    let (_, parameters, value, _) = &secrets[&item];
+    assert_ne!(value, b"rotated");
+    assert_eq!(cipher.decrypt(parameters, value)?, b"rotated");
+
+    let collections = vec![ObjectPath::try_from(COLLECTION_PATH)?];
+    let _: (Vec<OwnedObjectPath>, OwnedObjectPath) = service.call("Lock", &(&collections,))?;
+    assert!(collection.get_property::<bool>("Locked")?);
+    assert!(item_proxy.call::<_, _, (Secret,)>("GetSecret", &(&session,)).is_err());
+
+    let (unlocked, prompt): (Vec<OwnedObjectPath>, OwnedObjectPath) = service.call("Unlock", &(&collections,))?;
+    assert!(unlocked.is_empty());
+    let prompt = Proxy::new(&connection, BUS_NAME, prompt.as_str(), "org.freedesktop.Secret.Prompt")?;
+    let mut completed = prompt.receive_signal("Completed")?;
+    prompt.call::<_, _, ()>("Prompt", &("",))?;
+    let (dismissed, _): (bool, OwnedValue) = completed.next().unwrap().body().deserialize()?;
+    assert!(!dismissed);
+    let (value,): (Secret,) = item_proxy.call("GetSecret", &(&session,))?;
+    assert_eq!(value.2, b"rotated");
+
+    if Command::new("secret-tool").arg("--version").output().is_ok() {
+        let mut store = Command::new("secret-tool").args(["store", "--label=Git", "service", "git"])
+            .env("DBUS_SESSION_BUS_ADDRESS", &address).stdin(Stdio::piped()).spawn()?;
+        std::io::Write::write_all(&mut store.stdin.take().unwrap(), b"s3cret")?;
+        assert!(store.wait()?.success());
+        let lookup = Command::new("secret-tool").args(["lookup", "service", "git"]).env("DBUS_SESSION_BUS_ADDRESS", &address).output()?;
+        assert_eq!(lookup.stdout, b"s3cret");
+    }
+
+    let _: OwnedObjectPath = item_proxy.call("Delete", &())?;
+    assert!(!PassTable::from_file(&vault)?.contains("Mail"));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
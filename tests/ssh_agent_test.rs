@@ -0,0 +1,61 @@
+#![cfg(unix)]
+//! Runs the agent on a unix socket and talks to it with OpenSSH's own tools.
+use std::{fs, os::unix::fs::PermissionsExt, path::Path, process::{Command, Output}, thread, time::Duration};
+
+use passtool::{ssh_agent, PassTable, PasswordMeta};
+
+fn ssh(program: &str, socket: &Path, dir: &Path) -> Command {
+    let mut command = Command::new(program);
+    command.current_dir(dir)
+        .env("SSH_AUTH_SOCK", socket)
+        .env("SSH_ASKPASS", dir.join("askpass"))
+        .env("SSH_ASKPASS_REQUIRE", "force")
+        .env("DISPLAY", ":0");
+    command
+}
+
+fn stdout(output: Output) -> String {
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn ssh_add_and_sign_test() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join(format!("passtool-ssh-agent-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("askpass"), "#!/bin/sh\necho vault-key\n")?;
+    fs::set_permissions(dir.join("askpass"), fs::Permissions::from_mode(0o700))?;
+    let public = fs::read_to_string("tests/data/id_rsa.pub")?;
+    fs::write(dir.join("id_rsa.pub"), &public)?;
+
+    let vault = dir.join("passwords.pt");
+    let mut table = PassTable::new();
+    ssh_agent::add_key(&mut table, "deploy key", &fs::read_to_string("tests/data/id_rsa")?, PasswordMeta::default(), "vault-key")?;
+    table.to_file(&vault)?;
+
+    let socket = dir.join("agent.sock");
+    let mut agent = ssh_agent::Agent::new(Box::new(move || PassTable::from_file(&vault)));
+    agent.unlock("vault-key")?;
+    let listening = socket.clone();
+    thread::spawn(move || ssh_agent::listen(listening, agent));
+    while !socket.exists() { thread::sleep(Duration::from_millis(10)); }
+    assert_eq!(fs::metadata(&socket)?.permissions().mode() & 0o777, 0o600);
+
+    let listed = stdout(ssh("ssh-add", &socket, &dir).arg("-L").output()?);
+    let key = public.split_whitespace().take(2).collect::<Vec<_>>().join(" ");
+    assert_eq!(listed, format!("{key} deploy key\n"));
+
+    fs::write(dir.join("data"), "signed through the agent")?;
+    let sign = ssh("ssh-keygen", &socket, &dir).args(["-Y", "sign", "-n", "file", "-f", "id_rsa.pub", "data"]).output()?;
+    assert!(sign.status.success(), "{}", String::from_utf8_lossy(&sign.stderr));
+    let check = ssh("sh", &socket, &dir).args(["-c", "ssh-keygen -Y check-novalidate -n file -f id_rsa.pub -s data.sig < data"]).output()?;
+    assert!(check.status.success(), "{}", String::from_utf8_lossy(&check.stderr));
+
+    assert!(ssh("ssh-add", &socket, &dir).arg("-x").status()?.success());
+    assert_eq!(stdout(ssh("ssh-add", &socket, &dir).arg("-L").output()?), "The agent has no identities.\n");
+    assert!(ssh("ssh-add", &socket, &dir).arg("-X").status()?.success());
+    assert_eq!(stdout(ssh("ssh-add", &socket, &dir).arg("-L").output()?), format!("{key} deploy key\n"));
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
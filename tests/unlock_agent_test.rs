@@ -0,0 +1,61 @@
+#![cfg(unix)]
+//! Runs `passtool agent` and reads entries through it without the key in the environment.
+use std::{path::Path, process::{Command, Output, Stdio}, thread, time::Duration};
+
+use passtool::*;
+
+fn passtool(vault: &Path, socket: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_passtool"));
+    command.arg("--vault").arg(vault)
+        .env("PASSTOOL_AGENT_SOCK", socket)
+        .env_remove("PASSTOOL_KEY")
+        .stdin(Stdio::null());
+    command
+}
+
+fn get(vault: &Path, socket: &Path, key: Option<&str>) -> Output {
+    let mut command = passtool(vault, socket);
+    if let Some(key) = key { command.env("PASSTOOL_KEY", key); }
+    command.args(["get", "mail"]).output().unwrap()
+}
+
+#[test]
+fn agent_test() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join(format!("passtool-unlock-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let vault = dir.join("passwords.pt");
+    let socket = dir.join("agent/agent.sock");
+    let mut pt = PassTable::new();
+    pt.add_password("mail", "hunter2", PasswordMeta::default(), "key")?;
+    pt.to_file(&vault)?;
+
+    let mut agent = passtool(&vault, &socket).args(["agent", "--timeout", "2"]).stdout(Stdio::null()).spawn()?;
+    while !socket.exists() { thread::sleep(Duration::from_millis(20)); }
+
+    // no terminal to prompt on, so this only works through the agent
+    assert!(!get(&vault, &socket, None).status.success());
+    assert!(!get(&vault, &socket, Some("wrong")).status.success());
+    assert_eq!(get(&vault, &socket, Some("key")).stdout, b"hunter2\n");
+    assert_eq!(get(&vault, &socket, None).stdout, b"hunter2\n");
+
+    // the agent only answers for its own vault
+    let other = dir.join("other.pt");
+    let mut pt = PassTable::new();
+    pt.add_password("mail", "other", PasswordMeta::default(), "key")?;
+    pt.to_file(&other)?;
+    assert!(!get(&other, &socket, None).status.success());
+    assert_eq!(get(&other, &socket, Some("key")).stdout, b"other\n");
+
+    assert!(passtool(&vault, &socket).arg("lock").status()?.success());
+    assert!(!get(&vault, &socket, None).status.success());
+
+    assert!(get(&vault, &socket, Some("key")).status.success());
+    thread::sleep(Duration::from_secs(4));
+    assert!(!get(&vault, &socket, None).status.success());
+
+    agent.kill()?;
+    agent.wait()?;
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}